@@ -1,12 +1,76 @@
 import asyncio
 import json
 import os
+from collections import deque
 from typing import Optional
 
 import numpy as np
 import websockets
 import yaml
 
+PROTOCOL_VERSION = 1
+SERVER_FEATURES = [
+    "partials",
+    "utterance_boundaries",
+    "utterance_ids",
+    "reset",
+    "server_endpointing",
+]
+
+
+class EnergyEndpointer:
+    """Minimal energy-based endpointer for server endpointing mode.
+
+    Mirrors the client's AutoVad thresholds; swap in a model-based VAD
+    (e.g. Silero) here without any client change.
+    """
+
+    def __init__(self, sample_rate: int = 16000):
+        self.sample_rate = sample_rate
+        self.start_ms = 60.0
+        self.end_silence_ms = 900.0
+        self.preroll_ms = 300.0
+        self.start_rms = 0.015
+        self.end_rms = 0.008
+        self.reset()
+
+    def reset(self) -> None:
+        self.in_speech = False
+        self.voiced_ms = 0.0
+        self.silence_ms = 0.0
+        self.preroll = deque()
+        self.preroll_len_ms = 0.0
+
+    def feed(self, frame: np.ndarray) -> Optional[str]:
+        """Returns "speech_start", "speech_end" or None for one frame."""
+        ms = len(frame) / self.sample_rate * 1000.0
+        rms = float(np.sqrt(np.mean(frame ** 2))) if len(frame) else 0.0
+
+        if not self.in_speech:
+            self.preroll.append(frame)
+            self.preroll_len_ms += ms
+            while self.preroll_len_ms > self.preroll_ms and len(self.preroll) > 1:
+                self.preroll_len_ms -= len(self.preroll.popleft()) / self.sample_rate * 1000.0
+            self.voiced_ms = self.voiced_ms + ms if rms >= self.start_rms else 0.0
+            if self.voiced_ms >= self.start_ms:
+                self.in_speech = True
+                self.silence_ms = 0.0
+                return "speech_start"
+            return None
+
+        self.silence_ms = self.silence_ms + ms if rms < self.end_rms else 0.0
+        if self.silence_ms >= self.end_silence_ms:
+            self.reset()
+            return "speech_end"
+        return None
+
+    def take_preroll(self) -> bytes:
+        audio = np.concatenate(list(self.preroll)) if self.preroll else np.array([], dtype=np.float32)
+        self.preroll.clear()
+        self.preroll_len_ms = 0.0
+        return (np.clip(audio, -1.0, 1.0) * 32767.0).astype(np.int16).tobytes()
+
+
 class ASRService:
     def __init__(self, host: str = "127.0.0.1", port: int = 8765):
         self.host = host
@@ -31,8 +95,19 @@ class ASRService:
         # Cap per-inference audio length when finalizing very long utterances
         self.max_final_chunk_sec = 25.0
         self.min_utterance_sec = 0.25
+        # Hot-word list from the client. GLM-ASR has no biasing hook, so it
+        # is only kept for logging; the client post-corrects final text.
+        self.vocabulary = []
+        # Client-assigned ID of the utterance being recorded, echoed back on
+        # every result so late partials can be told apart.
+        self.utterance_id = None
+        # Server endpointing: the client streams continuously and we decide
+        # where utterances start and end.
+        self.endpointer = EnergyEndpointer(self.sample_rate)
+        self.server_endpointing = False
 
     def reset_utterance(self) -> None:
+        self.utterance_id = None
         self.window_audio = []
         self.utterance_audio = []
         self.partial_texts = []
@@ -180,6 +255,7 @@ class ASRService:
             # text comes from finalize_utterance on end_utterance.
             return {
                 "type": "asr_result",
+                "utterance_id": self.utterance_id,
                 "partial": " ".join(self.partial_texts),
                 "final": None,
                 "confidence": result.get("confidence", 0.0),
@@ -195,11 +271,13 @@ class ASRService:
         2.5s windows causes.
         """
         audio = np.array(self.utterance_audio, dtype=np.float32)
+        utterance_id = self.utterance_id
         self.reset_utterance()
 
         if len(audio) < int(self.sample_rate * self.min_utterance_sec):
             return {
                 "type": "utterance_final",
+                "utterance_id": utterance_id,
                 "partial": "",
                 "final": "",
                 "confidence": 0.0,
@@ -219,34 +297,116 @@ class ASRService:
 
         return {
             "type": "utterance_final",
+            "utterance_id": utterance_id,
             "partial": "",
             "final": " ".join(texts),
             "confidence": 0.95,
         }
 
+    async def send_final(self, websocket) -> None:
+        result = await self.finalize_utterance()
+        await websocket.send(json.dumps(result))
+        print(f"Utterance finalized: {result['final'][:80]}")
+
+    async def process_endpointed_audio(self, websocket, audio_data: bytes) -> None:
+        """Server endpointing: only audio inside detected speech is transcribed."""
+        event = self.endpointer.feed(self.pcm16_to_float32(audio_data))
+        if event == "speech_start":
+            self.reset_utterance()
+            await websocket.send(json.dumps({"type": "endpoint", "event": "speech_start"}))
+            # The pre-roll already holds this frame
+            audio_data = self.endpointer.take_preroll()
+        elif event == "speech_end":
+            await websocket.send(json.dumps({"type": "endpoint", "event": "speech_end"}))
+            await self.process_audio_frame(audio_data)
+            await self.send_final(websocket)
+            return
+        elif not self.endpointer.in_speech:
+            return
+
+        result = await self.process_audio_frame(audio_data)
+        if result:
+            await websocket.send(json.dumps(result))
+
+    async def set_endpointing(self, websocket, mode: str) -> None:
+        enabled = mode == "server"
+        if self.server_endpointing and not enabled and self.endpointer.in_speech:
+            # Handing endpointing back mid-speech: finalize what we have
+            await self.send_final(websocket)
+        self.server_endpointing = enabled
+        self.endpointer.reset()
+        print(f"Endpointing mode: {mode}")
+
+    def capabilities(self) -> dict:
+        return {
+            "type": "ready",
+            "protocol_version": PROTOCOL_VERSION,
+            "server": "glm-asr",
+            "sample_rates": [self.sample_rate],
+            "encodings": ["pcm_s16le"],
+            "languages": ["zh", "en"],
+            "features": SERVER_FEATURES,
+        }
+
+    async def handle_hello(self, websocket, hello: dict) -> None:
+        sample_rate = hello.get("sample_rate", self.sample_rate)
+        encoding = hello.get("encoding", "pcm_s16le")
+        if sample_rate != self.sample_rate or encoding != "pcm_s16le":
+            await websocket.send(json.dumps({
+                "type": "error",
+                "code": "ASR_UNSUPPORTED_FORMAT",
+                "message": f"expected pcm_s16le @ {self.sample_rate}Hz, got {encoding} @ {sample_rate}Hz",
+                "fatal": True,
+            }))
+            return
+        print(
+            f"Client hello: protocol v{hello.get('protocol_version')}, "
+            f"languages={hello.get('language_hints')}, features={hello.get('features')}"
+        )
+        await websocket.send(json.dumps(self.capabilities()))
+
     async def handle_connection(self, websocket):
         print(f"New ASR connection from {websocket.remote_address}")
         self.reset_utterance()
+        self.server_endpointing = False
+        self.endpointer.reset()
 
         try:
             async for message in websocket:
-                if isinstance(message, bytes):
+                if isinstance(message, bytes) and self.server_endpointing:
+                    await self.process_endpointed_audio(websocket, message)
+                elif isinstance(message, bytes):
                     result = await self.process_audio_frame(message)
                     if result:
                         await websocket.send(json.dumps(result))
                 elif isinstance(message, str):
-                    control = json.loads(message)
+                    try:
+                        control = json.loads(message)
+                    except json.JSONDecodeError as e:
+                        await websocket.send(json.dumps({
+                            "type": "warning",
+                            "code": "ASR_BAD_CONTROL",
+                            "message": f"invalid JSON control message: {e}",
+                        }))
+                        continue
                     control_type = control.get("type")
-                    if control_type == "begin_utterance":
+                    if control_type == "hello":
+                        await self.handle_hello(websocket, control)
+                    elif control_type == "begin_utterance":
                         self.reset_utterance()
-                        print("Utterance started")
+                        self.utterance_id = control.get("utterance_id")
+                        print(f"Utterance {self.utterance_id} started")
                     elif control_type == "end_utterance":
-                        result = await self.finalize_utterance()
-                        await websocket.send(json.dumps(result))
-                        print(f"Utterance finalized: {result['final'][:80]}")
+                        await self.send_final(websocket)
+                    elif control_type == "endpointing":
+                        await self.set_endpointing(websocket, control.get("mode", "client"))
+                    elif control_type == "vocabulary":
+                        self.vocabulary = [t for t in control.get("terms", []) if isinstance(t, str)]
+                        print(f"Vocabulary updated: {len(self.vocabulary)} terms")
                     elif control_type == "reset":
+                        # Client cancelled the utterance: no final is sent
                         self.reset_utterance()
-                        print("Audio buffer reset")
+                        print(f"Utterance {control.get('utterance_id')} cancelled, buffer reset")
         except websockets.exceptions.ConnectionClosed:
             print(f"ASR connection closed: {websocket.remote_address}")
         except Exception as e:
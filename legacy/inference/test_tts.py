@@ -31,6 +31,8 @@ async def test_tts_service():
                 print("Message sent, receiving audio frames...")
                 
                 frames = []
+                sample_rate = 16000
+                encoding = "pcm_s16le"
                 timeout_seconds = 30
                 start_time = asyncio.get_event_loop().time()
                 
@@ -40,6 +42,12 @@ async def test_tts_service():
                         if isinstance(frame, bytes):
                             frames.append(frame)
                             print(f"Received frame {len(frames)}/{len(frame)}")
+                        else:
+                            status = json.loads(frame)
+                            if status.get("type") == "audio_start":
+                                sample_rate = status.get("sample_rate", sample_rate)
+                                encoding = status.get("encoding", encoding)
+                                print(f"Audio format: {sample_rate}Hz {encoding}")
                     except asyncio.TimeoutError:
                         continue
                 
@@ -47,8 +55,17 @@ async def test_tts_service():
                     total_audio = b''.join(frames)
                     print(f"Received {len(frames)} frames ({len(total_audio)} bytes)")
                     
-                    output_file = f"tts_test_output_{i}.wav"
-                    save_wav(output_file, total_audio, 16000)
+                    if encoding in ("wav", "mp3", "ogg"):
+                        # Already a complete file
+                        output_file = f"tts_test_output_{i}.{encoding}"
+                        with open(output_file, "wb") as handle:
+                            handle.write(total_audio)
+                    else:
+                        output_file = f"tts_test_output_{i}.wav"
+                        if encoding == "pcm_f32le":
+                            samples = np.frombuffer(total_audio, dtype="<f4")
+                            total_audio = (np.clip(samples, -1.0, 1.0) * 32767).astype(np.int16).tobytes()
+                        save_wav(output_file, total_audio, sample_rate)
                     print(f"Saved audio to {output_file}")
                 else:
                     print("No audio received within timeout")
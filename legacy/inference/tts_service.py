@@ -1,4 +1,5 @@
 import asyncio
+import io
 import json
 import os
 from typing import Optional
@@ -7,6 +8,17 @@ import numpy as np
 import websockets
 import yaml
 
+PCM_ENCODINGS = ("pcm_s16le", "pcm_f32le")
+# audio_encoding -> soundfile (format, subtype)
+CONTAINER_FORMATS = {
+    "wav": ("WAV", "PCM_16"),
+    "mp3": ("MP3", "MPEG_LAYER_III"),
+    "ogg": ("OGG", "VORBIS"),
+    "opus": ("OGG", "OPUS"),
+}
+# Sample rates the Opus encoder accepts; others are resampled to 48 kHz
+OPUS_RATES = (8000, 12000, 16000, 24000, 48000)
+
 class TTSService:
     def __init__(self, host: str = "127.0.0.1", port: int = 8766):
         self.host = host
@@ -22,8 +34,9 @@ class TTSService:
         self.base_model_path = "Qwen/Qwen-Audio-TTS"
         self.custom_model_path = "Qwen/Qwen-Audio-TTS"
         self.sample_rate = 24000  # TTS model native sample rate
-        self.target_sample_rate = 16000  # Target for Rust playback
-        self.frame_size = 640  # 640 bytes = 320 samples @ 16kHz = 20ms
+        self.audio_encoding = "pcm_s16le"  # or "pcm_f32le", "wav", "mp3", "ogg", "opus"
+        self.frame_ms = 20
+        self.encoded_frame_bytes = 4096
 
     def load_config(self) -> None:
         config_path = os.path.join(os.path.dirname(__file__), "models.yaml")
@@ -38,6 +51,10 @@ class TTSService:
         self.default_voice = tts_config.get("default_voice", self.default_voice)
         self.default_language = tts_config.get("default_language", self.default_language)
         self.default_speaker = tts_config.get("default_speaker", self.default_speaker)
+        self.audio_encoding = tts_config.get("audio_encoding", self.audio_encoding)
+        if self.audio_encoding not in PCM_ENCODINGS + tuple(CONTAINER_FORMATS):
+            print(f"Unknown audio_encoding {self.audio_encoding}, using pcm_s16le")
+            self.audio_encoding = "pcm_s16le"
         self.attn_implementation = tts_config.get(
             "attn_implementation", self.attn_implementation
         )
@@ -98,6 +115,21 @@ class TTSService:
     def float32_to_pcm16(self, audio_array: np.ndarray) -> bytes:
         audio_int16 = np.clip(audio_array * 32767, -32768, 32767).astype(np.int16)
         return audio_int16.tobytes()
+
+    def encode(self, audio_array: np.ndarray, sample_rate: int) -> bytes:
+        if self.audio_encoding == "pcm_f32le":
+            return audio_array.astype("<f4").tobytes()
+        if self.audio_encoding in CONTAINER_FORMATS:
+            import soundfile
+
+            buffer = io.BytesIO()
+            container, subtype = CONTAINER_FORMATS[self.audio_encoding]
+            soundfile.write(buffer, audio_array, sample_rate, format=container, subtype=subtype)
+            return buffer.getvalue()
+        return self.float32_to_pcm16(audio_array)
+
+    def bytes_per_sample(self) -> int:
+        return 4 if self.audio_encoding == "pcm_f32le" else 2
     
     async def synthesize(
         self,
@@ -109,9 +141,11 @@ class TTSService:
         ref_audio: Optional[str],
         ref_text: Optional[str],
         voice_mode: Optional[str],
-    ) -> np.ndarray:
+    ) -> tuple[np.ndarray, int]:
+        """The waveform and its sample rate, as the model produced it."""
+        silence = (np.zeros(int(self.sample_rate * 0.5)), self.sample_rate)
         if self.base_model is None or self.custom_model is None:
-            return np.zeros(int(self.sample_rate * 0.5))
+            return silence
 
         try:
             selected_voice = voice or self.default_voice
@@ -131,7 +165,7 @@ class TTSService:
             else:
                 if not ref_audio or not ref_text:
                     print("Voice clone requires ref_audio and ref_text")
-                    return np.zeros(int(self.sample_rate * 0.5))
+                    return silence
 
                 wavs, sr = self.base_model.generate_voice_clone(
                     text=text,
@@ -142,19 +176,9 @@ class TTSService:
                 audio_waveform = wavs[0]
                 output_sample_rate = sr
             
-            print(f"TTS model output sample rate: {output_sample_rate}Hz, target: {self.target_sample_rate}Hz")
-            print(f"Audio waveform shape before resample: {audio_waveform.shape}, min: {audio_waveform.min():.4f}, max: {audio_waveform.max():.4f}")
-
-            # Always resample to target rate for consistency
-            if output_sample_rate != self.target_sample_rate:
-                import librosa
-                print(f"Resampling from {output_sample_rate}Hz to {self.target_sample_rate}Hz")
-                audio_waveform = librosa.resample(
-                    audio_waveform,
-                    orig_sr=output_sample_rate,
-                    target_sr=self.target_sample_rate
-                )
-                print(f"Audio waveform shape after resample: {audio_waveform.shape}")
+            # Sent at the model's native rate; the client resamples
+            print(f"TTS model output sample rate: {output_sample_rate}Hz")
+            print(f"Audio waveform shape: {audio_waveform.shape}, min: {audio_waveform.min():.4f}, max: {audio_waveform.max():.4f}")
 
             # Normalize audio to prevent clipping
             max_val = np.abs(audio_waveform).max()
@@ -162,10 +186,10 @@ class TTSService:
                 print(f"Normalizing audio (max was {max_val})")
                 audio_waveform = audio_waveform / max_val * 0.95
 
-            return audio_waveform
+            return audio_waveform, int(output_sample_rate)
         except Exception as e:
             print(f"TTS synthesis error: {e}")
-            return np.zeros(int(self.sample_rate * 0.5))
+            return silence
     
     async def process_text_chunk(
         self,
@@ -178,29 +202,42 @@ class TTSService:
         ref_audio: Optional[str],
         ref_text: Optional[str],
         voice_mode: Optional[str],
-    ) -> list[bytes]:
-        audio_waveform = await self.synthesize(
+    ) -> tuple[list[bytes], int]:
+        """The encoded audio in frames, and its sample rate.
+
+        PCM is cut into 20 ms frames; a compressed file is cut into pieces
+        the client decodes as one stream.
+        """
+        audio_waveform, sample_rate = await self.synthesize(
             text, voice, language, speaker, instruct, ref_audio, ref_text, voice_mode
         )
 
         # Debug: show audio stats
-        duration_sec = len(audio_waveform) / self.target_sample_rate
-        print(f"Audio duration: {duration_sec:.2f}s ({len(audio_waveform)} samples @ {self.target_sample_rate}Hz)")
+        duration_sec = len(audio_waveform) / sample_rate
+        print(f"Audio duration: {duration_sec:.2f}s ({len(audio_waveform)} samples @ {sample_rate}Hz)")
+
+        if self.audio_encoding == "opus" and sample_rate not in OPUS_RATES:
+            import librosa
 
-        audio_pcm16 = self.float32_to_pcm16(audio_waveform)
+            audio_waveform = librosa.resample(audio_waveform, orig_sr=sample_rate, target_sr=48000)
+            sample_rate = 48000
 
-        # Debug: show first few PCM16 samples
-        first_samples = np.frombuffer(audio_pcm16[:20], dtype=np.int16)
-        print(f"First PCM16 samples: {first_samples}")
+        audio_bytes = self.encode(audio_waveform, sample_rate)
+        if self.audio_encoding in CONTAINER_FORMATS:
+            size = self.encoded_frame_bytes
+            frames = [audio_bytes[i:i+size] for i in range(0, len(audio_bytes), size)]
+            return frames, sample_rate
+
+        frame_size = sample_rate * self.frame_ms // 1000 * self.bytes_per_sample()
 
         frames = []
-        for i in range(0, len(audio_pcm16), self.frame_size):
-            frame = audio_pcm16[i:i+self.frame_size]
-            if len(frame) < self.frame_size:
-                frame += b'\x00' * (self.frame_size - len(frame))
+        for i in range(0, len(audio_bytes), frame_size):
+            frame = audio_bytes[i:i+frame_size]
+            if len(frame) < frame_size:
+                frame += b'\x00' * (frame_size - len(frame))
             frames.append(frame)
 
-        return frames
+        return frames, sample_rate
     
     async def handle_connection(self, websocket):
         print(f"New TTS connection from {websocket.remote_address}")
@@ -224,7 +261,7 @@ class TTSService:
                             voice_mode = control.get("voice_mode")
                             
                             print(f"Synthesizing text: {text}")
-                            frames = await self.process_text_chunk(
+                            frames, sample_rate = await self.process_text_chunk(
                                 text,
                                 text_id,
                                 voice,
@@ -236,6 +273,17 @@ class TTSService:
                                 voice_mode,
                             )
                             
+                            # Tag the frames that follow, so the client knows
+                            # how much of each chunk it has played and how to
+                            # decode it
+                            await websocket.send(json.dumps({
+                                "type": "audio_start",
+                                "text_id": text_id,
+                                "bytes": sum(len(frame) for frame in frames),
+                                "sample_rate": sample_rate,
+                                "channels": 1,
+                                "encoding": self.audio_encoding,
+                            }))
                             if frames:
                                 print(f"Sending {len(frames)} audio frames for text_id={text_id}")
                                 for i, frame in enumerate(frames):
@@ -245,6 +293,10 @@ class TTSService:
                         elif message_type == "end":
                             await websocket.send(json.dumps({"type": "complete"}))
                             break
+                        elif message_type == "cancel":
+                            # Barge-in: the client dropped this reply, stop synthesizing
+                            print("Reply cancelled by client")
+                            break
                     except json.JSONDecodeError as e:
                         print(f"Invalid JSON message: {e}")
                     except Exception as e:
@@ -0,0 +1,253 @@
+//! Wire protocol spoken with the ASR service.
+//!
+//! Every connection opens with a `hello` from us (protocol version, audio
+//! format, language hints, client features) and a `ready` from the server
+//! carrying its capabilities. Servers that predate the handshake never
+//! answer; they are treated as protocol version 0 with default capabilities.
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::audio::state::TARGET_SAMPLE_RATE;
+
+pub const ASR_PROTOCOL_VERSION: u32 = 1;
+pub const ASR_ENCODING: &str = "pcm_s16le";
+pub const ASR_DEFAULT_LANGUAGE_HINTS: &str = "zh,en";
+
+/// Features this client understands; the server may tailor its messages.
+pub const CLIENT_FEATURES: &[&str] = &[
+    "partials",
+    "utterance_boundaries",
+    "server_errors",
+    "word_timestamps",
+    "word_confidence",
+    "alternatives",
+    "language_detection",
+    "vocabulary",
+    "utterance_ids",
+    "server_endpointing",
+];
+
+#[derive(Debug, Serialize)]
+pub struct ClientHello {
+    #[serde(rename = "type")]
+    msg_type: &'static str,
+    pub protocol_version: u32,
+    pub sample_rate: u32,
+    pub encoding: &'static str,
+    pub channels: u16,
+    pub language_hints: Vec<String>,
+    pub features: Vec<String>,
+}
+
+impl ClientHello {
+    pub fn new(language_hints: Vec<String>) -> Self {
+        Self {
+            msg_type: "hello",
+            protocol_version: ASR_PROTOCOL_VERSION,
+            sample_rate: TARGET_SAMPLE_RATE,
+            encoding: ASR_ENCODING,
+            channels: 1,
+            language_hints,
+            features: CLIENT_FEATURES.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+}
+
+/// What the server told us it can do, forwarded to the UI as
+/// `voice_assistant:asr_capabilities`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, TS)]
+pub struct AsrCapabilities {
+    #[serde(default)]
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub server: Option<String>,
+    #[serde(default)]
+    pub sample_rates: Vec<u32>,
+    #[serde(default)]
+    pub encodings: Vec<String>,
+    #[serde(default)]
+    pub languages: Vec<String>,
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+impl AsrCapabilities {
+    /// Capabilities assumed for a server that never answered `hello`.
+    pub fn legacy() -> Self {
+        Self {
+            protocol_version: 0,
+            server: None,
+            sample_rates: vec![TARGET_SAMPLE_RATE],
+            encodings: vec![ASR_ENCODING.to_string()],
+            languages: Vec::new(),
+            features: Vec::new(),
+        }
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Why we can't talk to this server, if we can't. Empty lists mean the
+    /// server didn't say, which we take as "whatever the client sends".
+    pub fn incompatibility(&self) -> Option<String> {
+        if self.protocol_version > ASR_PROTOCOL_VERSION {
+            return Some(format!(
+                "ASR server speaks protocol v{}, client supports up to v{}",
+                self.protocol_version, ASR_PROTOCOL_VERSION
+            ));
+        }
+        if !self.sample_rates.is_empty() && !self.sample_rates.contains(&TARGET_SAMPLE_RATE) {
+            return Some(format!(
+                "ASR server does not accept {}Hz audio (supports {:?})",
+                TARGET_SAMPLE_RATE, self.sample_rates
+            ));
+        }
+        if !self.encodings.is_empty() && !self.encodings.iter().any(|e| e == ASR_ENCODING) {
+            return Some(format!(
+                "ASR server does not accept {} audio (supports {:?})",
+                ASR_ENCODING, self.encodings
+            ));
+        }
+        None
+    }
+}
+
+/// An `error` or `warning` message from the server.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ServerNotice {
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub fatal: bool,
+}
+
+/// One recognized word. Times are seconds from the utterance start.
+#[derive(Clone, Debug, Serialize, Deserialize, TS)]
+pub struct AsrWord {
+    pub word: String,
+    #[serde(default)]
+    pub start: Option<f32>,
+    #[serde(default)]
+    pub end: Option<f32>,
+    #[serde(default)]
+    pub confidence: Option<f32>,
+}
+
+/// One n-best hypothesis for the whole text.
+#[derive(Clone, Debug, Serialize, Deserialize, TS)]
+pub struct AsrAlternative {
+    pub text: String,
+    #[serde(default)]
+    pub confidence: Option<f32>,
+}
+
+/// Optional detail a server may attach to `asr_result`/`utterance_final`.
+/// Each field is absent when the server doesn't provide it, and is then
+/// left out of the `user_transcript` event as well.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, TS)]
+pub struct TranscriptDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub words: Option<Vec<AsrWord>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub alternatives: Option<Vec<AsrAlternative>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub language: Option<String>,
+}
+
+impl TranscriptDetails {
+    /// Lenient: a malformed field is dropped rather than losing the text.
+    pub fn from_message(message: &serde_json::Value) -> Self {
+        fn field<T: serde::de::DeserializeOwned>(
+            message: &serde_json::Value,
+            key: &str,
+        ) -> Option<T> {
+            message
+                .get(key)
+                .filter(|v| !v.is_null())
+                .and_then(|v| serde_json::from_value(v.clone()).ok())
+        }
+
+        Self {
+            words: field(message, "words"),
+            alternatives: field(message, "alternatives"),
+            language: field(message, "language"),
+        }
+    }
+}
+
+/// Parse `ASR_LANGUAGE_HINTS` style input: comma-separated language codes.
+pub fn parse_language_hints(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hello_carries_format_and_hints() {
+        let hello = ClientHello::new(parse_language_hints(" zh , en,,"));
+        let json = serde_json::to_value(&hello).unwrap();
+        assert_eq!(json["type"], "hello");
+        assert_eq!(json["protocol_version"], ASR_PROTOCOL_VERSION);
+        assert_eq!(json["sample_rate"], TARGET_SAMPLE_RATE);
+        assert_eq!(json["encoding"], ASR_ENCODING);
+        assert_eq!(json["language_hints"], serde_json::json!(["zh", "en"]));
+    }
+
+    #[test]
+    fn sparse_ready_message_is_compatible() {
+        let caps: AsrCapabilities =
+            serde_json::from_str(r#"{"type":"ready","protocol_version":1}"#).unwrap();
+        assert!(caps.incompatibility().is_none());
+        assert!(caps.features.is_empty());
+    }
+
+    #[test]
+    fn transcript_details_are_optional_and_lenient() {
+        let message = serde_json::json!({
+            "type": "utterance_final",
+            "final": "你好 world",
+            "words": [
+                { "word": "你好", "start": 0.0, "end": 0.42, "confidence": 0.97 },
+                { "word": "world", "start": 0.5, "end": 0.9, "confidence": 0.41 }
+            ],
+            "alternatives": "not a list",
+            "language": "zh"
+        });
+        let details = TranscriptDetails::from_message(&message);
+        let words = details.words.as_ref().unwrap();
+        assert_eq!(words.len(), 2);
+        assert_eq!(words[1].confidence, Some(0.41));
+        assert!(details.alternatives.is_none(), "malformed field is dropped");
+        assert_eq!(details.language.as_deref(), Some("zh"));
+
+        let bare = TranscriptDetails::from_message(&serde_json::json!({ "partial": "hi" }));
+        assert_eq!(serde_json::to_value(&bare).unwrap(), serde_json::json!({}));
+    }
+
+    #[test]
+    fn newer_protocol_or_wrong_format_is_rejected() {
+        let newer = AsrCapabilities {
+            protocol_version: ASR_PROTOCOL_VERSION + 1,
+            ..AsrCapabilities::legacy()
+        };
+        assert!(newer.incompatibility().is_some());
+
+        let wrong_rate = AsrCapabilities {
+            sample_rates: vec![8000],
+            ..AsrCapabilities::legacy()
+        };
+        assert!(wrong_rate.incompatibility().is_some());
+    }
+}
@@ -1,22 +1,51 @@
 //! Persistent ASR session: lives for the whole service session and survives
 //! mic open/close cycles. Reconnects if the WebSocket drops while the
 //! service is still active.
+//!
+//! Pings keep the link honest: a server that stops answering is treated as
+//! disconnected. Every ended utterance must be finalized within
+//! ASR_FINAL_TIMEOUT_MS, otherwise its last partial stands in for the final.
+//!
+//! Utterance boundaries come from the mic toggles, the local `AutoVad`, or,
+//! in server endpointing mode, from the server's `endpoint` messages while
+//! audio streams continuously.
 
 use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
 use std::sync::atomic::Ordering;
-use tauri::Emitter;
+use std::sync::{Arc, Mutex};
+use tauri::Manager;
 use tokio::sync::mpsc;
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 
-use crate::audio::playback;
+use crate::audio::asr_protocol::{
+    parse_language_hints, AsrCapabilities, ClientHello, ServerNotice, TranscriptDetails,
+    ASR_DEFAULT_LANGUAGE_HINTS,
+};
+use crate::audio::service::VoiceService;
 use crate::audio::state::{
-    emit_pipeline_status, emit_service_status, emit_speech_end, emit_speech_start, AsrTranscript,
-    CaptureMsg, ASR_HOST, ASR_OK, AUDIO_FRAME_SIZE, AUTO_VAD, SERVICE_ACTIVE,
+    emit_error, emit_service_status, emit_speech_end, emit_speech_start, emit_warning,
+    AsrTranscript, CaptureMsg, EndpointingMode, ASR_HOST, AUDIO_FRAME_SIZE,
 };
+use crate::audio::utterance::{FinalMatch, Unfinalized, UtteranceTracker};
+use crate::audio::utterance_filter::{DiscardReason, UtteranceAudio, UtteranceFilter};
 use crate::audio::vad::{AutoVad, VadAction};
+use crate::audio::vocabulary::correct_transcript;
+use crate::conversation::{
+    interruption, set_turn_status, ConversationState, ConversationStatus, TurnOrchestrator,
+};
+use crate::events::{
+    self, ServiceStatus, UtteranceCancelled, UtteranceDiscarded, UtteranceFinal, VadEvent,
+    VadStatus,
+};
 
 const ASR_CONNECT_RETRIES: u32 = 5;
 const ASR_RECONNECT_DELAY_MS: u64 = 1000;
+const ASR_HELLO_TIMEOUT_MS: u64 = 2000;
+const ASR_FINAL_TIMEOUT_MS: u64 = 10000;
+const ASR_HEARTBEAT_INTERVAL_MS: u64 = 5000;
+/// Silence (not even a pong) for this long means the link is dead.
+const ASR_HEARTBEAT_TIMEOUT_MS: u64 = 15000;
 
 type WsStream =
     tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
@@ -34,21 +63,373 @@ async fn send_full_frames(ws_stream: &mut WsStream, audio_buffer: &mut Vec<u8>)
 }
 
 /// Flush the sub-frame tail and signal the utterance boundary to ASR.
-async fn send_end_utterance(ws_stream: &mut WsStream, audio_buffer: &mut Vec<u8>) -> Result<(), ()> {
+async fn send_end_utterance(
+    ws_stream: &mut WsStream,
+    audio_buffer: &mut Vec<u8>,
+    utterance_id: Option<usize>,
+) -> Result<(), ()> {
     if !audio_buffer.is_empty() {
         let tail = std::mem::take(audio_buffer);
         let _ = ws_stream.send(Message::Binary(tail)).await;
     }
-    let msg = serde_json::json!({ "type": "end_utterance" }).to_string();
+    let msg =
+        serde_json::json!({ "type": "end_utterance", "utterance_id": utterance_id }).to_string();
     ws_stream.send(Message::Text(msg)).await.map_err(|_| ())
 }
 
-async fn send_begin_utterance(ws_stream: &mut WsStream) -> Result<(), ()> {
-    let msg = serde_json::json!({ "type": "begin_utterance" }).to_string();
+async fn send_begin_utterance(ws_stream: &mut WsStream, utterance_id: usize) -> Result<(), ()> {
+    let msg =
+        serde_json::json!({ "type": "begin_utterance", "utterance_id": utterance_id }).to_string();
     ws_stream.send(Message::Text(msg)).await.map_err(|_| ())
 }
 
-fn handle_asr_message(app: &tauri::AppHandle, text: &str) {
+/// The user aborted the utterance: back to Idle without a final, and let
+/// any answer paused by barge-in carry on.
+fn finish_cancelled_utterance(app: &tauri::AppHandle, utterance_id: usize) {
+    end_utterance_turn(app, Some(utterance_id));
+    events::emit(
+        app,
+        UtteranceCancelled {
+            utterance_id: Some(utterance_id),
+        },
+    );
+    events::emit(
+        app,
+        VadEvent {
+            status: VadStatus::SpeechEnd,
+        },
+    );
+    interruption::utterance_dropped(app);
+}
+
+/// The utterance was noise rather than speech: report why and go back to
+/// Idle as if it never happened, letting a paused reply carry on.
+fn discard_utterance(
+    app: &tauri::AppHandle,
+    utterance_id: Option<usize>,
+    reason: DiscardReason,
+    text: Option<&str>,
+) {
+    println!("Discarding utterance {:?}: {:?}", utterance_id, reason);
+    end_utterance_turn(app, utterance_id);
+    events::emit(
+        app,
+        UtteranceDiscarded {
+            utterance_id,
+            reason,
+            text: text.map(str::to_string),
+        },
+    );
+    interruption::utterance_dropped(app);
+}
+
+/// Close the open utterance. Audio too short or too quiet to be speech is
+/// reset on the server and discarded here instead of being finalized.
+async fn close_utterance(
+    app: &tauri::AppHandle,
+    ws_stream: &mut WsStream,
+    audio_buffer: &mut Vec<u8>,
+    utterances: &mut UtteranceTracker,
+    audio: &UtteranceAudio,
+) -> Result<(), ()> {
+    if let Some(reason) = audio.verdict() {
+        if let Some(utterance_id) = utterances.discard() {
+            audio_buffer.clear();
+            let sent = send_reset(ws_stream, utterance_id).await;
+            discard_utterance(app, Some(utterance_id), reason, None);
+            return sent;
+        }
+    }
+    let utterance_id = utterances.end();
+    send_end_utterance(ws_stream, audio_buffer, utterance_id).await
+}
+
+/// A new utterance is a new conversation turn; record which utterance
+/// produced it so transcripts and turns can be tied together.
+fn start_turn_for_utterance(app: &tauri::AppHandle, utterance_id: usize) {
+    let turn_id = app
+        .try_state::<Arc<Mutex<ConversationState>>>()
+        .map(|conv_state| {
+            let mut conv = conv_state.lock().unwrap();
+            let turn_id = conv.start_new_turn();
+            conv.set_asr_segment_id(utterance_id);
+            turn_id
+        });
+    set_turn_status(app, turn_id, ConversationStatus::Listening);
+}
+
+/// The utterance ended without a final: its turn is over.
+fn end_utterance_turn(app: &tauri::AppHandle, utterance_id: Option<usize>) {
+    let turn_id = app
+        .try_state::<Arc<Mutex<ConversationState>>>()
+        .map(|conv_state| {
+            let conv = conv_state.lock().unwrap();
+            utterance_id
+                .and_then(|id| conv.turn_for_segment(id))
+                .unwrap_or(conv.get_current_turn().id)
+        });
+    set_turn_status(app, turn_id, ConversationStatus::Idle);
+}
+
+/// Tell the server to drop whatever it has buffered for the utterance.
+async fn send_reset(ws_stream: &mut WsStream, utterance_id: usize) -> Result<(), ()> {
+    let msg = serde_json::json!({ "type": "reset", "utterance_id": utterance_id }).to_string();
+    ws_stream.send(Message::Text(msg)).await.map_err(|_| ())
+}
+
+/// Hot-word list for servers that can bias decoding; others ignore it.
+async fn send_vocabulary(ws_stream: &mut WsStream, terms: &[String]) -> Result<(), ()> {
+    let msg = serde_json::json!({ "type": "vocabulary", "terms": terms }).to_string();
+    ws_stream.send(Message::Text(msg)).await.map_err(|_| ())
+}
+
+/// Ask the server to endpoint the continuous stream itself (`server`), or
+/// to leave it to the client again.
+async fn send_endpointing(ws_stream: &mut WsStream, mode: EndpointingMode) -> Result<(), ()> {
+    let msg = serde_json::json!({ "type": "endpointing", "mode": mode }).to_string();
+    ws_stream.send(Message::Text(msg)).await.map_err(|_| ())
+}
+
+/// Whether the server can endpoint for us. If not, hands-free mode falls
+/// back to the local VAD and the UI is told so.
+fn server_endpointing_available(
+    app: &tauri::AppHandle,
+    service: &VoiceService,
+    capabilities: &AsrCapabilities,
+) -> bool {
+    if capabilities.supports("server_endpointing") {
+        return true;
+    }
+    emit_warning(
+        app,
+        "ASR_ENDPOINTING_UNSUPPORTED",
+        "ASR server cannot detect utterance boundaries; using local VAD",
+    );
+    service.server_endpointing.store(false, Ordering::SeqCst);
+    service.emit_vad_mode(app);
+    false
+}
+
+enum HandshakeError {
+    /// The link dropped mid-handshake; worth reconnecting.
+    Disconnected,
+    /// The server refused us or can't take our audio; retrying won't help.
+    Rejected { code: String, message: String },
+}
+
+/// Send `hello` and wait for the server's `ready`. A server that stays
+/// silent past the timeout predates the handshake and gets legacy defaults.
+async fn perform_handshake(
+    app: &tauri::AppHandle,
+    ws_stream: &mut WsStream,
+    language_hints: Vec<String>,
+) -> Result<AsrCapabilities, HandshakeError> {
+    let hello = serde_json::to_string(&ClientHello::new(language_hints))
+        .expect("ClientHello is always serializable");
+    if ws_stream.send(Message::Text(hello)).await.is_err() {
+        return Err(HandshakeError::Disconnected);
+    }
+
+    let deadline =
+        tokio::time::Instant::now() + tokio::time::Duration::from_millis(ASR_HELLO_TIMEOUT_MS);
+    loop {
+        let next = match tokio::time::timeout_at(deadline, ws_stream.next()).await {
+            Ok(next) => next,
+            Err(_) => {
+                eprintln!("ASR server did not answer hello; assuming legacy protocol");
+                return Ok(AsrCapabilities::legacy());
+            }
+        };
+
+        match next {
+            Some(Ok(Message::Text(text))) => {
+                let Ok(value) = serde_json::from_str::<serde_json::Value>(&text) else {
+                    continue;
+                };
+                match value.get("type").and_then(|v| v.as_str()) {
+                    Some("ready") => {
+                        let capabilities = AsrCapabilities::deserialize(&value).map_err(|e| {
+                            HandshakeError::Rejected {
+                                code: "ASR_PROTOCOL_MISMATCH".to_string(),
+                                message: format!("Malformed ready message: {}", e),
+                            }
+                        })?;
+                        if let Some(reason) = capabilities.incompatibility() {
+                            return Err(HandshakeError::Rejected {
+                                code: "ASR_PROTOCOL_MISMATCH".to_string(),
+                                message: reason,
+                            });
+                        }
+                        return Ok(capabilities);
+                    }
+                    Some("error") => {
+                        let notice = ServerNotice::deserialize(&value).ok();
+                        return Err(HandshakeError::Rejected {
+                            code: notice
+                                .as_ref()
+                                .and_then(|n| n.code.clone())
+                                .unwrap_or_else(|| "ASR_HANDSHAKE_REJECTED".to_string()),
+                            message: notice.map(|n| n.message).unwrap_or_default(),
+                        });
+                    }
+                    Some("warning") => handle_server_notice(app, &value, false),
+                    _ => {}
+                }
+            }
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                return Err(HandshakeError::Disconnected);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Surface a server `error`/`warning` as a typed `voice_assistant:error`.
+fn handle_server_notice(app: &tauri::AppHandle, result: &serde_json::Value, is_error: bool) {
+    let Ok(notice) = ServerNotice::deserialize(result) else {
+        return;
+    };
+
+    if is_error {
+        let code = notice.code.as_deref().unwrap_or("ASR_SERVER_ERROR");
+        eprintln!("ASR server error [{}]: {}", code, notice.message);
+        emit_error(app, code, &notice.message);
+        if notice.fatal {
+            emit_service_status(
+                app,
+                ServiceStatus::Error,
+                &format!("语音识别服务出错：{}", notice.message),
+            );
+        }
+    } else {
+        let code = notice.code.as_deref().unwrap_or("ASR_SERVER_WARNING");
+        emit_warning(app, code, &notice.message);
+    }
+}
+
+/// Whole-utterance text on its way to becoming a turn.
+struct FinalTranscript {
+    utterance_id: Option<usize>,
+    raw_text: String,
+    confidence: f32,
+    details: TranscriptDetails,
+    /// No final came; this is the last partial standing in for it.
+    from_partial: bool,
+}
+
+fn deliver_final(
+    app: &tauri::AppHandle,
+    utterance: FinalTranscript,
+    vocabulary: &[String],
+    filter: &UtteranceFilter,
+) {
+    let FinalTranscript {
+        utterance_id,
+        raw_text,
+        confidence,
+        details,
+        from_partial,
+    } = utterance;
+    // Hot-word post-correction: the server may not support biasing
+    let final_text = correct_transcript(&raw_text, vocabulary);
+    if let Some(reason) = filter.check_text(&final_text) {
+        discard_utterance(app, utterance_id, reason, Some(&final_text));
+        return;
+    }
+    if !interruption::accept_final(app, &final_text) {
+        discard_utterance(
+            app,
+            utterance_id,
+            DiscardReason::Backchannel,
+            Some(&final_text),
+        );
+        return;
+    }
+
+    let language = details.language.clone();
+    events::emit(
+        app,
+        AsrTranscript {
+            utterance_id,
+            partial: String::new(),
+            final_text: Some(final_text.clone()),
+            confidence,
+            details,
+        },
+    );
+    events::emit(
+        app,
+        UtteranceFinal {
+            utterance_id,
+            text: final_text.clone(),
+            raw_text,
+            confidence,
+            language,
+            from_partial,
+        },
+    );
+    if let Some(orchestrator) = app.try_state::<TurnOrchestrator>() {
+        orchestrator.submit(app, utterance_id, final_text);
+    }
+}
+
+/// The server never finalized this utterance. Use the last partial if
+/// there was one; otherwise report it and give the turn up.
+fn resolve_unfinalized(
+    app: &tauri::AppHandle,
+    utterance: Unfinalized,
+    vocabulary: &[String],
+    filter: &UtteranceFilter,
+) {
+    if utterance.last_partial.trim().is_empty() {
+        eprintln!(
+            "No final for utterance {} and no partial to fall back on",
+            utterance.id
+        );
+        emit_error(
+            app,
+            "ASR_FINAL_TIMEOUT",
+            &format!("ASR did not finalize utterance {}", utterance.id),
+        );
+        end_utterance_turn(app, Some(utterance.id));
+        interruption::utterance_dropped(app);
+        return;
+    }
+
+    eprintln!(
+        "No final for utterance {}; using its last partial",
+        utterance.id
+    );
+    emit_warning(
+        app,
+        "ASR_FINAL_TIMEOUT",
+        &format!(
+            "ASR did not finalize utterance {}; using the last partial transcript",
+            utterance.id
+        ),
+    );
+    deliver_final(
+        app,
+        FinalTranscript {
+            utterance_id: Some(utterance.id),
+            raw_text: utterance.last_partial,
+            confidence: 0.0,
+            details: TranscriptDetails::default(),
+            from_partial: true,
+        },
+        vocabulary,
+        filter,
+    );
+}
+
+fn handle_asr_message(
+    app: &tauri::AppHandle,
+    text: &str,
+    vocabulary: &[String],
+    utterances: &mut UtteranceTracker,
+    filter: &UtteranceFilter,
+    audio: &mut UtteranceAudio,
+) {
     let Ok(result) = serde_json::from_str::<serde_json::Value>(text) else {
         return;
     };
@@ -63,6 +444,11 @@ fn handle_asr_message(app: &tauri::AppHandle, text: &str) {
         .and_then(|v| v.as_f64())
         .unwrap_or(0.0) as f32;
 
+    let echoed_id = result
+        .get("utterance_id")
+        .and_then(|v| v.as_u64())
+        .map(|id| id as usize);
+
     match msg_type {
         // Streaming window transcription while the utterance is in progress.
         // The Python side accumulates windows, so `partial` is the full
@@ -73,43 +459,96 @@ fn handle_asr_message(app: &tauri::AppHandle, text: &str) {
                 .and_then(|v| v.as_str())
                 .unwrap_or("")
                 .to_string();
-
-            let transcript = AsrTranscript {
-                partial,
-                final_text: None,
-                confidence,
+            // Late partials of an utterance that already ended are stale
+            let Some(utterance_id) = utterances.accept_partial(echoed_id, &partial) else {
+                return;
             };
-            let _ = app.emit("voice_assistant:user_transcript", &transcript);
+
+            events::emit(
+                app,
+                AsrTranscript {
+                    utterance_id: Some(utterance_id),
+                    partial,
+                    final_text: None,
+                    confidence,
+                    details: TranscriptDetails::from_message(&result),
+                },
+            );
         }
         // Authoritative whole-utterance transcription (with punctuation),
         // produced after end_utterance. This is the text that goes to the LLM.
         "utterance_final" => {
-            let final_text = result
+            let utterance_id = match utterances.accept_final(echoed_id) {
+                FinalMatch::Deliver(id) => id,
+                FinalMatch::Discard(id) => {
+                    println!("Dropping final of cancelled or timed-out utterance {}", id);
+                    return;
+                }
+            };
+            let raw_text = result
                 .get("final")
                 .and_then(|v| v.as_str())
                 .unwrap_or("")
                 .to_string();
-
-            let transcript = AsrTranscript {
-                partial: String::new(),
-                final_text: Some(final_text.clone()),
-                confidence,
-            };
-            let _ = app.emit("voice_assistant:user_transcript", &transcript);
-            let _ = app.emit(
-                "voice_assistant:utterance_final",
-                serde_json::json!({ "text": final_text, "confidence": confidence }),
+            deliver_final(
+                app,
+                FinalTranscript {
+                    utterance_id,
+                    raw_text,
+                    confidence,
+                    details: TranscriptDetails::from_message(&result),
+                    from_partial: false,
+                },
+                vocabulary,
+                filter,
             );
         }
+        // Server endpointing: the server's VAD saw speech start or stop.
+        // Its messages carry no client ID; the tracker attributes them.
+        "endpoint" => match result.get("event").and_then(|v| v.as_str()) {
+            Some("speech_start") => {
+                interruption::user_started_speaking(app);
+                emit_speech_start(app);
+                audio.reset();
+                let utterance_id = utterances.begin();
+                start_turn_for_utterance(app, utterance_id);
+            }
+            Some("speech_end") => {
+                if let Some(utterance_id) = utterances.end() {
+                    emit_speech_end(app);
+                    // The server finalizes right away; if the audio was no
+                    // speech, its final is swallowed
+                    if let Some(reason) = audio.verdict() {
+                        utterances.cancel();
+                        discard_utterance(app, Some(utterance_id), reason, None);
+                    }
+                    interruption::user_stopped_speaking(app);
+                }
+            }
+            _ => {}
+        },
+        "error" => handle_server_notice(app, &result, true),
+        "warning" => handle_server_notice(app, &result, false),
         _ => {}
     }
 }
 
 pub async fn run_asr_session(
     app: tauri::AppHandle,
+    service: Arc<VoiceService>,
     mut pipe_rx: mpsc::UnboundedReceiver<CaptureMsg>,
+    mut vocabulary: Vec<String>,
 ) {
-    'outer: while SERVICE_ACTIVE.load(Ordering::SeqCst) {
+    let language_hints = parse_language_hints(
+        &std::env::var("ASR_LANGUAGE_HINTS")
+            .unwrap_or_else(|_| ASR_DEFAULT_LANGUAGE_HINTS.to_string()),
+    );
+    // IDs stay unique across reconnects for the whole service session
+    let mut utterances =
+        UtteranceTracker::new(tokio::time::Duration::from_millis(ASR_FINAL_TIMEOUT_MS));
+    let filter = UtteranceFilter::from_env();
+
+    'outer: while service.active.load(Ordering::SeqCst) {
         let mut ws_stream = None;
         for attempt in 0..ASR_CONNECT_RETRIES {
             match connect_async(ASR_HOST).await {
@@ -130,7 +569,7 @@ pub async fn run_asr_session(
                     .await;
                 }
             }
-            if !SERVICE_ACTIVE.load(Ordering::SeqCst) {
+            if !service.active.load(Ordering::SeqCst) {
                 break 'outer;
             }
         }
@@ -138,52 +577,115 @@ pub async fn run_asr_session(
         let mut ws_stream = match ws_stream {
             Some(s) => s,
             None => {
-                emit_service_status(&app, "error", "无法连接语音识别服务（端口 8765），请确认推理服务已启动");
-                let _ = app.emit(
-                    "voice_assistant:error",
-                    serde_json::json!({ "code": "ASR_CONNECTION_FAILED", "message": "ASR unreachable" }),
+                emit_service_status(
+                    &app,
+                    ServiceStatus::Error,
+                    "无法连接语音识别服务（端口 8765），请确认推理服务已启动",
                 );
-                SERVICE_ACTIVE.store(false, Ordering::SeqCst);
+                emit_error(&app, "ASR_CONNECTION_FAILED", "ASR unreachable");
+                service.active.store(false, Ordering::SeqCst);
                 break;
             }
         };
 
-        ASR_OK.store(true, Ordering::SeqCst);
-        emit_pipeline_status(&app);
+        let capabilities =
+            match perform_handshake(&app, &mut ws_stream, language_hints.clone()).await {
+                Ok(capabilities) => capabilities,
+                Err(HandshakeError::Disconnected) => {
+                    emit_service_status(
+                        &app,
+                        ServiceStatus::Reconnecting,
+                        "语音识别连接中断，正在重连…",
+                    );
+                    tokio::time::sleep(tokio::time::Duration::from_millis(ASR_RECONNECT_DELAY_MS))
+                        .await;
+                    continue 'outer;
+                }
+                Err(HandshakeError::Rejected { code, message }) => {
+                    eprintln!("ASR handshake rejected [{}]: {}", code, message);
+                    emit_service_status(
+                        &app,
+                        ServiceStatus::Error,
+                        "语音识别服务版本或音频格式不兼容",
+                    );
+                    emit_error(&app, &code, &message);
+                    service.active.store(false, Ordering::SeqCst);
+                    let _ = ws_stream.close(None).await;
+                    break;
+                }
+            };
+        events::emit(&app, capabilities.clone());
+
+        service.asr_ok.store(true, Ordering::SeqCst);
+        service.emit_pipeline_status(&app);
 
         let mut audio_buffer: Vec<u8> = Vec::with_capacity(AUDIO_FRAME_SIZE);
         let mut vad = AutoVad::new();
+        let mut utterance_audio = UtteranceAudio::new();
+        let heartbeat_period = tokio::time::Duration::from_millis(ASR_HEARTBEAT_INTERVAL_MS);
+        let mut heartbeat = tokio::time::interval_at(
+            tokio::time::Instant::now() + heartbeat_period,
+            heartbeat_period,
+        );
+        let mut last_heard = tokio::time::Instant::now();
 
         macro_rules! reconnect {
             () => {{
-                ASR_OK.store(false, Ordering::SeqCst);
-                emit_service_status(&app, "reconnecting", "语音识别连接中断，正在重连…");
+                service.asr_ok.store(false, Ordering::SeqCst);
+                for lost in utterances.reset_in_flight() {
+                    resolve_unfinalized(&app, lost, &vocabulary, &filter);
+                }
+                emit_service_status(
+                    &app,
+                    ServiceStatus::Reconnecting,
+                    "语音识别连接中断，正在重连…",
+                );
                 continue 'outer;
             }};
         }
 
+        if !vocabulary.is_empty() && send_vocabulary(&mut ws_stream, &vocabulary).await.is_err() {
+            reconnect!();
+        }
+
+        let mut server_endpointing = service.endpointing_mode() == EndpointingMode::Server
+            && server_endpointing_available(&app, &service, &capabilities);
+        if server_endpointing
+            && send_endpointing(&mut ws_stream, EndpointingMode::Server)
+                .await
+                .is_err()
+        {
+            reconnect!();
+        }
+
         loop {
+            let final_deadline = utterances.next_deadline();
+
             tokio::select! {
                 // Audio frames + utterance boundaries, in FIFO order
                 maybe_msg = pipe_rx.recv() => {
                     match maybe_msg {
                         Some(CaptureMsg::Frame(audio_data)) => {
-                            if AUTO_VAD.load(Ordering::SeqCst) {
-                                let playback_active = playback::is_audibly_playing();
+                            if service.auto_vad.load(Ordering::SeqCst) && !server_endpointing {
+                                let playback_active = service.playback.is_audibly_playing();
                                 let (send_frame, action) = vad.feed(audio_data, playback_active);
 
                                 match action {
                                     VadAction::StartUtterance => {
-                                        playback::pause_playback_internal(&app);
+                                        interruption::user_started_speaking(&app);
                                         emit_speech_start(&app);
                                         audio_buffer.clear();
-                                        if send_begin_utterance(&mut ws_stream).await.is_err() {
+                                        utterance_audio.reset();
+                                        let utterance_id = utterances.begin();
+                                        start_turn_for_utterance(&app, utterance_id);
+                                        if send_begin_utterance(&mut ws_stream, utterance_id).await.is_err() {
                                             reconnect!();
                                         }
                                         // Flush the pre-roll so the onset isn't clipped
                                         let preroll: Vec<Vec<u8>> = vad.prebuffer.drain(..).collect();
                                         vad.prebuffer_ms = 0.0;
                                         for f in preroll {
+                                            utterance_audio.feed(&f);
                                             audio_buffer.extend_from_slice(&f);
                                         }
                                         if send_full_frames(&mut ws_stream, &mut audio_buffer).await.is_err() {
@@ -192,18 +694,20 @@ pub async fn run_asr_session(
                                     }
                                     VadAction::EndUtterance => {
                                         if let Some(f) = send_frame {
+                                            utterance_audio.feed(&f);
                                             audio_buffer.extend_from_slice(&f);
                                         }
+                                        emit_speech_end(&app);
                                         if send_full_frames(&mut ws_stream, &mut audio_buffer).await.is_err()
-                                            || send_end_utterance(&mut ws_stream, &mut audio_buffer).await.is_err()
+                                            || close_utterance(&app, &mut ws_stream, &mut audio_buffer, &mut utterances, &utterance_audio).await.is_err()
                                         {
                                             reconnect!();
                                         }
-                                        emit_speech_end(&app);
-                                        playback::resume_playback_internal(&app);
+                                        interruption::user_stopped_speaking(&app);
                                     }
                                     VadAction::None => {
                                         if let Some(f) = send_frame {
+                                            utterance_audio.feed(&f);
                                             audio_buffer.extend_from_slice(&f);
                                             if send_full_frames(&mut ws_stream, &mut audio_buffer).await.is_err() {
                                                 reconnect!();
@@ -212,7 +716,9 @@ pub async fn run_asr_session(
                                     }
                                 }
                             } else {
-                                // Manual mode: frames only arrive while the mic is open
+                                // Manual mode: frames only arrive while the mic is open.
+                                // Server endpointing: they stream continuously.
+                                utterance_audio.feed(&audio_data);
                                 audio_buffer.extend_from_slice(&audio_data);
                                 if send_full_frames(&mut ws_stream, &mut audio_buffer).await.is_err() {
                                     reconnect!();
@@ -221,31 +727,67 @@ pub async fn run_asr_session(
                         }
                         Some(CaptureMsg::BeginUtterance) => {
                             audio_buffer.clear();
-                            if send_begin_utterance(&mut ws_stream).await.is_err() {
+                            utterance_audio.reset();
+                            let utterance_id = utterances.begin();
+                            start_turn_for_utterance(&app, utterance_id);
+                            if send_begin_utterance(&mut ws_stream, utterance_id).await.is_err() {
                                 reconnect!();
                             }
                         }
                         Some(CaptureMsg::EndUtterance) => {
-                            if send_end_utterance(&mut ws_stream, &mut audio_buffer).await.is_err() {
+                            if close_utterance(&app, &mut ws_stream, &mut audio_buffer, &mut utterances, &utterance_audio).await.is_err() {
                                 reconnect!();
                             }
                         }
-                        Some(CaptureMsg::SetAutoVad(enabled)) => {
-                            // Turning auto mode off mid-speech: close the
+                        Some(CaptureMsg::SetEndpointing(mode)) => {
+                            // Leaving local VAD mid-speech: close the
                             // in-flight utterance so it isn't left dangling.
-                            if !enabled && vad.in_speech {
-                                if send_end_utterance(&mut ws_stream, &mut audio_buffer).await.is_err() {
+                            if mode != EndpointingMode::Client && vad.in_speech {
+                                emit_speech_end(&app);
+                                if close_utterance(&app, &mut ws_stream, &mut audio_buffer, &mut utterances, &utterance_audio).await.is_err() {
                                     vad.reset();
                                     reconnect!();
                                 }
-                                emit_speech_end(&app);
-                                playback::resume_playback_internal(&app);
+                                interruption::user_stopped_speaking(&app);
                             }
                             vad.reset();
+
+                            let use_server = mode == EndpointingMode::Server
+                                && server_endpointing_available(&app, &service, &capabilities);
+                            if use_server != server_endpointing {
+                                server_endpointing = use_server;
+                                let requested = if use_server { EndpointingMode::Server } else { mode };
+                                if send_endpointing(&mut ws_stream, requested).await.is_err() {
+                                    reconnect!();
+                                }
+                                // Taking endpointing back: the server finalizes what it had open
+                                if !use_server && utterances.end().is_some() {
+                                    emit_speech_end(&app);
+                                    interruption::user_stopped_speaking(&app);
+                                }
+                            }
+                        }
+                        Some(CaptureMsg::CancelUtterance) => {
+                            // Nothing in flight: there is no turn to end
+                            if let Some(id) = utterances.cancel() {
+                                audio_buffer.clear();
+                                vad.reset();
+                                let sent = send_reset(&mut ws_stream, id).await;
+                                finish_cancelled_utterance(&app, id);
+                                if sent.is_err() {
+                                    reconnect!();
+                                }
+                            }
+                        }
+                        Some(CaptureMsg::SetVocabulary(terms)) => {
+                            vocabulary = terms;
+                            if send_vocabulary(&mut ws_stream, &vocabulary).await.is_err() {
+                                reconnect!();
+                            }
                         }
                         None => {
                             // Service shut down
-                            ASR_OK.store(false, Ordering::SeqCst);
+                            service.asr_ok.store(false, Ordering::SeqCst);
                             let _ = ws_stream.close(None).await;
                             break 'outer;
                         }
@@ -254,19 +796,22 @@ pub async fn run_asr_session(
 
                 // Results from ASR
                 maybe_msg = ws_stream.next() => {
+                    if matches!(maybe_msg, Some(Ok(_))) {
+                        last_heard = tokio::time::Instant::now();
+                    }
                     match maybe_msg {
                         Some(Ok(Message::Text(text))) => {
-                            handle_asr_message(&app, &text);
+                            handle_asr_message(&app, &text, &vocabulary, &mut utterances, &filter, &mut utterance_audio);
                         }
                         Some(Ok(Message::Close(_))) | None => {
-                            if SERVICE_ACTIVE.load(Ordering::SeqCst) {
+                            if service.active.load(Ordering::SeqCst) {
                                 reconnect!();
                             }
                             break 'outer;
                         }
                         Some(Err(e)) => {
                             eprintln!("ASR WebSocket error: {}", e);
-                            if SERVICE_ACTIVE.load(Ordering::SeqCst) {
+                            if service.active.load(Ordering::SeqCst) {
                                 reconnect!();
                             }
                             break 'outer;
@@ -274,9 +819,29 @@ pub async fn run_asr_session(
                         _ => {}
                     }
                 }
+
+                // An ended utterance ran out of time to be finalized
+                _ = tokio::time::sleep_until(
+                    final_deadline.map(tokio::time::Instant::from_std).unwrap_or_else(tokio::time::Instant::now)
+                ), if final_deadline.is_some() => {
+                    for expired in utterances.take_expired(std::time::Instant::now()) {
+                        resolve_unfinalized(&app, expired, &vocabulary, &filter);
+                    }
+                }
+
+                _ = heartbeat.tick() => {
+                    if last_heard.elapsed() >= tokio::time::Duration::from_millis(ASR_HEARTBEAT_TIMEOUT_MS) {
+                        eprintln!("ASR server stopped answering pings");
+                        let _ = ws_stream.close(None).await;
+                        reconnect!();
+                    }
+                    if ws_stream.send(Message::Ping(Vec::new())).await.is_err() {
+                        reconnect!();
+                    }
+                }
             }
         }
     }
 
-    ASR_OK.store(false, Ordering::SeqCst);
+    service.asr_ok.store(false, Ordering::SeqCst);
 }
@@ -0,0 +1,187 @@
+//! Short sounds that tell the user what the assistant is doing when the
+//! screen isn't in view: speech heard, speech over, an error, the reply
+//! finished.
+//!
+//! The sounds are a few notes synthesized at the output rate, so they play
+//! on any device. The `EarconMixer` adds them on top of whatever the output
+//! callback already rendered, TTS or silence.
+
+use serde::{Deserialize, Serialize};
+
+/// Peak level of a note before the cue's volume.
+const LEVEL: f32 = 0.4;
+/// Each note fades in and out over this long, so it doesn't click.
+const RAMP_MS: u32 = 8;
+const DEFAULT_VOLUME: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Earcon {
+    /// The VAD heard speech start.
+    SpeechStart,
+    /// The VAD heard speech end: the utterance was taken.
+    SpeechEnd,
+    Error,
+    /// A reply has been spoken in full.
+    TurnComplete,
+}
+
+impl Earcon {
+    pub const ALL: [Earcon; 4] = [
+        Earcon::SpeechStart,
+        Earcon::SpeechEnd,
+        Earcon::Error,
+        Earcon::TurnComplete,
+    ];
+
+    /// (frequency in Hz, length in ms); 0Hz is a rest.
+    fn notes(self) -> &'static [(f32, u32)] {
+        match self {
+            Earcon::SpeechStart => &[(660.0, 60), (880.0, 90)],
+            Earcon::SpeechEnd => &[(880.0, 60), (660.0, 90)],
+            Earcon::Error => &[(330.0, 110), (0.0, 50), (311.1, 160)],
+            Earcon::TurnComplete => &[(523.3, 80), (784.0, 180)],
+        }
+    }
+
+    /// Output frame `position` of the sound at `rate`, or None past its end.
+    fn sample(self, position: usize, rate: u32) -> Option<f32> {
+        let mut start = 0;
+        for &(frequency, ms) in self.notes() {
+            let len = (rate * ms / 1000) as usize;
+            if position >= start + len {
+                start += len;
+                continue;
+            }
+            if frequency == 0.0 {
+                return Some(0.0);
+            }
+            let offset = position - start;
+            let ramp = (rate * RAMP_MS / 1000).max(1) as usize;
+            let envelope = (offset.min(len - 1 - offset) as f32 / ramp as f32).min(1.0);
+            let phase = std::f32::consts::TAU * frequency * offset as f32 / rate as f32;
+            return Some(LEVEL * envelope * phase.sin());
+        }
+        None
+    }
+}
+
+/// Whether a cue plays, and how loud.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct EarconSetting {
+    pub enabled: bool,
+    /// In [0, 1].
+    pub volume: f32,
+}
+
+impl Default for EarconSetting {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            volume: DEFAULT_VOLUME,
+        }
+    }
+}
+
+/// The cues playing now.
+#[derive(Default)]
+pub struct EarconMixer {
+    voices: Vec<Voice>,
+}
+
+struct Voice {
+    earcon: Earcon,
+    gain: f32,
+    /// Output frames played.
+    position: usize,
+}
+
+impl EarconMixer {
+    /// Start `earcon` at `gain`; one already playing starts over.
+    pub fn play(&mut self, earcon: Earcon, gain: f32) {
+        self.voices.retain(|voice| voice.earcon != earcon);
+        self.voices.push(Voice {
+            earcon,
+            gain,
+            position: 0,
+        });
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.voices.is_empty()
+    }
+
+    pub fn stop(&mut self) {
+        self.voices.clear();
+    }
+
+    /// Add the cues to one output buffer. Returns true when the last of
+    /// them has just ended.
+    pub fn mix(&mut self, out: &mut [f32], rate: u32, channels: u16) -> bool {
+        if self.voices.is_empty() || channels == 0 {
+            return false;
+        }
+        self.voices.retain_mut(|voice| {
+            for frame in out.chunks_mut(channels as usize) {
+                let Some(sample) = voice.earcon.sample(voice.position, rate) else {
+                    return false;
+                };
+                voice.position += 1;
+                for out in frame {
+                    *out = (*out + sample * voice.gain).clamp(-1.0, 1.0);
+                }
+            }
+            voice.earcon.sample(voice.position, rate).is_some()
+        });
+        self.voices.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn length(earcon: Earcon, rate: u32) -> usize {
+        (0..)
+            .take_while(|&i| earcon.sample(i, rate).is_some())
+            .count()
+    }
+
+    #[test]
+    fn cues_are_short_and_start_and_end_silent() {
+        for earcon in Earcon::ALL {
+            let len = length(earcon, 48000);
+            assert!(len > 4800 && len < 19200, "{:?}: {}", earcon, len);
+            assert_eq!(earcon.sample(0, 48000), Some(0.0));
+            assert!(earcon.sample(len - 1, 48000).unwrap().abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn cues_are_added_on_top_of_the_output() {
+        let mut mixer = EarconMixer::default();
+        mixer.play(Earcon::SpeechStart, 1.0);
+        let mut out = vec![0.9; 960];
+        assert!(!mixer.mix(&mut out, 16000, 2));
+        assert_eq!(out[0], 0.9);
+        assert!(out.iter().all(|s| (-1.0..=1.0).contains(s)));
+        assert!(out.iter().any(|&s| s != 0.9));
+        // Both channels get the cue
+        assert!(out.chunks(2).all(|frame| frame[0] == frame[1]));
+    }
+
+    #[test]
+    fn mixing_reports_when_the_last_cue_ends() {
+        let mut mixer = EarconMixer::default();
+        assert!(!mixer.mix(&mut [0.0; 160], 16000, 1), "nothing to end");
+        mixer.play(Earcon::SpeechEnd, 0.5);
+        mixer.play(Earcon::SpeechEnd, 0.5);
+        assert_eq!(mixer.voices.len(), 1, "restarted, not doubled");
+
+        let len = length(Earcon::SpeechEnd, 16000);
+        let mut out = vec![0.0; len - 1];
+        assert!(!mixer.mix(&mut out, 16000, 1));
+        assert!(mixer.mix(&mut [0.0; 160], 16000, 1));
+        assert!(!mixer.is_playing());
+    }
+}
@@ -0,0 +1,184 @@
+//! How much TTS audio to hold back before playing, learned from how it
+//! arrives.
+//!
+//! Each frame's arrival is compared with when playback would have needed
+//! it, had it started with the first frame: the largest shortfall is the
+//! pre-roll that would have avoided every underrun. The longest gap between
+//! arrivals bounds how long the queue may sit empty mid-reply before the
+//! reply counts as finished. After each playback both move towards what it
+//! needed: up at once, down slowly.
+
+use std::time::{Duration, Instant};
+
+/// Before the first playback: five 20ms frames, and the old drain timeout.
+const INITIAL_PREROLL: Duration = Duration::from_millis(100);
+const INITIAL_DRAIN_TIMEOUT: Duration = Duration::from_millis(1000);
+
+const MIN_PREROLL: Duration = Duration::from_millis(40);
+const MAX_PREROLL: Duration = Duration::from_millis(1000);
+/// Head room over the shortfall that was seen.
+const PREROLL_MARGIN: Duration = Duration::from_millis(20);
+
+const MIN_DRAIN_TIMEOUT: Duration = Duration::from_millis(300);
+const MAX_DRAIN_TIMEOUT: Duration = Duration::from_millis(2000);
+
+pub struct JitterEstimator {
+    preroll: Duration,
+    drain_timeout: Duration,
+    /// The arrivals of the current playback.
+    arrivals: Option<Arrivals>,
+}
+
+struct Arrivals {
+    first: Instant,
+    last: Instant,
+    /// Audio received so far.
+    received: Duration,
+    shortfall: Duration,
+    max_gap: Option<Duration>,
+}
+
+impl Default for JitterEstimator {
+    fn default() -> Self {
+        Self {
+            preroll: INITIAL_PREROLL,
+            drain_timeout: INITIAL_DRAIN_TIMEOUT,
+            arrivals: None,
+        }
+    }
+}
+
+impl JitterEstimator {
+    /// `duration` of audio arrived at `now`. The first arrival starts a
+    /// playback.
+    pub fn arrival(&mut self, now: Instant, duration: Duration) {
+        let Some(arrivals) = self.arrivals.as_mut() else {
+            self.arrivals = Some(Arrivals {
+                first: now,
+                last: now,
+                received: duration,
+                shortfall: Duration::ZERO,
+                max_gap: None,
+            });
+            return;
+        };
+
+        let due = arrivals.first + arrivals.received;
+        arrivals.shortfall = arrivals.shortfall.max(now.saturating_duration_since(due));
+        let gap = now.saturating_duration_since(arrivals.last);
+        arrivals.max_gap = Some(arrivals.max_gap.map_or(gap, |max| max.max(gap)));
+        arrivals.last = now;
+        arrivals.received += duration;
+    }
+
+    /// The playback is over: learn from it.
+    pub fn end_playback(&mut self) {
+        let Some(arrivals) = self.arrivals.take() else {
+            return;
+        };
+        self.preroll = adapt(
+            self.preroll,
+            arrivals.shortfall + PREROLL_MARGIN,
+            MIN_PREROLL,
+            MAX_PREROLL,
+        );
+        if let Some(gap) = arrivals.max_gap {
+            self.drain_timeout = adapt(
+                self.drain_timeout,
+                gap * 3 / 2,
+                MIN_DRAIN_TIMEOUT,
+                MAX_DRAIN_TIMEOUT,
+            );
+        }
+    }
+
+    /// Audio to buffer before playing.
+    pub fn preroll(&self) -> Duration {
+        self.preroll
+    }
+
+    /// Silence after which playback counts as drained.
+    pub fn drain_timeout(&self) -> Duration {
+        self.drain_timeout
+    }
+
+    /// Time since the playback's first arrival.
+    pub fn waited(&self, now: Instant) -> Duration {
+        self.arrivals
+            .as_ref()
+            .map_or(Duration::ZERO, |a| now.saturating_duration_since(a.first))
+    }
+}
+
+fn adapt(current: Duration, observed: Duration, min: Duration, max: Duration) -> Duration {
+    let next = if observed > current {
+        observed
+    } else {
+        current * 3 / 4 + observed / 4
+    };
+    next.clamp(min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// 20ms frames arriving `interval` apart, then the playback ends.
+    fn playback(jitter: &mut JitterEstimator, frames: u32, interval: Duration) {
+        let start = Instant::now();
+        for i in 0..frames {
+            jitter.arrival(start + interval * i, ms(20));
+        }
+        jitter.end_playback();
+    }
+
+    #[test]
+    fn a_slow_host_raises_the_preroll_at_once() {
+        let mut jitter = JitterEstimator::default();
+        // Frames come 30ms apart but play for 20ms: 10 frames fall 90ms behind
+        playback(&mut jitter, 10, ms(30));
+        assert_eq!(jitter.preroll(), ms(110));
+    }
+
+    #[test]
+    fn a_fast_host_lowers_the_preroll_slowly() {
+        let mut jitter = JitterEstimator::default();
+        playback(&mut jitter, 10, ms(5));
+        assert_eq!(jitter.preroll(), ms(80));
+        for _ in 0..20 {
+            playback(&mut jitter, 10, ms(5));
+        }
+        assert_eq!(jitter.preroll(), MIN_PREROLL);
+    }
+
+    #[test]
+    fn drain_timeout_follows_the_longest_gap() {
+        let mut jitter = JitterEstimator::default();
+        let start = Instant::now();
+        jitter.arrival(start, ms(2000));
+        jitter.arrival(start + ms(1200), ms(2000));
+        jitter.end_playback();
+        assert_eq!(jitter.drain_timeout(), ms(1800));
+        assert_eq!(jitter.preroll(), ms(80));
+
+        for _ in 0..30 {
+            playback(&mut jitter, 10, ms(5));
+        }
+        assert_eq!(jitter.drain_timeout(), MIN_DRAIN_TIMEOUT);
+    }
+
+    #[test]
+    fn waited_counts_from_the_first_arrival() {
+        let mut jitter = JitterEstimator::default();
+        let start = Instant::now();
+        assert_eq!(jitter.waited(start), Duration::ZERO);
+        jitter.arrival(start, ms(20));
+        assert_eq!(jitter.waited(start + ms(70)), ms(70));
+        jitter.end_playback();
+        assert_eq!(jitter.waited(start + ms(70)), Duration::ZERO);
+    }
+}
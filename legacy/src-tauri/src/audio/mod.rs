@@ -1,5 +1,19 @@
+pub mod asr_protocol;
 pub mod asr_session;
 pub mod capture;
+pub mod earcon;
+pub mod jitter;
+pub mod output_device;
 pub mod playback;
+pub mod playback_queue;
+pub mod replay;
+pub mod service;
+pub mod spoken;
 pub mod state;
+pub mod time_stretch;
+pub mod tts_audio;
+pub mod tts_decoder;
+pub mod utterance;
+pub mod utterance_filter;
 pub mod vad;
+pub mod vocabulary;
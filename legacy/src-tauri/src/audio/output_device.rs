@@ -0,0 +1,139 @@
+//! Which speaker replies are played on.
+//!
+//! The user's choice is remembered by device name in the app config dir.
+//! With no choice, or when the chosen device is not connected, playback
+//! uses the system default.
+
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::Device;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+pub const OUTPUT_DEVICE_FILE: &str = "output_device.json";
+
+#[derive(Default, Serialize, Deserialize)]
+struct OutputDeviceFile {
+    #[serde(default)]
+    device: Option<String>,
+}
+
+pub struct OutputDevicePreference {
+    path: Option<PathBuf>,
+    device: Option<String>,
+}
+
+impl OutputDevicePreference {
+    /// Load from `path`; a missing or unreadable file means the default.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let device = path
+            .as_ref()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|raw| serde_json::from_str::<OutputDeviceFile>(&raw).ok())
+            .and_then(|file| file.device);
+
+        Self { path, device }
+    }
+
+    /// The chosen device, or None for the system default.
+    pub fn device(&self) -> Option<&str> {
+        self.device.as_deref()
+    }
+
+    /// Remember `device`; it takes effect only once saved.
+    pub fn set(&mut self, device: Option<String>) -> Result<(), String> {
+        self.save(&device)?;
+        self.device = device;
+        Ok(())
+    }
+
+    fn save(&self, device: &Option<String>) -> Result<(), String> {
+        let Some(path) = self.path.as_ref() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create config dir: {}", e))?;
+        }
+        let file = OutputDeviceFile {
+            device: device.clone(),
+        };
+        let json = serde_json::to_string_pretty(&file)
+            .map_err(|e| format!("Failed to encode output device: {}", e))?;
+        std::fs::write(path, json).map_err(|e| format!("Failed to save output device: {}", e))
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OutputDeviceInfo {
+    pub name: String,
+    pub is_default: bool,
+    /// Chosen by the user.
+    pub selected: bool,
+}
+
+/// The connected output devices.
+pub fn list(preferred: Option<&str>) -> Result<Vec<OutputDeviceInfo>, String> {
+    let host = cpal::default_host();
+    let default_name = host.default_output_device().and_then(|d| d.name().ok());
+    let devices = host
+        .output_devices()
+        .map_err(|e| format!("Failed to list output devices: {}", e))?;
+
+    Ok(devices
+        .filter_map(|device| device.name().ok())
+        .map(|name| OutputDeviceInfo {
+            is_default: default_name.as_deref() == Some(name.as_str()),
+            selected: preferred == Some(name.as_str()),
+            name,
+        })
+        .collect())
+}
+
+pub fn find(name: &str) -> Option<Device> {
+    cpal::default_host()
+        .output_devices()
+        .ok()?
+        .find(|device| device.name().is_ok_and(|n| n == name))
+}
+
+/// The chosen device if it is connected, otherwise the default.
+pub fn resolve(preferred: Option<&str>) -> Result<Device, String> {
+    if let Some(name) = preferred {
+        if let Some(device) = find(name) {
+            return Ok(device);
+        }
+        eprintln!("Output device {} not found, using the default", name);
+    }
+    cpal::default_host()
+        .default_output_device()
+        .ok_or_else(|| "No default output device found".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preference_persists() {
+        let path = std::env::temp_dir().join(format!(
+            "sisyphus-output-device-test-{}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let mut preference = OutputDevicePreference::load(Some(path.clone()));
+        assert_eq!(preference.device(), None);
+
+        preference.set(Some("Headset".to_string())).unwrap();
+        let reloaded = OutputDevicePreference::load(Some(path.clone()));
+        assert_eq!(reloaded.device(), Some("Headset"));
+
+        let mut reloaded = reloaded;
+        reloaded.set(None).unwrap();
+        assert_eq!(
+            OutputDevicePreference::load(Some(path.clone())).device(),
+            None
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+}
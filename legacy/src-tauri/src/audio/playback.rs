@@ -1,35 +1,48 @@
+//! TTS playback.
+//!
+//! A dedicated engine thread owns the one output stream, as capture does;
+//! cpal streams are !Send. The `Playback` handle queues audio into a shared
+//! `PlaybackQueue` and tells the engine to open the stream when playback
+//! starts. When the output callback finds the queue drained it tells the
+//! engine, which releases the stream and ends the playback. Earcons (see
+//! `earcon`) are mixed in by the same callback and keep the stream open
+//! until they have played.
+//!
+//! Replies' audio is also kept after it is played (see `replay`), so a
+//! reply can be heard again, or a sentence of it, without asking TTS.
+//!
+//! The stream plays on the device the user chose (see `output_device`).
+//! When that device goes away mid-reply, the engine moves the stream to the
+//! new default and playback carries on.
+
 use anyhow::Result;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::traits::{DeviceTrait, StreamTrait};
 use cpal::{Device, SampleRate, SupportedStreamConfigRange};
-use std::collections::VecDeque;
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex, OnceLock};
-use tauri::Emitter;
-
-const SOURCE_SAMPLE_RATE: u32 = 16000; // TTS output is 16kHz mono
-const JITTER_BUFFER_FRAMES: usize = 5;
-const DRAIN_TIMEOUT_CALLBACKS: u32 = 50; // Wait ~50 callbacks (~1 sec) before stopping
-
-static PLAYING: AtomicBool = AtomicBool::new(false);
-// Barge-in pause: output silence but keep the queue cached for resume.
-static PAUSED: AtomicBool = AtomicBool::new(false);
-static STREAM_ACTIVE: AtomicBool = AtomicBool::new(false);
-static AUDIO_QUEUE: OnceLock<Arc<Mutex<VecDeque<Vec<u8>>>>> = OnceLock::new();
-static APP_HANDLE: OnceLock<Arc<Mutex<Option<tauri::AppHandle>>>> = OnceLock::new();
-static PLAYBACK_COMPLETE_FLAG: AtomicBool = AtomicBool::new(false);
-static DRAIN_COUNTER: OnceLock<Arc<Mutex<u32>>> = OnceLock::new();
-
-fn get_drain_counter() -> &'static Arc<Mutex<u32>> {
-    DRAIN_COUNTER.get_or_init(|| Arc::new(Mutex::new(0)))
-}
-
-fn get_queue() -> &'static Arc<Mutex<VecDeque<Vec<u8>>>> {
-    AUDIO_QUEUE.get_or_init(|| Arc::new(Mutex::new(VecDeque::new())))
-}
-
-fn get_app_handle() -> &'static Arc<Mutex<Option<tauri::AppHandle>>> {
-    APP_HANDLE.get_or_init(|| Arc::new(Mutex::new(None)))
-}
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use tauri::Manager;
+
+use crate::audio::earcon::{Earcon, EarconSetting};
+use crate::audio::output_device::{self, OutputDeviceInfo, OutputDevicePreference};
+use crate::audio::playback_queue::{PlaybackQueue, PlaybackStats, QueuedAudio};
+use crate::audio::replay::ReplayHistory;
+use crate::audio::service::VoiceService;
+use crate::audio::spoken::SpokenReply;
+use crate::audio::state::{emit_error, emit_warning};
+use crate::audio::time_stretch::{MAX_RATE, MIN_RATE};
+use crate::audio::tts_audio::{AudioFormat, SampleEncoding};
+use crate::audio::tts_decoder::{self, DecodedAudio, StreamDecoder};
+use crate::conversation::TurnOrchestrator;
+use crate::events::{
+    self, PlaybackEnded, PlaybackFlushed, PlaybackPaused, PlaybackProgress, PlaybackResumed,
+    PlaybackStarted,
+};
+
+/// Rewinding this soon after a sentence started goes to the one before.
+const REWIND_GRACE: Duration = Duration::from_secs(1);
 
 pub struct AudioPlayback;
 
@@ -40,16 +53,6 @@ struct PlaybackConfig {
 }
 
 impl AudioPlayback {
-    fn get_default_output_device() -> Result<Device> {
-        let host = cpal::default_host();
-
-        let default_device = host
-            .default_output_device()
-            .ok_or_else(|| anyhow::anyhow!("No default output device found"))?;
-
-        Ok(default_device)
-    }
-
     /// Find the best supported output configuration
     fn get_supported_config(device: &Device) -> Result<PlaybackConfig> {
         let supported_configs: Vec<SupportedStreamConfigRange> = device
@@ -114,128 +117,552 @@ impl AudioPlayback {
             channels: best_config.channels(),
         })
     }
+}
 
-    pub fn is_playing() -> bool {
-        PLAYING.load(Ordering::SeqCst)
-    }
+/// What the engine thread is told.
+enum Command {
+    /// Playback started: open the output stream if it isn't.
+    Open,
+    /// The output callback ran out of audio.
+    Drained,
+    /// Playback was stopped: release the stream.
+    Close,
+    /// The user chose another device: move an open stream there.
+    SwitchDevice,
+    /// The stream reported an error; its device may be gone.
+    StreamError,
+    /// The output callback has progress to report.
+    Progress(PlaybackProgress),
+    /// The earcons have played and nothing else is.
+    Quiet,
+    Shutdown,
 }
 
-/// True when TTS audio is actually coming out of the speakers right now
-/// (used by the VAD to raise its trigger threshold against self-echo).
-pub fn is_audibly_playing() -> bool {
-    PLAYING.load(Ordering::SeqCst) && !PAUSED.load(Ordering::SeqCst)
+/// TTS output, owned by the `VoiceService`.
+pub struct Playback {
+    /// Shared with the output callback.
+    queue: Arc<Mutex<PlaybackQueue>>,
+    /// The engine thread, started when first needed.
+    engine: Mutex<Option<mpsc::Sender<Command>>>,
+    /// Shared with the engine thread.
+    preference: Arc<Mutex<OutputDevicePreference>>,
+    /// Duck rather than pause while the user speaks.
+    ducking: AtomicBool,
+    history: Mutex<ReplayHistory>,
+    earcons: Mutex<BTreeMap<Earcon, EarconSetting>>,
 }
 
-/// Resample from 16kHz mono to target rate and channels
-fn resample_for_playback(input: &[i16], target_rate: u32, target_channels: u16) -> Vec<f32> {
-    // First, convert to f32
-    let mono_f32: Vec<f32> = input.iter().map(|&s| s as f32 / 32768.0).collect();
+#[derive(Clone, Debug, Serialize)]
+pub struct PlaybackSettings {
+    pub volume: f32,
+    pub ducking: bool,
+    pub rate: f32,
+}
 
-    // Resample if needed
-    let resampled = if target_rate != SOURCE_SAMPLE_RATE {
-        let ratio = target_rate as f64 / SOURCE_SAMPLE_RATE as f64;
-        let output_len = (mono_f32.len() as f64 * ratio).ceil() as usize;
-        let mut output = Vec::with_capacity(output_len);
+impl Playback {
+    pub fn new(preference: OutputDevicePreference) -> Self {
+        Self {
+            queue: Arc::new(Mutex::new(PlaybackQueue::new())),
+            engine: Mutex::new(None),
+            preference: Arc::new(Mutex::new(preference)),
+            ducking: AtomicBool::new(false),
+            history: Mutex::new(ReplayHistory::default()),
+            earcons: Mutex::new(
+                Earcon::ALL
+                    .into_iter()
+                    .map(|earcon| (earcon, EarconSetting::default()))
+                    .collect(),
+            ),
+        }
+    }
 
-        for i in 0..output_len {
-            let src_idx = i as f64 / ratio;
-            let src_idx_floor = src_idx.floor() as usize;
-            let frac = (src_idx - src_idx_floor as f64) as f32;
+    pub fn is_playing(&self) -> bool {
+        self.queue.lock().unwrap().is_playing()
+    }
 
-            let sample = if src_idx_floor + 1 < mono_f32.len() {
-                mono_f32[src_idx_floor] * (1.0 - frac) + mono_f32[src_idx_floor + 1] * frac
-            } else if src_idx_floor < mono_f32.len() {
-                mono_f32[src_idx_floor]
+    /// True when TTS audio or an earcon is actually coming out of the
+    /// speakers right now (used by the VAD to raise its trigger threshold
+    /// against self-echo).
+    pub fn is_audibly_playing(&self) -> bool {
+        let queue = self.queue.lock().unwrap();
+        queue.is_audible() || queue.is_earcon_playing()
+    }
+
+    /// Audio is playing, paused, or queued waiting for a stream.
+    pub fn has_pending_audio(&self) -> bool {
+        self.queue.lock().unwrap().has_pending()
+    }
+
+    pub fn queue_audio(&self, app: &tauri::AppHandle, audio_data: Vec<u8>, format: AudioFormat) {
+        self.enqueue(app, &audio_data, format, None);
+    }
+
+    /// Queue a frame of a reply; `text_id` is the chunk it speaks, if the TTS
+    /// server said. Played frames are counted on `reply`.
+    pub fn queue_reply_audio(
+        &self,
+        app: &tauri::AppHandle,
+        audio_data: Vec<u8>,
+        format: AudioFormat,
+        reply: &Arc<Mutex<SpokenReply>>,
+        text_id: Option<usize>,
+    ) {
+        reply.lock().unwrap().add_queued(text_id, audio_data.len());
+        self.enqueue(app, &audio_data, format, Some((reply.clone(), text_id)));
+    }
+
+    /// Decode a reply's compressed audio as it arrives, see `ReplyDecoder`.
+    pub fn reply_decoder(
+        self: &Arc<Self>,
+        app: &tauri::AppHandle,
+        reply: &Arc<Mutex<SpokenReply>>,
+    ) -> ReplyDecoder {
+        let playback = self.clone();
+        let (audio_app, error_app) = (app.clone(), app.clone());
+        let on_reply = reply.clone();
+        let decoder = StreamDecoder::spawn(
+            move |text_id: &Option<usize>, audio| {
+                playback.push(&audio_app, audio, Some((on_reply.clone(), *text_id)));
+            },
+            move |_, e| {
+                eprintln!("{}", e);
+                emit_warning(&error_app, "TTS_DECODE_FAILED", &e);
+            },
+        );
+        ReplyDecoder {
+            decoder,
+            reply: reply.clone(),
+            text_id: None,
+        }
+    }
+
+    fn enqueue(
+        &self,
+        app: &tauri::AppHandle,
+        audio_data: &[u8],
+        format: AudioFormat,
+        reply: Option<(Arc<Mutex<SpokenReply>>, Option<usize>)>,
+    ) {
+        if !format.encoding.is_pcm() {
+            // A complete compressed file
+            let result = tts_decoder::decode(audio_data.to_vec(), format.encoding, |audio| {
+                self.push(app, audio, reply.clone())
+            });
+            if let Err(e) = result {
+                eprintln!("{}", e);
+                emit_warning(app, "TTS_DECODE_FAILED", &e);
+            }
+            return;
+        }
+
+        let audio = DecodedAudio {
+            samples: format.decode(audio_data),
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+            bytes: audio_data.len(),
+        };
+        self.push(app, audio, reply);
+    }
+
+    fn push(
+        &self,
+        app: &tauri::AppHandle,
+        audio: DecodedAudio,
+        reply: Option<(Arc<Mutex<SpokenReply>>, Option<usize>)>,
+    ) {
+        // Bytes with no sound in them (file headers, a stream's remainder)
+        // are done with as soon as they arrive
+        if audio.samples.is_empty() {
+            if let Some((reply, text_id)) = reply {
+                reply.lock().unwrap().add_played(text_id, audio.bytes);
+            }
+            return;
+        }
+        let audio = QueuedAudio {
+            samples: audio.samples,
+            sample_rate: audio.sample_rate,
+            channels: audio.channels,
+            bytes: audio.bytes,
+            reply,
+            counted: Default::default(),
+        };
+        self.history.lock().unwrap().record(&audio);
+        let started = self.queue.lock().unwrap().push(audio);
+        if started {
+            self.open(app);
+        }
+    }
+
+    /// Play what is queued without waiting for the jitter buffer.
+    pub fn start(&self, app: &tauri::AppHandle) {
+        self.queue.lock().unwrap().start();
+        self.open(app);
+    }
+
+    /// The reply's audio has all arrived: play what the jitter buffer holds
+    /// rather than wait for it to fill.
+    pub fn play_buffered(&self, app: &tauri::AppHandle) {
+        if self.queue.lock().unwrap().play_buffered() {
+            self.open(app);
+        }
+    }
+
+    /// Play the latest reply again from the start.
+    pub fn replay_last_reply(&self, app: &tauri::AppHandle) -> Result<(), String> {
+        let frames = self.history.lock().unwrap().last_reply();
+        if frames.is_empty() {
+            return Err("No reply to replay".to_string());
+        }
+        {
+            let mut queue = self.queue.lock().unwrap();
+            queue.replace(frames);
+            queue.unduck();
+            queue.resume();
+            queue.start();
+        }
+        self.open(app);
+        Ok(())
+    }
+
+    /// Jump past the rest of the sentence being spoken. Returns false when
+    /// no reply is playing.
+    pub fn skip_sentence(&self) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        let Some((reply, text_id)) = queue.current_chunk() else {
+            return false;
+        };
+        let next = reply.lock().unwrap().sentence_of(text_id).end;
+        queue.skip_to(next)
+    }
+
+    /// Play the sentence being spoken again from its start, or the one
+    /// before when it has only just started. Returns false when no reply is
+    /// playing.
+    pub fn rewind_sentence(&self) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        let Some((reply, text_id)) = queue.current_chunk() else {
+            return false;
+        };
+        let start = {
+            let spoken = reply.lock().unwrap();
+            let sentence = spoken.sentence_of(text_id);
+            let just_started = queue
+                .since_chunk_started(sentence.start)
+                .is_none_or(|played| played < REWIND_GRACE);
+            if just_started && sentence.start > 0 {
+                spoken.sentence_of(sentence.start - 1).start
             } else {
-                0.0
-            };
+                sentence.start
+            }
+        };
+        let frames = self.history.lock().unwrap().reply_from(&reply, start);
+        if frames.is_empty() {
+            return false;
+        }
+        queue.replace(frames);
+        true
+    }
+
+    pub fn stats(&self) -> PlaybackStats {
+        self.queue.lock().unwrap().stats()
+    }
 
-            output.push(sample);
+    pub fn settings(&self) -> PlaybackSettings {
+        let queue = self.queue.lock().unwrap();
+        PlaybackSettings {
+            volume: queue.volume(),
+            ducking: self.ducking.load(Ordering::SeqCst),
+            rate: queue.rate(),
         }
-        output
-    } else {
-        mono_f32
-    };
+    }
 
-    // Convert to stereo if needed
-    if target_channels == 2 {
-        resampled
-            .iter()
-            .flat_map(|&s| [s, s])
-            .collect()
-    } else {
-        resampled
+    pub fn set_volume(&self, volume: f32) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&volume) {
+            return Err(format!("Volume must be between 0 and 1, got {}", volume));
+        }
+        self.queue.lock().unwrap().set_volume(volume);
+        Ok(())
     }
-}
 
-fn bytes_to_i16_samples(bytes: &[u8]) -> Vec<i16> {
-    bytes
-        .chunks_exact(2)
-        .map(|chunk| i16::from_le_bytes([chunk[0], chunk[1]]))
-        .collect()
-}
+    pub fn set_rate(&self, rate: f32) -> Result<(), String> {
+        if !(MIN_RATE..=MAX_RATE).contains(&rate) {
+            return Err(format!(
+                "Playback rate must be between {} and {}, got {}",
+                MIN_RATE, MAX_RATE, rate
+            ));
+        }
+        self.queue.lock().unwrap().set_rate(rate);
+        Ok(())
+    }
 
-static DEBUG_FRAME_COUNT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    pub fn set_ducking(&self, enabled: bool) {
+        self.ducking.store(enabled, Ordering::SeqCst);
+    }
 
-#[tauri::command]
-pub fn queue_playback_audio(audio_data: Vec<u8>) -> Result<(), String> {
-    // Debug: print info for first few frames
-    let frame_num = DEBUG_FRAME_COUNT.fetch_add(1, Ordering::SeqCst);
-    if frame_num < 3 {
-        // Show first few samples as i16
-        let samples = bytes_to_i16_samples(&audio_data[..audio_data.len().min(20)]);
-        println!(
-            "TTS frame {}: {} bytes, first samples: {:?}",
-            frame_num,
-            audio_data.len(),
-            &samples[..samples.len().min(5)]
-        );
+    /// Play a cue over whatever is playing, unless the user turned it off.
+    pub fn play_earcon(&self, app: &tauri::AppHandle, earcon: Earcon) {
+        let setting = self.earcons.lock().unwrap()[&earcon];
+        if !setting.enabled || setting.volume == 0.0 {
+            return;
+        }
+        self.queue
+            .lock()
+            .unwrap()
+            .play_earcon(earcon, setting.volume);
+        self.open(app);
     }
 
-    // Reset drain counter when new audio arrives
-    {
-        let mut counter = get_drain_counter().lock().unwrap();
-        *counter = 0;
+    pub fn earcon_settings(&self) -> BTreeMap<Earcon, EarconSetting> {
+        self.earcons.lock().unwrap().clone()
     }
 
-    let queue = get_queue();
-    let mut q = queue.lock().unwrap();
-    q.push_back(audio_data);
+    pub fn set_earcon_enabled(&self, earcon: Earcon, enabled: bool) {
+        let mut earcons = self.earcons.lock().unwrap();
+        earcons.entry(earcon).or_default().enabled = enabled;
+    }
 
-    // Only start if we have enough frames AND no stream is active yet.
-    // While paused (barge-in) just keep caching; resume will start playback.
-    let should_start = q.len() >= JITTER_BUFFER_FRAMES
-        && !STREAM_ACTIVE.load(Ordering::SeqCst)
-        && !PAUSED.load(Ordering::SeqCst);
-    drop(q);
+    pub fn set_earcon_volume(&self, earcon: Earcon, volume: f32) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&volume) {
+            return Err(format!("Volume must be between 0 and 1, got {}", volume));
+        }
+        let mut earcons = self.earcons.lock().unwrap();
+        earcons.entry(earcon).or_default().volume = volume;
+        Ok(())
+    }
 
-    if should_start {
-        start_playback_internal()?;
+    /// Pause playback for barge-in: audio keeps arriving from TTS and is
+    /// cached in the queue; the output callback plays silence until resume.
+    pub fn pause(&self, app: &tauri::AppHandle) {
+        if self.queue.lock().unwrap().pause() {
+            events::emit(app, PlaybackPaused {});
+        }
     }
 
-    Ok(())
+    /// The user started speaking over the reply: duck it in ducking mode,
+    /// otherwise pause it.
+    pub fn yield_to_speech(&self, app: &tauri::AppHandle) {
+        if self.ducking.load(Ordering::SeqCst) {
+            self.queue.lock().unwrap().duck();
+        } else {
+            self.pause(app);
+        }
+    }
+
+    /// Resume playback from the cached queue after barge-in, or restore
+    /// the volume of a ducked reply.
+    pub fn resume(&self, app: &tauri::AppHandle) {
+        let playing = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.unduck();
+            if !queue.resume() {
+                return;
+            }
+            queue.is_playing()
+        };
+
+        events::emit(app, PlaybackResumed {});
+
+        // If audio was cached while no stream was running, start one now
+        if playing {
+            self.open(app);
+        }
+    }
+
+    /// Drop all queued audio after an interrupted reply. The output stream
+    /// runs dry and completes as usual; a paused stream is released first.
+    pub fn flush(&self, app: &tauri::AppHandle) {
+        self.queue.lock().unwrap().flush();
+        events::emit(app, PlaybackFlushed {});
+    }
+
+    pub fn stop(&self, app: &tauri::AppHandle) {
+        self.queue.lock().unwrap().stop();
+        self.notify(Command::Close);
+        playback_ended(app, false);
+    }
+
+    pub fn output_devices(&self) -> Result<Vec<OutputDeviceInfo>, String> {
+        output_device::list(self.preference.lock().unwrap().device())
+    }
+
+    /// Play on `device` from now on, or on the system default for None.
+    /// A reply that is playing moves over at once.
+    pub fn set_output_device(&self, device: Option<String>) -> Result<(), String> {
+        if let Some(name) = device.as_deref() {
+            if output_device::find(name).is_none() {
+                return Err(format!("Output device not found: {}", name));
+            }
+        }
+        self.preference.lock().unwrap().set(device)?;
+        self.notify(Command::SwitchDevice);
+        Ok(())
+    }
+
+    /// Release the output stream and end the engine thread.
+    pub fn shutdown(&self) {
+        if let Some(engine) = self.engine.lock().unwrap().take() {
+            let _ = engine.send(Command::Shutdown);
+        }
+    }
+
+    /// Have the engine open its stream, starting the engine if needed.
+    fn open(&self, app: &tauri::AppHandle) {
+        let mut engine = self.engine.lock().unwrap();
+        if let Some(tx) = engine.as_ref() {
+            if tx.send(Command::Open).is_ok() {
+                return;
+            }
+        }
+        let tx = spawn_engine(app.clone(), self.queue.clone(), self.preference.clone());
+        let _ = tx.send(Command::Open);
+        *engine = Some(tx);
+    }
+
+    fn notify(&self, command: Command) {
+        if let Some(tx) = self.engine.lock().unwrap().as_ref() {
+            let _ = tx.send(command);
+        }
+    }
 }
 
-fn start_playback_internal() -> Result<(), String> {
-    // Check if stream is already active (not just playing)
-    if STREAM_ACTIVE.load(Ordering::SeqCst) {
-        return Ok(());
+impl Drop for Playback {
+    fn drop(&mut self) {
+        self.shutdown();
     }
+}
+
+/// A reply's compressed audio from one TTS connection: after each
+/// `audio_start`, the chunk's file arrives in pieces and is decoded in
+/// order on a thread of its own. Dropping it discards what is still being
+/// decoded.
+pub struct ReplyDecoder {
+    decoder: StreamDecoder<Option<usize>>,
+    reply: Arc<Mutex<SpokenReply>>,
+    text_id: Option<usize>,
+}
+
+impl ReplyDecoder {
+    /// The pieces pushed next are the file of chunk `text_id`.
+    pub fn start(&mut self, encoding: SampleEncoding, text_id: Option<usize>) {
+        self.text_id = text_id;
+        self.decoder.start(encoding, text_id);
+    }
+
+    pub fn push(&self, data: Vec<u8>) {
+        self.reply
+            .lock()
+            .unwrap()
+            .add_queued(self.text_id, data.len());
+        self.decoder.push(data);
+    }
+
+    /// Wait until everything pushed is decoded and queued.
+    pub async fn finish(&mut self) {
+        self.decoder.finish().await;
+    }
+}
+
+fn spawn_engine(
+    app: tauri::AppHandle,
+    queue: Arc<Mutex<PlaybackQueue>>,
+    preference: Arc<Mutex<OutputDevicePreference>>,
+) -> mpsc::Sender<Command> {
+    let (tx, rx) = mpsc::channel();
+    let callback_tx = tx.clone();
+
+    std::thread::spawn(move || {
+        let mut stream: Option<OutputStream> = None;
+        let open = |stream: &mut Option<OutputStream>| {
+            let preferred = preference.lock().unwrap().device().map(str::to_string);
+            match open_stream(&queue, &callback_tx, preferred.as_deref()) {
+                Ok(s) => *stream = Some(s),
+                Err(e) => {
+                    eprintln!("Failed to start playback: {}", e);
+                    emit_error(&app, "PLAYBACK_FAILED", &e);
+                    queue.lock().unwrap().stop();
+                    playback_ended(&app, false);
+                }
+            }
+        };
+
+        for command in rx {
+            match command {
+                Command::Open => {
+                    if stream.is_none() {
+                        open(&mut stream);
+                    }
+                }
+                Command::Drained => {
+                    // New audio may have restarted playback in the meantime
+                    let (playing, active) = {
+                        let queue = queue.lock().unwrap();
+                        (queue.is_playing(), queue.is_active())
+                    };
+                    if stream.is_some() && !playing {
+                        if !active {
+                            stream = None;
+                        }
+                        playback_ended(&app, true);
+                    }
+                }
+                Command::Close | Command::Quiet => {
+                    if !queue.lock().unwrap().is_active() {
+                        stream = None;
+                    }
+                }
+                Command::SwitchDevice => {
+                    if stream.take().is_some() && queue.lock().unwrap().is_active() {
+                        open(&mut stream);
+                    }
+                }
+                Command::Progress(progress) => events::emit(&app, progress),
+                Command::StreamError => {
+                    let lost = stream
+                        .as_ref()
+                        .is_some_and(|s| output_device::find(&s.device).is_none());
+                    if !lost {
+                        continue;
+                    }
+                    let device = stream.take().map(|s| s.device).unwrap_or_default();
+                    eprintln!("Output device {} disconnected", device);
+                    emit_warning(
+                        &app,
+                        "OUTPUT_DEVICE_LOST",
+                        "Output device disconnected; playing on the default device",
+                    );
+                    if queue.lock().unwrap().is_active() {
+                        open(&mut stream);
+                    }
+                }
+                Command::Shutdown => break,
+            }
+        }
+    });
+
+    tx
+}
 
-    STREAM_ACTIVE.store(true, Ordering::SeqCst);
-    PLAYING.store(true, Ordering::SeqCst);
-    PLAYBACK_COMPLETE_FLAG.store(false, Ordering::SeqCst);
+/// An open output stream and the device it plays on.
+struct OutputStream {
+    _stream: cpal::Stream,
+    device: String,
+}
 
-    let device =
-        AudioPlayback::get_default_output_device().map_err(|e| format!("Device error: {}", e))?;
+fn open_stream(
+    queue: &Arc<Mutex<PlaybackQueue>>,
+    engine: &mpsc::Sender<Command>,
+    preferred: Option<&str>,
+) -> Result<OutputStream, String> {
+    let device = output_device::resolve(preferred).map_err(|e| format!("Device error: {}", e))?;
+    let device_name = device.name().unwrap_or_default();
 
     // Get supported configuration
     let playback_config = AudioPlayback::get_supported_config(&device)
         .map_err(|e| format!("Config error: {}", e))?;
 
     println!(
-        "Audio playback config: {}Hz, {} channels",
-        playback_config.sample_rate, playback_config.channels
+        "Audio playback on {}: {}Hz, {} channels",
+        device_name, playback_config.sample_rate, playback_config.channels
     );
 
     let config = cpal::StreamConfig {
@@ -246,87 +673,36 @@ fn start_playback_internal() -> Result<(), String> {
 
     let target_rate = playback_config.sample_rate;
     let target_channels = playback_config.channels;
+    let queue = queue.clone();
+    let notify = engine.clone();
+    let errors = engine.clone();
 
-    let queue = get_queue().clone();
-    let drain_counter = get_drain_counter().clone();
-
-    // Buffer for resampled audio
-    let resampled_buffer: Arc<Mutex<VecDeque<f32>>> = Arc::new(Mutex::new(VecDeque::new()));
-    let resampled_buffer_clone = resampled_buffer.clone();
+    // Samples rendered for a previous stream may not fit this one
+    queue.lock().unwrap().reset_output();
 
     let stream = device
         .build_output_stream(
             &config,
             move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                // Initialize all samples to silence
-                for sample in data.iter_mut() {
-                    *sample = 0.0;
-                }
-
-                if !PLAYING.load(Ordering::SeqCst) {
-                    return;
-                }
-
-                // Paused for barge-in: emit silence, keep the queue cached,
-                // and don't let the drain counter run down to "complete".
-                if PAUSED.load(Ordering::SeqCst) {
-                    return;
-                }
-
-                // First, try to use resampled buffer
-                let mut resampled = resampled_buffer_clone.lock().unwrap();
-                let mut output_idx = 0;
-                let mut had_audio = false;
-
-                // Drain from resampled buffer first
-                while !resampled.is_empty() && output_idx < data.len() {
-                    if let Some(sample) = resampled.pop_front() {
-                        data[output_idx] = sample;
-                        output_idx += 1;
-                        had_audio = true;
-                    }
+                let (drained, progress, quiet) = {
+                    let mut queue = queue.lock().unwrap();
+                    let drained = queue.render(data, target_rate, target_channels);
+                    let quiet = queue.mix_earcons(data, target_rate, target_channels);
+                    (drained, queue.progress(), quiet)
+                };
+                if let Some(progress) = progress {
+                    let _ = notify.send(Command::Progress(progress));
                 }
-
-                // If we need more samples, process from queue
-                let mut queue = queue.lock().unwrap();
-                while !queue.is_empty() && output_idx < data.len() {
-                    if let Some(audio_bytes) = queue.pop_front() {
-                        let samples = bytes_to_i16_samples(&audio_bytes);
-
-                        // Resample to target format
-                        let resampled_samples =
-                            resample_for_playback(&samples, target_rate, target_channels);
-
-                        for sample in resampled_samples {
-                            if output_idx < data.len() {
-                                data[output_idx] = sample;
-                                output_idx += 1;
-                                had_audio = true;
-                            } else {
-                                // Store remaining in buffer
-                                resampled.push_back(sample);
-                            }
-                        }
-                    }
+                if drained {
+                    let _ = notify.send(Command::Drained);
                 }
-
-                // Check if we've finished playing all audio
-                if queue.is_empty() && resampled.is_empty() && !had_audio {
-                    // Increment drain counter when no audio is available
-                    let mut counter = drain_counter.lock().unwrap();
-                    *counter += 1;
-
-                    // Only signal completion after timeout period
-                    if *counter >= DRAIN_TIMEOUT_CALLBACKS
-                        && PLAYING.load(Ordering::SeqCst)
-                        && !PLAYBACK_COMPLETE_FLAG.swap(true, Ordering::SeqCst)
-                    {
-                        PLAYING.store(false, Ordering::SeqCst);
-                    }
+                if quiet {
+                    let _ = notify.send(Command::Quiet);
                 }
             },
             move |err| {
                 eprintln!("Audio playback error: {}", err);
+                let _ = errors.send(Command::StreamError);
             },
             None,
         )
@@ -336,134 +712,182 @@ fn start_playback_internal() -> Result<(), String> {
         .play()
         .map_err(|e| format!("Failed to play stream: {}", e))?;
 
-    // Leak the stream to keep it alive
-    Box::leak(Box::new(stream));
-
-    // Start a monitoring task to emit events when playback completes
-    if let Some(app) = get_app_handle().lock().unwrap().as_ref() {
-        let app_clone = app.clone();
-        tauri::async_runtime::spawn(async move {
-            // Wait for playback to complete
-            loop {
-                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-
-                if PLAYBACK_COMPLETE_FLAG.load(Ordering::SeqCst) {
-                    // Reset stream active flag so new playback can start
-                    STREAM_ACTIVE.store(false, Ordering::SeqCst);
-
-                    // Emit playback ended and state change
-                    let _ = app_clone.emit("voice_assistant:playback_ended", ());
-                    let _ = app_clone.emit(
-                        "voice_assistant:state_changed",
-                        serde_json::json!({ "state": "Idle" }),
-                    );
-                    break;
-                }
+    Ok(OutputStream {
+        _stream: stream,
+        device: device_name,
+    })
+}
 
-                if !PLAYING.load(Ordering::SeqCst) && !STREAM_ACTIVE.load(Ordering::SeqCst) {
-                    break;
-                }
-            }
-        });
+/// Playback is over: its turn is too. `drained` when the reply was heard in
+/// full rather than stopped or failed.
+fn playback_ended(app: &tauri::AppHandle, drained: bool) {
+    if let Some(orchestrator) = app.try_state::<TurnOrchestrator>() {
+        orchestrator.playback_finished(app, drained);
     }
+    events::emit(app, PlaybackEnded {});
+}
 
+/// Queue audio from the webview; without a `format` it is 16kHz mono s16le.
+/// Compressed audio must be a complete file.
+#[tauri::command]
+pub fn queue_playback_audio(
+    app: tauri::AppHandle,
+    service: tauri::State<'_, Arc<VoiceService>>,
+    audio_data: Vec<u8>,
+    format: Option<AudioFormat>,
+) -> Result<(), String> {
+    service
+        .playback
+        .queue_audio(&app, audio_data, format.unwrap_or_default());
     Ok(())
 }
 
 #[tauri::command]
-pub fn start_playback(app: tauri::AppHandle) -> Result<(), String> {
-    // Store the app handle for later use
-    {
-        let mut handle = get_app_handle().lock().unwrap();
-        *handle = Some(app.clone());
-    }
-
-    start_playback_internal()?;
+pub fn start_playback(
+    app: tauri::AppHandle,
+    service: tauri::State<'_, Arc<VoiceService>>,
+) -> Result<(), String> {
+    service.playback.start(&app);
 
-    app.emit("voice_assistant:playback_started", ())
-        .map_err(|e| format!("Failed to emit event: {}", e))?;
+    events::emit(&app, PlaybackStarted {});
 
     Ok(())
 }
 
-/// Pause playback for barge-in: audio keeps arriving from TTS and is cached
-/// in the queue; the output callback plays silence until resume.
-pub fn pause_playback_internal(app: &tauri::AppHandle) {
-    let has_pending = STREAM_ACTIVE.load(Ordering::SeqCst)
-        || PLAYING.load(Ordering::SeqCst)
-        || !get_queue().lock().unwrap().is_empty();
+#[tauri::command]
+pub fn pause_playback(
+    app: tauri::AppHandle,
+    service: tauri::State<'_, Arc<VoiceService>>,
+) -> Result<(), String> {
+    service.playback.pause(&app);
+    Ok(())
+}
 
-    if !has_pending {
-        return;
-    }
+#[tauri::command]
+pub fn resume_playback(
+    app: tauri::AppHandle,
+    service: tauri::State<'_, Arc<VoiceService>>,
+) -> Result<(), String> {
+    service.playback.resume(&app);
+    Ok(())
+}
 
-    if !PAUSED.swap(true, Ordering::SeqCst) {
-        *get_drain_counter().lock().unwrap() = 0;
-        let _ = app.emit("voice_assistant:playback_paused", ());
-    }
+#[tauri::command]
+pub fn stop_playback(
+    app: tauri::AppHandle,
+    service: tauri::State<'_, Arc<VoiceService>>,
+) -> Result<(), String> {
+    service.playback.stop(&app);
+    Ok(())
 }
 
-/// Resume playback from the cached queue after barge-in.
-pub fn resume_playback_internal(app: &tauri::AppHandle) {
-    if !PAUSED.swap(false, Ordering::SeqCst) {
-        return;
-    }
+#[tauri::command]
+pub fn is_playback_active(service: tauri::State<'_, Arc<VoiceService>>) -> bool {
+    service.playback.is_playing()
+}
 
-    *get_drain_counter().lock().unwrap() = 0;
-    let _ = app.emit("voice_assistant:playback_resumed", ());
+/// Play the latest reply again from kept audio.
+#[tauri::command]
+pub fn replay_last_response(
+    app: tauri::AppHandle,
+    service: tauri::State<'_, Arc<VoiceService>>,
+) -> Result<(), String> {
+    service.playback.replay_last_reply(&app)?;
+    events::emit(&app, PlaybackStarted {});
+    Ok(())
+}
 
-    // If audio was cached while no stream was running, start one now
-    let has_audio = !get_queue().lock().unwrap().is_empty();
-    if has_audio && !STREAM_ACTIVE.load(Ordering::SeqCst) {
-        if let Err(e) = start_playback_internal() {
-            eprintln!("Failed to resume playback: {}", e);
-        }
-    }
+/// Whether each earcon plays, and how loud.
+#[tauri::command]
+pub fn get_earcon_settings(
+    service: tauri::State<'_, Arc<VoiceService>>,
+) -> BTreeMap<Earcon, EarconSetting> {
+    service.playback.earcon_settings()
 }
 
 #[tauri::command]
-pub fn pause_playback(app: tauri::AppHandle) -> Result<(), String> {
-    pause_playback_internal(&app);
+pub fn set_earcon_enabled(
+    service: tauri::State<'_, Arc<VoiceService>>,
+    earcon: Earcon,
+    enabled: bool,
+) -> Result<(), String> {
+    service.playback.set_earcon_enabled(earcon, enabled);
     Ok(())
 }
 
+/// Earcon volume, from 0 to 1.
 #[tauri::command]
-pub fn resume_playback(app: tauri::AppHandle) -> Result<(), String> {
-    resume_playback_internal(&app);
-    Ok(())
+pub fn set_earcon_volume(
+    service: tauri::State<'_, Arc<VoiceService>>,
+    earcon: Earcon,
+    volume: f32,
+) -> Result<(), String> {
+    service.playback.set_earcon_volume(earcon, volume)
 }
 
+/// Returns false when no reply is playing.
 #[tauri::command]
-pub fn stop_playback(app: tauri::AppHandle) -> Result<(), String> {
-    PLAYING.store(false, Ordering::SeqCst);
-    PAUSED.store(false, Ordering::SeqCst);
-    STREAM_ACTIVE.store(false, Ordering::SeqCst);
-    get_queue().lock().unwrap().clear();
+pub fn skip_to_next_sentence(service: tauri::State<'_, Arc<VoiceService>>) -> bool {
+    service.playback.skip_sentence()
+}
 
-    // Reset counters
-    *get_drain_counter().lock().unwrap() = 0;
-    DEBUG_FRAME_COUNT.store(0, Ordering::SeqCst);
+/// Returns false when no reply is playing.
+#[tauri::command]
+pub fn rewind_sentence(service: tauri::State<'_, Arc<VoiceService>>) -> bool {
+    service.playback.rewind_sentence()
+}
 
-    app.emit("voice_assistant:playback_ended", ())
-        .map_err(|e| format!("Failed to emit event: {}", e))?;
+#[tauri::command]
+pub fn get_playback_settings(service: tauri::State<'_, Arc<VoiceService>>) -> PlaybackSettings {
+    service.playback.settings()
+}
+
+/// TTS volume, from 0 to 1.
+#[tauri::command]
+pub fn set_playback_volume(
+    service: tauri::State<'_, Arc<VoiceService>>,
+    volume: f32,
+) -> Result<(), String> {
+    service.playback.set_volume(volume)
+}
 
-    app.emit(
-        "voice_assistant:state_changed",
-        serde_json::json!({ "state": "Idle" }),
-    )
-    .map_err(|e| format!("Failed to emit state: {}", e))?;
+/// Speak faster or slower at the same pitch, from 0.5x to 2x.
+#[tauri::command]
+pub fn set_playback_rate(
+    service: tauri::State<'_, Arc<VoiceService>>,
+    rate: f32,
+) -> Result<(), String> {
+    service.playback.set_rate(rate)
+}
 
+/// Lower the reply's volume while the user speaks instead of pausing it.
+#[tauri::command]
+pub fn set_playback_ducking(
+    service: tauri::State<'_, Arc<VoiceService>>,
+    enabled: bool,
+) -> Result<(), String> {
+    service.playback.set_ducking(enabled);
     Ok(())
 }
 
+/// Underruns and jitter buffer depth.
+#[tauri::command]
+pub fn get_playback_stats(service: tauri::State<'_, Arc<VoiceService>>) -> PlaybackStats {
+    service.playback.stats()
+}
+
 #[tauri::command]
-pub fn is_playback_active() -> bool {
-    AudioPlayback::is_playing()
+pub fn list_output_devices(
+    service: tauri::State<'_, Arc<VoiceService>>,
+) -> Result<Vec<OutputDeviceInfo>, String> {
+    service.playback.output_devices()
 }
 
-/// Initialize playback with app handle for event emission
-/// This should be called at app startup
-pub fn init_playback(app: tauri::AppHandle) {
-    let mut handle = get_app_handle().lock().unwrap();
-    *handle = Some(app);
+/// Choose the output device by name; None goes back to the system default.
+#[tauri::command]
+pub fn set_output_device(
+    service: tauri::State<'_, Arc<VoiceService>>,
+    name: Option<String>,
+) -> Result<(), String> {
+    service.playback.set_output_device(name)
 }
@@ -0,0 +1,1214 @@
+//! The TTS audio waiting to be played, and what the output callback makes
+//! of it.
+//!
+//! Frames are held back until the jitter buffer holds its pre-roll, then the
+//! callback renders them in order. The pre-roll, and the silence after which
+//! the queue reports itself drained (once), adapt to how the TTS host
+//! delivers (see `jitter`). Paused playback outputs silence and keeps its
+//! frames.
+//!
+//! Output goes through a gain: the user's volume, lowered while ducked under
+//! the user's speech. Gain changes, pause and resume ramp over FADE_MS so
+//! they never click; a pause keeps playing until it has faded out, and what
+//! it did not reach plays on resume.
+//!
+//! When the queue runs dry mid-reply the last few milliseconds are played
+//! back mirrored and fading out rather than cut off, and playback refills
+//! the pre-roll before fading back in; that is counted as an underrun.
+//!
+//! As it plays, the queue tracks which reply chunk is being spoken and how
+//! much has been played, for `PlaybackProgress`. Skipping ahead or jumping
+//! back to other audio fades out what was playing like an underrun, and
+//! fades the new audio in.
+//!
+//! Earcons are mixed over the output, whether or not TTS is playing.
+//!
+//! Frames keep the rate and channel count the TTS server sent them in; they
+//! are converted to the output stream's format as they are played, then
+//! sped up or slowed down to the playback rate (see `time_stretch`).
+
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::audio::earcon::{Earcon, EarconMixer};
+use crate::audio::jitter::JitterEstimator;
+use crate::audio::spoken::SpokenReply;
+use crate::audio::time_stretch::TimeStretcher;
+use crate::events::PlaybackProgress;
+
+/// Length of the concealment after an underrun, and of the fade back in.
+const CONCEAL_MS: u32 = 10;
+/// Length of the gain ramps.
+const FADE_MS: u32 = 10;
+/// Gain while ducked, about -10 dB.
+const DUCK_GAIN: f32 = 0.3;
+/// Progress is reported at least this often while playing.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A frame of TTS audio, and the reply chunk it speaks when known.
+#[derive(Clone)]
+pub struct QueuedAudio {
+    /// Interleaved, in [-1, 1].
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Size as received, which is what the reply's progress is counted in.
+    pub bytes: usize,
+    pub reply: Option<(Arc<Mutex<SpokenReply>>, Option<usize>)>,
+    /// Whether the bytes were counted on the reply. Shared with the copy
+    /// kept for replay, so a frame heard again is not counted again.
+    pub counted: Arc<AtomicBool>,
+}
+
+impl QueuedAudio {
+    fn duration(&self) -> Duration {
+        duration(self.samples.len(), self.sample_rate, self.channels)
+    }
+
+    fn text_id(&self) -> Option<usize> {
+        self.reply.as_ref().and_then(|(_, text_id)| *text_id)
+    }
+
+    fn count_played(&self) {
+        if self.counted.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if let Some((reply, text_id)) = &self.reply {
+            reply.lock().unwrap().add_played(*text_id, self.bytes);
+        }
+    }
+
+    /// Fade in over the first CONCEAL_MS.
+    fn fade_in(&mut self) {
+        let channels = self.channels.max(1) as usize;
+        let len = conceal_len(self.sample_rate, self.channels) / channels;
+        for (i, frame) in self.samples.chunks_mut(channels).take(len).enumerate() {
+            let gain = i as f32 / len as f32;
+            frame.iter_mut().for_each(|sample| *sample *= gain);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PlaybackStats {
+    /// Times playback ran dry mid-reply, since the app started.
+    pub underruns: u32,
+    /// Audio queued and not yet played.
+    pub buffered_ms: u32,
+    /// What the jitter buffer fills to before playing.
+    pub preroll_ms: u32,
+    /// Silence after which playback counts as finished.
+    pub drain_timeout_ms: u32,
+}
+
+#[derive(Default)]
+pub struct PlaybackQueue {
+    frames: VecDeque<QueuedAudio>,
+    /// Output samples of a frame that did not fit the last callback.
+    leftover: VecDeque<f32>,
+    playing: bool,
+    /// Barge-in pause: output silence but keep the frames for resume.
+    paused: bool,
+    /// Silence rendered since the queue last had audio.
+    idle: Duration,
+    resampler: Resampler,
+    stretcher: TimeStretcher,
+    jitter: JitterEstimator,
+    /// The output format, once rendered to.
+    output: Option<(u32, u16)>,
+    /// The last output samples, for concealment.
+    tail: VecDeque<f32>,
+    /// Concealment still to be played.
+    concealment: VecDeque<f32>,
+    /// Ran dry mid-playback; more audio makes it an underrun.
+    ran_dry: bool,
+    /// Refilling the pre-roll after an underrun, for this long so far.
+    rebuffering: Option<Duration>,
+    /// Output samples left to fade in.
+    fade_in: usize,
+    underruns: u32,
+    /// Audio played since playback started.
+    elapsed: Duration,
+    /// The reply and chunk of the frame being played.
+    reply: Option<Arc<Mutex<SpokenReply>>>,
+    text_id: Option<usize>,
+    /// When each chunk of this playback last started, in `elapsed`.
+    chunk_started: HashMap<usize, Duration>,
+    /// Skipping the reply's chunks before this one.
+    skip_before: Option<usize>,
+    /// The chunk and elapsed time last reported.
+    reported: Option<(Option<usize>, Duration)>,
+    gain: Gain,
+    earcons: EarconMixer,
+}
+
+impl PlaybackQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a frame. Returns true when this starts playback.
+    pub fn push(&mut self, audio: QueuedAudio) -> bool {
+        self.push_at(audio, Instant::now())
+    }
+
+    fn push_at(&mut self, audio: QueuedAudio, now: Instant) -> bool {
+        if let Some(next) = self.skip_before {
+            match audio.text_id() {
+                Some(text_id) if text_id < next => {
+                    audio.count_played();
+                    return false;
+                }
+                _ => self.skip_before = None,
+            }
+        }
+        let playing_time = self.playing_time(audio.duration());
+        self.jitter.arrival(now, playing_time);
+        self.frames.push_back(audio);
+        self.idle = Duration::ZERO;
+        if self.playing && self.ran_dry {
+            self.ran_dry = false;
+            self.underruns += 1;
+            self.rebuffering = Some(Duration::ZERO);
+        }
+
+        // While paused (barge-in) just keep caching; resume starts playback
+        if self.playing || self.paused {
+            return false;
+        }
+        let preroll = self.jitter.preroll();
+        if self.buffered() < preroll && self.jitter.waited(now) < preroll {
+            return false;
+        }
+        self.playing = true;
+        true
+    }
+
+    /// Play what is queued without waiting for the jitter buffer.
+    pub fn start(&mut self) {
+        self.playing = true;
+        self.idle = Duration::ZERO;
+    }
+
+    /// Start playing frames still short of the pre-roll. Returns true when
+    /// this starts playback.
+    pub fn play_buffered(&mut self) -> bool {
+        if self.playing || self.paused || self.frames.is_empty() {
+            return false;
+        }
+        self.start();
+        true
+    }
+
+    /// Returns false when there was nothing to pause.
+    pub fn pause(&mut self) -> bool {
+        if !self.has_pending() || self.paused {
+            return false;
+        }
+        self.paused = true;
+        self.idle = Duration::ZERO;
+        true
+    }
+
+    /// Lower the volume instead of pausing. Returns false when there was
+    /// nothing to duck.
+    pub fn duck(&mut self) -> bool {
+        if !self.has_pending() || self.gain.ducked {
+            return false;
+        }
+        self.gain.ducked = true;
+        true
+    }
+
+    pub fn unduck(&mut self) -> bool {
+        std::mem::replace(&mut self.gain.ducked, false)
+    }
+
+    /// In [0, 1].
+    pub fn set_volume(&mut self, volume: f32) {
+        self.gain.volume = volume;
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.gain.volume
+    }
+
+    /// Playback speed, in [MIN_RATE, MAX_RATE].
+    pub fn set_rate(&mut self, rate: f32) {
+        self.stretcher.set_rate(rate);
+    }
+
+    pub fn rate(&self) -> f32 {
+        self.stretcher.rate()
+    }
+
+    /// Returns false when playback was not paused. Frames cached while
+    /// paused start playing.
+    pub fn resume(&mut self) -> bool {
+        if !self.paused {
+            return false;
+        }
+        self.paused = false;
+        self.idle = Duration::ZERO;
+        if !self.frames.is_empty() {
+            self.playing = true;
+        }
+        true
+    }
+
+    /// The reply and chunk being played.
+    pub fn current_chunk(&self) -> Option<(Arc<Mutex<SpokenReply>>, usize)> {
+        Some((self.reply.clone()?, self.text_id?))
+    }
+
+    /// How long chunk `text_id` has been playing, if it started in this
+    /// playback.
+    pub fn since_chunk_started(&self, text_id: usize) -> Option<Duration> {
+        let started = self.chunk_started.get(&text_id)?;
+        Some(self.elapsed.saturating_sub(*started))
+    }
+
+    /// Skip the reply's chunks before `next`, counting them as played: what
+    /// is queued of them now and what arrives later. Returns false when
+    /// nothing is playing.
+    pub fn skip_to(&mut self, next: usize) -> bool {
+        if !self.playing {
+            return false;
+        }
+        while self
+            .frames
+            .front()
+            .is_some_and(|audio| audio.text_id().is_some_and(|id| id < next))
+        {
+            self.frames.pop_front().unwrap().count_played();
+        }
+        self.skip_before = Some(next);
+        self.cut();
+        true
+    }
+
+    /// Play `frames` instead of what is queued.
+    pub fn replace(&mut self, frames: Vec<QueuedAudio>) {
+        self.frames = frames.into();
+        self.skip_before = None;
+        self.text_id = None;
+        self.ran_dry = false;
+        self.rebuffering = None;
+        self.cut();
+    }
+
+    /// Drop everything queued. A running playback runs dry and drains as
+    /// usual.
+    pub fn flush(&mut self) {
+        self.frames.clear();
+        self.leftover.clear();
+        self.resampler.reset();
+        self.stretcher.reset();
+        self.paused = false;
+        self.gain.ducked = false;
+        self.idle = Duration::ZERO;
+        self.ran_dry = false;
+        self.rebuffering = None;
+        self.tail.clear();
+        self.concealment.clear();
+        self.skip_before = None;
+    }
+
+    pub fn stop(&mut self) {
+        self.flush();
+        self.end_playback();
+        self.earcons.stop();
+    }
+
+    pub fn play_earcon(&mut self, earcon: Earcon, volume: f32) {
+        self.earcons.play(earcon, volume);
+    }
+
+    /// Mix earcons over a rendered output buffer. Returns true when the
+    /// last one has just ended and no TTS is playing.
+    pub fn mix_earcons(&mut self, out: &mut [f32], rate: u32, channels: u16) -> bool {
+        self.earcons.mix(out, rate, channels) && !self.playing
+    }
+
+    /// The output stream changed: samples rendered for the old one are
+    /// dropped.
+    pub fn reset_output(&mut self) {
+        self.leftover.clear();
+        self.resampler.reset();
+        self.stretcher.reset();
+        self.tail.clear();
+        self.concealment.clear();
+        self.output = None;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Playing and not paused: audio is coming out of the speakers.
+    pub fn is_audible(&self) -> bool {
+        self.playing && !self.paused
+    }
+
+    pub fn is_earcon_playing(&self) -> bool {
+        self.earcons.is_playing()
+    }
+
+    /// TTS or an earcon needs the output stream.
+    pub fn is_active(&self) -> bool {
+        self.playing || self.earcons.is_playing()
+    }
+
+    /// Audio is playing, paused, or queued waiting for the jitter buffer.
+    pub fn has_pending(&self) -> bool {
+        self.playing || !self.frames.is_empty()
+    }
+
+    pub fn stats(&self) -> PlaybackStats {
+        PlaybackStats {
+            underruns: self.underruns,
+            buffered_ms: self.buffered().as_millis() as u32,
+            preroll_ms: self.jitter.preroll().as_millis() as u32,
+            drain_timeout_ms: self.jitter.drain_timeout().as_millis() as u32,
+        }
+    }
+
+    /// Progress worth reporting after a render: a new chunk started, or
+    /// PROGRESS_INTERVAL went by.
+    pub fn progress(&mut self) -> Option<PlaybackProgress> {
+        if !self.is_audible() || self.elapsed.is_zero() {
+            return None;
+        }
+        if let Some((text_id, at)) = self.reported {
+            if text_id == self.text_id && self.elapsed < at + PROGRESS_INTERVAL {
+                return None;
+            }
+        }
+        self.reported = Some((self.text_id, self.elapsed));
+        Some(PlaybackProgress {
+            text_id: self.text_id,
+            elapsed_ms: self.elapsed.as_millis() as u32,
+            buffered_ms: self.buffered().as_millis() as u32,
+        })
+    }
+
+    /// Fill one output buffer of the given format. Returns true when
+    /// playback has just drained.
+    pub fn render(&mut self, out: &mut [f32], rate: u32, channels: u16) -> bool {
+        out.fill(0.0);
+        // Paused, once faded out
+        if !self.playing || (self.paused && self.gain.is_silent()) {
+            return false;
+        }
+        self.output = Some((rate, channels));
+        let length = duration(out.len(), rate, channels);
+
+        if let Some(waited) = self.rebuffering {
+            // Give up refilling after as long as the pre-roll would take
+            let preroll = self.jitter.preroll();
+            if self.buffered() < preroll && waited < preroll {
+                self.rebuffering = Some(waited + length);
+                self.play_concealment(out);
+                return false;
+            }
+            self.rebuffering = None;
+            self.concealment.clear();
+            self.fade_in = conceal_len(rate, channels);
+        }
+
+        let mut written = 0;
+        while written < out.len() {
+            let Some(sample) = self.leftover.pop_front() else {
+                break;
+            };
+            out[written] = sample;
+            written += 1;
+        }
+
+        while written < out.len() {
+            let Some(audio) = self.frames.pop_front() else {
+                break;
+            };
+            audio.count_played();
+            let text_id = audio.text_id();
+            if let Some(id) = text_id.filter(|_| text_id != self.text_id) {
+                self.chunk_started.insert(id, self.elapsed);
+            }
+            self.text_id = text_id;
+            self.reply = audio.reply.as_ref().map(|(reply, _)| reply.clone());
+
+            let converted = self.resampler.process(
+                &audio.samples,
+                (audio.sample_rate, audio.channels),
+                (rate, channels),
+            );
+            let stretched = self.stretcher.process(&converted, (rate, channels));
+            written = fill(out, written, stretched, &mut self.leftover);
+        }
+        if written < out.len() {
+            // Out of frames: play what the stretcher held back
+            let held = self.stretcher.finish();
+            written = fill(out, written, held, &mut self.leftover);
+        }
+
+        let audible = self
+            .gain
+            .apply(&mut out[..written], rate, channels, self.paused);
+        if audible < written {
+            // The pause faded out: keep the rest for resume
+            for &sample in out[audible..written].iter().rev() {
+                self.leftover.push_front(sample);
+            }
+            out[audible..written].fill(0.0);
+            written = audible;
+        }
+
+        self.elapsed += duration(written, rate, channels);
+        self.apply_fade_in(&mut out[..written], rate, channels);
+        self.remember_tail(&out[..written], rate, channels);
+
+        if self.paused {
+            return false;
+        }
+        if written == out.len() {
+            self.idle = Duration::ZERO;
+            return false;
+        }
+        // Ran dry after playing something
+        if !self.ran_dry && !self.tail.is_empty() {
+            self.ran_dry = true;
+            self.conceal(channels);
+        }
+        self.play_concealment(&mut out[written..]);
+        if written > 0 {
+            self.idle = Duration::ZERO;
+            return false;
+        }
+
+        self.idle += length;
+        if self.idle < self.jitter.drain_timeout() {
+            return false;
+        }
+        self.end_playback();
+        true
+    }
+
+    fn end_playback(&mut self) {
+        self.playing = false;
+        self.idle = Duration::ZERO;
+        self.ran_dry = false;
+        self.rebuffering = None;
+        self.tail.clear();
+        self.concealment.clear();
+        self.gain.ducked = false;
+        self.elapsed = Duration::ZERO;
+        self.reply = None;
+        self.text_id = None;
+        self.chunk_started.clear();
+        self.skip_before = None;
+        self.reported = None;
+        self.jitter.end_playback();
+    }
+
+    /// How long audio of `duration` takes to play at the current rate.
+    fn playing_time(&self, duration: Duration) -> Duration {
+        Duration::from_secs_f64(duration.as_secs_f64() / self.rate() as f64)
+    }
+
+    /// How long the audio queued and not yet played takes to play.
+    fn buffered(&self) -> Duration {
+        // The leftover is already stretched
+        let leftover = self.output.map_or(Duration::ZERO, |(rate, channels)| {
+            duration(self.leftover.len(), rate, channels)
+        });
+        let frames: Duration = self.frames.iter().map(QueuedAudio::duration).sum();
+        leftover + self.playing_time(frames)
+    }
+
+    /// Jump to other audio: what was playing fades out as after an
+    /// underrun, and the first queued frame fades in.
+    fn cut(&mut self) {
+        self.leftover.clear();
+        self.resampler.reset();
+        self.stretcher.reset();
+        self.concealment.clear();
+        if let Some((_, channels)) = self.output {
+            self.conceal(channels);
+            self.leftover.extend(self.concealment.drain(..));
+        }
+        if let Some(audio) = self.frames.front_mut() {
+            audio.fade_in();
+        }
+    }
+
+    fn apply_fade_in(&mut self, out: &mut [f32], rate: u32, channels: u16) {
+        let len = conceal_len(rate, channels);
+        for sample in out.iter_mut() {
+            if self.fade_in == 0 {
+                break;
+            }
+            *sample *= 1.0 - self.fade_in as f32 / len as f32;
+            self.fade_in -= 1;
+        }
+    }
+
+    fn remember_tail(&mut self, out: &[f32], rate: u32, channels: u16) {
+        let len = conceal_len(rate, channels);
+        let start = out.len().saturating_sub(len);
+        self.tail.extend(&out[start..]);
+        while self.tail.len() > len {
+            self.tail.pop_front();
+        }
+    }
+
+    /// The last output frames backwards, fading out: continuous with what
+    /// was just played, then silence.
+    fn conceal(&mut self, channels: u16) {
+        let frames: Vec<&[f32]> = self
+            .tail
+            .make_contiguous()
+            .chunks_exact(channels as usize)
+            .collect();
+        let total = frames.len();
+        for (i, frame) in frames.iter().rev().enumerate() {
+            let gain = 1.0 - (i + 1) as f32 / total as f32;
+            self.concealment.extend(frame.iter().map(|s| s * gain));
+        }
+        self.tail.clear();
+    }
+
+    fn play_concealment(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            let Some(concealed) = self.concealment.pop_front() else {
+                break;
+            };
+            *sample = concealed;
+        }
+    }
+}
+
+/// The user's volume, ducking and pausing, as one gain that ramps towards
+/// where they put it.
+struct Gain {
+    volume: f32,
+    ducked: bool,
+    current: f32,
+}
+
+impl Default for Gain {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            ducked: false,
+            current: 1.0,
+        }
+    }
+}
+
+impl Gain {
+    fn is_silent(&self) -> bool {
+        self.current <= 0.0
+    }
+
+    /// Apply the gain, ramping it one output frame at a time. Returns how
+    /// many samples played before a pause faded out; the rest are left
+    /// untouched.
+    fn apply(&mut self, out: &mut [f32], rate: u32, channels: u16, paused: bool) -> usize {
+        let target = match (paused, self.ducked) {
+            (true, _) => 0.0,
+            (false, true) => self.volume * DUCK_GAIN,
+            (false, false) => self.volume,
+        };
+        let step = 1000.0 / (FADE_MS * rate) as f32;
+        for (i, frame) in out.chunks_mut(channels as usize).enumerate() {
+            if paused && self.is_silent() {
+                return i * channels as usize;
+            }
+            self.current = if self.current < target {
+                (self.current + step).min(target)
+            } else {
+                (self.current - step).max(target)
+            };
+            for sample in frame {
+                *sample *= self.current;
+            }
+        }
+        out.len()
+    }
+}
+
+/// Write samples after the first `written` of `out`, and what does not fit
+/// to `leftover`. Returns the samples now written.
+fn fill(
+    out: &mut [f32],
+    mut written: usize,
+    samples: Vec<f32>,
+    leftover: &mut VecDeque<f32>,
+) -> usize {
+    for sample in samples {
+        if written < out.len() {
+            out[written] = sample;
+            written += 1;
+        } else {
+            leftover.push_back(sample);
+        }
+    }
+    written
+}
+
+fn duration(samples: usize, rate: u32, channels: u16) -> Duration {
+    if rate == 0 || channels == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_nanos(samples as u64 * 1_000_000_000 / (rate as u64 * channels as u64))
+}
+
+/// Output samples in CONCEAL_MS.
+fn conceal_len(rate: u32, channels: u16) -> usize {
+    (rate * CONCEAL_MS / 1000) as usize * channels as usize
+}
+
+/// Linear resampling and channel mapping that carries its position from
+/// one frame to the next, so frame boundaries neither click nor drift.
+#[derive(Default)]
+struct Resampler {
+    /// (rate, channels) in and out; a change starts over.
+    from: (u32, u16),
+    to: (u32, u16),
+    /// The last input frame, already in the output channels.
+    previous: Vec<f32>,
+    /// Where the next output frame falls, in input frames after `previous`.
+    position: f64,
+}
+
+impl Resampler {
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    fn process(&mut self, samples: &[f32], from: (u32, u16), to: (u32, u16)) -> Vec<f32> {
+        let (from_rate, from_channels) = from;
+        let (to_rate, to_channels) = to;
+        let channels = to_channels as usize;
+        if (from, to) != (self.from, self.to) {
+            self.from = from;
+            self.to = to;
+            self.previous = vec![0.0; channels];
+            self.position = 1.0;
+        }
+
+        // Channels first: the same layout passes through, anything else is
+        // mixed down and spread over the output channels
+        let input: Vec<f32> = if from_channels == to_channels {
+            samples.to_vec()
+        } else {
+            samples
+                .chunks_exact(from_channels as usize)
+                .flat_map(|frame| {
+                    let mono = frame.iter().sum::<f32>() / frame.len() as f32;
+                    std::iter::repeat_n(mono, channels)
+                })
+                .collect()
+        };
+        let frames = input.len() / channels;
+        if frames == 0 {
+            return Vec::new();
+        }
+        if from_rate == to_rate {
+            self.previous = input[(frames - 1) * channels..frames * channels].to_vec();
+            return input;
+        }
+
+        // Frame k of `previous` followed by the input
+        let frame = |k: usize| -> &[f32] {
+            if k == 0 {
+                &self.previous
+            } else {
+                &input[(k - 1) * channels..k * channels]
+            }
+        };
+
+        let step = from_rate as f64 / to_rate as f64;
+        let mut position = self.position;
+        let mut output =
+            Vec::with_capacity(((frames as f64 / step).ceil() as usize + 1) * channels);
+        while position < frames as f64 {
+            let index = position.floor() as usize;
+            let frac = (position - index as f64) as f32;
+            let (a, b) = (frame(index), frame(index + 1));
+            for c in 0..channels {
+                output.push(a[c] + (b[c] - a[c]) * frac);
+            }
+            position += step;
+        }
+
+        self.position = position - frames as f64;
+        self.previous = frame(frames).to_vec();
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 10ms at 16kHz mono.
+    const CALLBACK: usize = 160;
+    /// Callbacks in the initial drain timeout.
+    const DRAIN_CALLBACKS: usize = 100;
+
+    /// `samples` samples of 16kHz mono at half scale.
+    fn frame(samples: usize) -> QueuedAudio {
+        QueuedAudio {
+            samples: vec![0.5; samples],
+            sample_rate: 16000,
+            channels: 1,
+            bytes: samples * 2,
+            reply: None,
+            counted: Default::default(),
+        }
+    }
+
+    /// 20ms at 16kHz mono.
+    fn frame_20ms() -> QueuedAudio {
+        frame(320)
+    }
+
+    fn render(queue: &mut PlaybackQueue, len: usize) -> (Vec<f32>, bool) {
+        let mut out = vec![1.0; len];
+        let drained = queue.render(&mut out, 16000, 1);
+        (out, drained)
+    }
+
+    #[test]
+    fn waits_for_the_jitter_buffer() {
+        let mut queue = PlaybackQueue::new();
+        let now = Instant::now();
+        for _ in 0..4 {
+            assert!(!queue.push_at(frame_20ms(), now));
+        }
+        assert_eq!(render(&mut queue, 4).0, vec![0.0; 4]);
+        assert!(queue.has_pending());
+
+        assert!(queue.push_at(frame_20ms(), now));
+        assert!(!queue.push_at(frame_20ms(), now), "already playing");
+        assert_eq!(render(&mut queue, 4).0, vec![0.5; 4]);
+    }
+
+    #[test]
+    fn a_slow_trickle_starts_after_the_preroll_time() {
+        let mut queue = PlaybackQueue::new();
+        let now = Instant::now();
+        assert!(!queue.push_at(frame_20ms(), now));
+        assert!(!queue.push_at(frame_20ms(), now + Duration::from_millis(50)));
+        assert!(queue.push_at(frame_20ms(), now + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn buffered_audio_plays_at_the_end_of_a_reply() {
+        let mut queue = PlaybackQueue::new();
+        assert!(!queue.play_buffered(), "nothing queued");
+        assert!(!queue.push(frame_20ms()));
+        assert!(queue.play_buffered());
+        assert!(!queue.play_buffered(), "already playing");
+    }
+
+    #[test]
+    fn carries_a_partly_played_frame_into_the_next_callback() {
+        let mut queue = PlaybackQueue::new();
+        queue.push(frame(6));
+        queue.start();
+        assert_eq!(render(&mut queue, 4).0, vec![0.5; 4]);
+        let (out, _) = render(&mut queue, 4);
+        assert_eq!(out[..2], [0.5, 0.5]);
+        // Then concealment fades out
+        assert!(out[2] > 0.0 && out[2] < 0.5);
+        assert!(out[3] < out[2]);
+    }
+
+    #[test]
+    fn drains_once_after_the_timeout() {
+        let mut queue = PlaybackQueue::new();
+        queue.push(frame(4));
+        queue.start();
+        render(&mut queue, CALLBACK);
+
+        for _ in 1..DRAIN_CALLBACKS {
+            assert!(!render(&mut queue, CALLBACK).1);
+        }
+        assert!(render(&mut queue, CALLBACK).1);
+        assert!(!queue.is_playing());
+        assert!(!render(&mut queue, CALLBACK).1);
+    }
+
+    #[test]
+    fn new_audio_restarts_the_drain_timeout() {
+        let mut queue = PlaybackQueue::new();
+        queue.start();
+        for _ in 1..DRAIN_CALLBACKS {
+            render(&mut queue, CALLBACK);
+        }
+        queue.push(frame(4));
+        assert_eq!(render(&mut queue, 4).0, vec![0.5; 4]);
+        assert!(!render(&mut queue, CALLBACK).1);
+    }
+
+    #[test]
+    fn underrun_is_concealed_and_refills_the_preroll() {
+        let mut queue = PlaybackQueue::new();
+        let now = Instant::now();
+        for _ in 0..5 {
+            queue.push_at(frame_20ms(), now);
+        }
+        for _ in 0..10 {
+            assert_eq!(render(&mut queue, CALLBACK).0, vec![0.5; CALLBACK]);
+        }
+
+        // Ran dry: the last 10ms mirrored and fading, not a cut to silence
+        let (out, _) = render(&mut queue, CALLBACK);
+        assert!(out[0] > 0.49 && out[CALLBACK / 2] > 0.2);
+        assert!(out.windows(2).all(|w| w[1] <= w[0]));
+        assert_eq!(queue.stats().underruns, 0);
+
+        queue.push_at(frame_20ms(), now + Duration::from_millis(200));
+        assert_eq!(queue.stats().underruns, 1);
+        // Refilling: at most as long as the pre-roll, then fading back in
+        for _ in 0..10 {
+            assert_eq!(render(&mut queue, CALLBACK).0, vec![0.0; CALLBACK]);
+        }
+        let (out, _) = render(&mut queue, CALLBACK);
+        assert!(out[0] < 0.1);
+        assert_eq!(out[CALLBACK - 1], 0.5 * (1.0 - 1.0 / CALLBACK as f32));
+    }
+
+    #[test]
+    fn stats_report_the_buffer() {
+        let mut queue = PlaybackQueue::new();
+        let now = Instant::now();
+        for _ in 0..3 {
+            queue.push_at(frame_20ms(), now);
+        }
+        let stats = queue.stats();
+        assert_eq!(stats.buffered_ms, 60);
+        assert_eq!(stats.preroll_ms, 100);
+        assert_eq!(stats.drain_timeout_ms, 1000);
+    }
+
+    #[test]
+    fn paused_playback_is_silent_and_never_drains() {
+        let mut queue = PlaybackQueue::new();
+        queue.push(frame(CALLBACK * 4));
+        queue.start();
+        assert!(queue.pause());
+        assert!(!queue.is_audible());
+
+        // Fades out over one 10ms callback, then silence
+        let (out, _) = render(&mut queue, CALLBACK);
+        assert!(out[0] > 0.49 && out[CALLBACK - 1] == 0.0);
+        assert!(out.windows(2).all(|w| w[1] <= w[0]));
+        for _ in 0..DRAIN_CALLBACKS * 2 {
+            let (out, drained) = render(&mut queue, CALLBACK);
+            assert_eq!(out, vec![0.0; CALLBACK]);
+            assert!(!drained);
+        }
+
+        // Fades back in
+        assert!(queue.resume());
+        let (out, _) = render(&mut queue, CALLBACK);
+        assert!(out[0] < 0.01 && out[CALLBACK - 1] == 0.5);
+        assert!(out.windows(2).all(|w| w[1] >= w[0]));
+    }
+
+    #[test]
+    fn a_pause_keeps_what_its_fade_did_not_reach() {
+        let mut queue = PlaybackQueue::new();
+        queue.push(frame(CALLBACK * 2));
+        queue.start();
+        queue.pause();
+        // The fade takes 160 samples; the other 160 wait for resume
+        render(&mut queue, CALLBACK * 2);
+        assert_eq!(queue.buffered(), Duration::from_millis(10));
+        queue.resume();
+        let (out, _) = render(&mut queue, CALLBACK * 2);
+        assert!(out[CALLBACK - 1] > 0.49);
+        assert_eq!(queue.buffered(), Duration::ZERO);
+    }
+
+    #[test]
+    fn volume_and_ducking_ramp_the_gain() {
+        let mut queue = PlaybackQueue::new();
+        queue.push(frame(CALLBACK * 10));
+        queue.start();
+        queue.set_volume(0.5);
+        let (out, _) = render(&mut queue, CALLBACK * 2);
+        assert!(out[0] < 0.5 && out[0] > 0.49, "ramps, no step");
+        assert_eq!(out[CALLBACK * 2 - 1], 0.25);
+
+        assert!(queue.duck());
+        assert!(!queue.duck(), "already ducked");
+        let (out, _) = render(&mut queue, CALLBACK * 2);
+        assert!((out[CALLBACK * 2 - 1] - 0.5 * 0.5 * DUCK_GAIN).abs() < 1e-6);
+        assert!(queue.is_audible(), "ducking keeps playing");
+
+        assert!(queue.unduck());
+        let (out, _) = render(&mut queue, CALLBACK * 2);
+        assert_eq!(out[CALLBACK * 2 - 1], 0.25);
+    }
+
+    #[test]
+    fn rate_changes_how_long_audio_plays() {
+        let mut queue = PlaybackQueue::new();
+        queue.set_rate(2.0);
+        queue.push(frame(CALLBACK * 20));
+        queue.start();
+        // 200ms plays in about 100ms: the last segment's end plays as is
+        let mut callbacks = 0;
+        while render(&mut queue, CALLBACK).0[CALLBACK - 1] > 0.49 {
+            callbacks += 1;
+        }
+        assert_eq!(callbacks, 11);
+    }
+
+    #[test]
+    fn buffer_is_measured_in_playing_time() {
+        let mut queue = PlaybackQueue::new();
+        queue.set_rate(2.0);
+        let now = Instant::now();
+        for _ in 0..5 {
+            assert!(!queue.push_at(frame_20ms(), now));
+        }
+        // 100ms of audio is only 50ms of the pre-roll at 2x
+        assert_eq!(queue.stats().buffered_ms, 50);
+        for _ in 0..5 {
+            queue.push_at(frame_20ms(), now);
+        }
+        assert!(queue.is_playing());
+    }
+
+    fn chunk(reply: &Arc<Mutex<SpokenReply>>, text_id: usize) -> QueuedAudio {
+        let mut audio = frame(CALLBACK);
+        audio.reply = Some((reply.clone(), Some(text_id)));
+        audio
+    }
+
+    #[test]
+    fn skipping_counts_the_rest_of_the_chunk_as_played() {
+        let reply = Arc::new(Mutex::new(SpokenReply::new()));
+        let mut queue = PlaybackQueue::new();
+        for text_id in [0, 0, 0, 1] {
+            reply
+                .lock()
+                .unwrap()
+                .add_queued(Some(text_id), CALLBACK * 2);
+            queue.push(chunk(&reply, text_id));
+        }
+        assert!(!queue.skip_to(1), "not playing");
+        queue.start();
+        render(&mut queue, CALLBACK);
+        assert_eq!(queue.current_chunk().map(|(_, id)| id), Some(0));
+
+        assert!(queue.skip_to(1));
+        assert_eq!(reply.lock().unwrap().played_fraction(), 0.75);
+        // Late audio of the skipped chunk is dropped too: 10ms of fading
+        // out, then chunk 1
+        queue.push(chunk(&reply, 0));
+        assert_eq!(queue.buffered(), Duration::from_millis(20));
+
+        let (out, _) = render(&mut queue, CALLBACK * 2);
+        assert!(out[0] > 0.49 && out[CALLBACK - 1] < 0.01);
+        assert!(out[CALLBACK] < 0.01 && out[CALLBACK * 2 - 1] > 0.49);
+        assert_eq!(queue.current_chunk().map(|(_, id)| id), Some(1));
+    }
+
+    #[test]
+    fn replaced_audio_starts_its_chunks_over() {
+        let reply = Arc::new(Mutex::new(SpokenReply::new()));
+        let mut queue = PlaybackQueue::new();
+        for _ in 0..4 {
+            queue.push(chunk(&reply, 0));
+        }
+        queue.start();
+        for _ in 0..3 {
+            render(&mut queue, CALLBACK);
+        }
+        assert_eq!(
+            queue.since_chunk_started(0),
+            Some(Duration::from_millis(30))
+        );
+        assert_eq!(queue.since_chunk_started(1), None);
+
+        queue.replace(vec![chunk(&reply, 0), chunk(&reply, 0)]);
+        render(&mut queue, CALLBACK);
+        render(&mut queue, CALLBACK);
+        assert_eq!(
+            queue.since_chunk_started(0),
+            Some(Duration::from_millis(10))
+        );
+    }
+
+    #[test]
+    fn audio_heard_again_is_not_counted_again() {
+        let reply = Arc::new(Mutex::new(SpokenReply::new()));
+        reply.lock().unwrap().add_text(0, "一二三四");
+        let frames: Vec<QueuedAudio> = (0..4).map(|_| chunk(&reply, 0)).collect();
+        let mut queue = PlaybackQueue::new();
+        for audio in &frames {
+            reply.lock().unwrap().add_queued(Some(0), audio.bytes);
+            queue.push(audio.clone());
+        }
+        queue.start();
+        render(&mut queue, CALLBACK * 2);
+        assert_eq!(reply.lock().unwrap().heard_text(), "一二");
+
+        // Rewound: 10ms of fading out, the first two frames again, then
+        // the third
+        queue.replace(frames);
+        render(&mut queue, CALLBACK * 4);
+        assert_eq!(reply.lock().unwrap().heard_text(), "一二三");
+    }
+
+    #[test]
+    fn audio_cached_while_paused_plays_on_resume() {
+        let mut queue = PlaybackQueue::new();
+        queue.push(frame(4));
+        assert!(queue.pause());
+        for _ in 0..5 {
+            assert!(!queue.push(frame_20ms()));
+        }
+        assert!(!queue.is_playing());
+
+        assert!(queue.resume());
+        assert!(queue.is_playing());
+    }
+
+    #[test]
+    fn nothing_to_pause_without_audio() {
+        let mut queue = PlaybackQueue::new();
+        assert!(!queue.pause());
+        assert!(!queue.resume());
+    }
+
+    #[test]
+    fn flush_drops_audio_and_still_drains() {
+        let mut queue = PlaybackQueue::new();
+        queue.push(frame(6));
+        queue.push(frame(4));
+        queue.start();
+        render(&mut queue, 4);
+        queue.pause();
+
+        queue.flush();
+        assert!(queue.is_audible());
+        assert_eq!(render(&mut queue, CALLBACK).0, vec![0.0; CALLBACK]);
+        for _ in 2..DRAIN_CALLBACKS {
+            render(&mut queue, CALLBACK);
+        }
+        assert!(render(&mut queue, CALLBACK).1);
+    }
+
+    #[test]
+    fn played_frames_are_counted_on_their_reply() {
+        let reply = Arc::new(Mutex::new(SpokenReply::new()));
+        reply.lock().unwrap().add_text(0, "你好");
+        reply.lock().unwrap().add_queued(Some(0), 16);
+        reply.lock().unwrap().add_queued(Some(0), 16);
+
+        let mut queue = PlaybackQueue::new();
+        for _ in 0..2 {
+            let mut audio = frame(8);
+            audio.reply = Some((reply.clone(), Some(0)));
+            queue.push(audio);
+        }
+        queue.start();
+        render(&mut queue, 8);
+        assert_eq!(reply.lock().unwrap().played_fraction(), 0.5);
+    }
+
+    #[test]
+    fn progress_follows_the_chunk_being_played() {
+        let reply = Arc::new(Mutex::new(SpokenReply::new()));
+        let mut queue = PlaybackQueue::new();
+        for text_id in 0..2 {
+            for _ in 0..3 {
+                let mut audio = frame(CALLBACK);
+                audio.reply = Some((reply.clone(), Some(text_id)));
+                queue.push(audio);
+            }
+        }
+        assert_eq!(queue.progress(), None, "not playing yet");
+        queue.start();
+
+        render(&mut queue, CALLBACK);
+        let first = queue.progress().unwrap();
+        assert_eq!(first.text_id, Some(0));
+        assert_eq!((first.elapsed_ms, first.buffered_ms), (10, 50));
+        render(&mut queue, CALLBACK);
+        assert_eq!(queue.progress(), None, "same chunk, too soon");
+
+        render(&mut queue, CALLBACK);
+        render(&mut queue, CALLBACK);
+        let next = queue.progress().unwrap();
+        assert_eq!(next.text_id, Some(1));
+        assert_eq!(next.elapsed_ms, 40);
+    }
+
+    #[test]
+    fn progress_is_reported_periodically_within_a_chunk() {
+        let mut queue = PlaybackQueue::new();
+        for _ in 0..40 {
+            queue.push(frame(CALLBACK));
+        }
+        queue.start();
+        let mut reports = 0;
+        for _ in 0..40 {
+            render(&mut queue, CALLBACK);
+            reports += queue.progress().is_some() as usize;
+        }
+        // At 10ms, 260ms
+        assert_eq!(reports, 2);
+    }
+
+    #[test]
+    fn resampling_is_continuous_across_frames() {
+        let ramp: Vec<f32> = (0..48).map(|i| i as f32 / 48.0).collect();
+
+        let mut whole = Resampler::default();
+        let expected = whole.process(&ramp, (24000, 1), (48000, 1));
+
+        let mut split = Resampler::default();
+        let mut output = split.process(&ramp[..17], (24000, 1), (48000, 1));
+        output.extend(split.process(&ramp[17..], (24000, 1), (48000, 1)));
+
+        assert_eq!(output, expected);
+        assert_eq!(expected.len(), 94);
+        // Halfway between two input samples
+        assert!((expected[1] - 0.5 / 48.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn output_length_follows_the_rate_ratio() {
+        let mut resampler = Resampler::default();
+        let mut total = 0;
+        for _ in 0..50 {
+            // 20ms at 44.1kHz
+            total += resampler.process(&[0.1; 882], (44100, 1), (16000, 1)).len();
+        }
+        assert!((total as i64 - 16000).abs() <= 1, "got {}", total);
+    }
+
+    #[test]
+    fn channels_are_mixed_down_and_spread() {
+        let mut resampler = Resampler::default();
+        assert_eq!(
+            resampler.process(&[0.2, 0.4, 0.0, 1.0], (16000, 2), (16000, 1)),
+            vec![0.3, 0.5]
+        );
+        assert_eq!(
+            resampler.process(&[0.2, 0.4], (16000, 1), (16000, 2)),
+            vec![0.2, 0.2, 0.4, 0.4]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! The audio of the last few replies, kept after it is played so it can be
+//! heard again without asking TTS for it.
+//!
+//! Frames are kept as queued, in the order they arrived, with the reply and
+//! chunk they speak.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::audio::playback_queue::QueuedAudio;
+use crate::audio::spoken::SpokenReply;
+
+/// Replies kept.
+const REPLIES: usize = 3;
+
+#[derive(Default)]
+pub struct ReplayHistory {
+    /// Oldest first.
+    replies: VecDeque<ReplyAudio>,
+}
+
+struct ReplyAudio {
+    reply: Arc<Mutex<SpokenReply>>,
+    frames: Vec<QueuedAudio>,
+}
+
+impl ReplayHistory {
+    /// Keep a reply's frame; frames of other audio are not kept.
+    pub fn record(&mut self, audio: &QueuedAudio) {
+        let Some((reply, _)) = &audio.reply else {
+            return;
+        };
+        let latest = self
+            .replies
+            .back()
+            .is_some_and(|r| Arc::ptr_eq(&r.reply, reply));
+        if !latest {
+            if self.replies.len() == REPLIES {
+                self.replies.pop_front();
+            }
+            self.replies.push_back(ReplyAudio {
+                reply: reply.clone(),
+                frames: Vec::new(),
+            });
+        }
+        self.replies.back_mut().unwrap().frames.push(audio.clone());
+    }
+
+    /// All of the latest reply's audio so far.
+    pub fn last_reply(&self) -> Vec<QueuedAudio> {
+        self.replies
+            .back()
+            .map(|r| r.frames.clone())
+            .unwrap_or_default()
+    }
+
+    /// `reply`'s audio from chunk `text_id` on; empty once it is no longer
+    /// kept.
+    pub fn reply_from(&self, reply: &Arc<Mutex<SpokenReply>>, text_id: usize) -> Vec<QueuedAudio> {
+        let Some(kept) = self.replies.iter().find(|r| Arc::ptr_eq(&r.reply, reply)) else {
+            return Vec::new();
+        };
+        let start = kept.frames.iter().position(|audio| {
+            audio
+                .reply
+                .as_ref()
+                .and_then(|(_, id)| *id)
+                .is_some_and(|id| id >= text_id)
+        });
+        start.map_or_else(Vec::new, |start| kept.frames[start..].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(reply: &Arc<Mutex<SpokenReply>>, text_id: usize) -> QueuedAudio {
+        QueuedAudio {
+            samples: vec![text_id as f32; 4],
+            sample_rate: 16000,
+            channels: 1,
+            bytes: 8,
+            reply: Some((reply.clone(), Some(text_id))),
+            counted: Default::default(),
+        }
+    }
+
+    fn chunks(frames: &[QueuedAudio]) -> Vec<f32> {
+        frames.iter().map(|audio| audio.samples[0]).collect()
+    }
+
+    #[test]
+    fn keeps_the_last_replies() {
+        let mut history = ReplayHistory::default();
+        let replies: Vec<_> = (0..4)
+            .map(|_| Arc::new(Mutex::new(SpokenReply::new())))
+            .collect();
+        for reply in &replies {
+            history.record(&frame(reply, 0));
+            history.record(&frame(reply, 1));
+        }
+        assert_eq!(chunks(&history.last_reply()), vec![0.0, 1.0]);
+        assert!(history.reply_from(&replies[0], 0).is_empty(), "dropped");
+        assert_eq!(history.reply_from(&replies[1], 0).len(), 2);
+    }
+
+    #[test]
+    fn untagged_audio_is_not_kept() {
+        let mut history = ReplayHistory::default();
+        let mut audio = frame(&Arc::new(Mutex::new(SpokenReply::new())), 0);
+        audio.reply = None;
+        history.record(&audio);
+        assert!(history.last_reply().is_empty());
+    }
+
+    #[test]
+    fn replays_from_a_chunk() {
+        let mut history = ReplayHistory::default();
+        let reply = Arc::new(Mutex::new(SpokenReply::new()));
+        for text_id in [0, 0, 1, 2, 2] {
+            history.record(&frame(&reply, text_id));
+        }
+        assert_eq!(chunks(&history.reply_from(&reply, 1)), vec![1.0, 2.0, 2.0]);
+        assert!(history.reply_from(&reply, 3).is_empty());
+    }
+}
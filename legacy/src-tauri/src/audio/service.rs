@@ -0,0 +1,349 @@
+//! The voice service: one instance, held in Tauri managed state, owns the
+//! session (capture stream + ASR link), playback and component health.
+//!
+//! `active` covers the whole session, `mic_open` only gates whether captured
+//! frames are forwarded to ASR, and `auto_vad` switches from manual mic
+//! toggling to hands-free voice detection. In hands-free mode
+//! `server_endpointing` hands the utterance boundaries to the ASR server
+//! instead of the local VAD. The commands in `capture` and `playback` only
+//! delegate here.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tauri::Manager;
+use tokio::sync::mpsc;
+use tokio_tungstenite::connect_async;
+
+use crate::audio::asr_session::run_asr_session;
+use crate::audio::capture::{spawn_capture_stream, AudioCapture};
+use crate::audio::output_device::OutputDevicePreference;
+use crate::audio::playback::Playback;
+use crate::audio::state::{
+    emit_service_status, emit_speech_end, emit_speech_start, CaptureMsg, EndpointingMode, TTS_HOST,
+};
+use crate::conversation::{interruption, set_current_turn_status, ConversationStatus};
+use crate::events::{self, ServiceStatus, VadModeEvent};
+
+const TTS_HEALTH_INTERVAL_SECS: u64 = 10;
+
+pub struct VoiceService {
+    pub active: AtomicBool,
+    pub mic_open: AtomicBool,
+    pub auto_vad: AtomicBool,
+    pub server_endpointing: AtomicBool,
+    // Component health, aggregated into ONE user-facing pipeline status.
+    pub asr_ok: AtomicBool,
+    pub tts_ok: AtomicBool,
+    /// Into the running ASR session; `None` while the service is stopped.
+    pipe_tx: Mutex<Option<mpsc::UnboundedSender<CaptureMsg>>>,
+    /// Tells the capture thread to drop its stream.
+    capture_shutdown: Mutex<Option<std::sync::mpsc::Sender<()>>>,
+    pub playback: Arc<Playback>,
+}
+
+/// The app's voice service.
+pub fn voice_service(app: &tauri::AppHandle) -> Arc<VoiceService> {
+    app.state::<Arc<VoiceService>>().inner().clone()
+}
+
+impl VoiceService {
+    pub fn new(output_device: OutputDevicePreference) -> Self {
+        Self {
+            active: AtomicBool::new(false),
+            mic_open: AtomicBool::new(false),
+            auto_vad: AtomicBool::new(false),
+            server_endpointing: AtomicBool::new(false),
+            asr_ok: AtomicBool::new(false),
+            tts_ok: AtomicBool::new(false),
+            pipe_tx: Mutex::new(None),
+            capture_shutdown: Mutex::new(None),
+            playback: Arc::new(Playback::new(output_device)),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.mic_open.load(Ordering::SeqCst)
+    }
+
+    /// Pass a message to the ASR session, if one is running.
+    pub fn send(&self, msg: CaptureMsg) {
+        if let Some(tx) = self.pipe_tx.lock().unwrap().as_ref() {
+            let _ = tx.send(msg);
+        }
+    }
+
+    pub fn endpointing_mode(&self) -> EndpointingMode {
+        if !self.auto_vad.load(Ordering::SeqCst) {
+            EndpointingMode::Manual
+        } else if self.server_endpointing.load(Ordering::SeqCst) {
+            EndpointingMode::Server
+        } else {
+            EndpointingMode::Client
+        }
+    }
+
+    pub fn emit_vad_mode(&self, app: &tauri::AppHandle) {
+        let mode = self.endpointing_mode();
+        events::emit(
+            app,
+            VadModeEvent {
+                auto: mode != EndpointingMode::Manual,
+                mode,
+            },
+        );
+    }
+
+    /// Derive the ONE status the user sees from component health. The
+    /// pipeline is usable as long as ASR works: without TTS we degrade to
+    /// text-only replies instead of failing, and recover automatically when
+    /// TTS returns.
+    pub fn emit_pipeline_status(&self, app: &tauri::AppHandle) {
+        if !self.active.load(Ordering::SeqCst) || !self.asr_ok.load(Ordering::SeqCst) {
+            return; // transitional states are emitted by the ASR session itself
+        }
+
+        if self.tts_ok.load(Ordering::SeqCst) {
+            emit_service_status(app, ServiceStatus::Ready, "语音服务就绪");
+        } else {
+            emit_service_status(
+                app,
+                ServiceStatus::Degraded,
+                "语音合成未连接：回复将只显示文字，不播放语音。启动 TTS 服务（端口 8766）后自动恢复。",
+            );
+        }
+    }
+
+    /// Start the session: persistent ASR link + always-on capture stream.
+    /// The mic starts CLOSED — no audio is forwarded until `open_mic` (or
+    /// until the auto-VAD detects speech).
+    pub fn start(
+        self: &Arc<Self>,
+        app: &tauri::AppHandle,
+        vocabulary: Vec<String>,
+    ) -> Result<(), String> {
+        if self.active.swap(true, Ordering::SeqCst) {
+            return Err("Voice service already running".to_string());
+        }
+        self.mic_open.store(false, Ordering::SeqCst);
+        self.asr_ok.store(false, Ordering::SeqCst);
+        self.tts_ok.store(false, Ordering::SeqCst);
+
+        emit_service_status(app, ServiceStatus::Starting, "正在启动语音服务");
+
+        let device = match AudioCapture::get_default_input_device() {
+            Ok(d) => d,
+            Err(e) => {
+                self.active.store(false, Ordering::SeqCst);
+                emit_service_status(app, ServiceStatus::Error, "没有可用的输入设备");
+                return Err(format!("Device error: {}", e));
+            }
+        };
+
+        let capture_config = match AudioCapture::get_supported_config(&device) {
+            Ok(c) => c,
+            Err(e) => {
+                self.active.store(false, Ordering::SeqCst);
+                emit_service_status(app, ServiceStatus::Error, "输入设备配置失败");
+                return Err(format!("Config error: {}", e));
+            }
+        };
+
+        let (pipe_tx, pipe_rx) = mpsc::unbounded_channel::<CaptureMsg>();
+        *self.pipe_tx.lock().unwrap() = Some(pipe_tx);
+
+        // Persistent ASR session for the whole service lifetime
+        let service = self.clone();
+        let app_for_asr = app.clone();
+        tauri::async_runtime::spawn(async move {
+            run_asr_session(app_for_asr, service, pipe_rx, vocabulary).await;
+        });
+
+        // TTS health monitor: keeps the aggregated pipeline status honest
+        // and self-healing — if TTS comes up later, "degraded" clears by
+        // itself.
+        let service = self.clone();
+        let app_for_tts = app.clone();
+        tauri::async_runtime::spawn(async move {
+            while service.active.load(Ordering::SeqCst) {
+                let ok = match connect_async(TTS_HOST).await {
+                    Ok((mut ws, _)) => {
+                        let _ = ws.close(None).await;
+                        true
+                    }
+                    Err(_) => false,
+                };
+
+                if service.tts_ok.swap(ok, Ordering::SeqCst) != ok {
+                    service.emit_pipeline_status(&app_for_tts);
+                }
+
+                tokio::time::sleep(tokio::time::Duration::from_secs(TTS_HEALTH_INTERVAL_SECS))
+                    .await;
+            }
+        });
+
+        let (shutdown_tx, shutdown_rx) = std::sync::mpsc::channel::<()>();
+        *self.capture_shutdown.lock().unwrap() = Some(shutdown_tx);
+        spawn_capture_stream(
+            self.clone(),
+            app.clone(),
+            device,
+            capture_config,
+            shutdown_rx,
+        );
+
+        Ok(())
+    }
+
+    /// Stop the whole session (capture stream + ASR link).
+    pub fn stop(&self, app: &tauri::AppHandle) {
+        self.active.store(false, Ordering::SeqCst);
+        self.mic_open.store(false, Ordering::SeqCst);
+
+        // Dropping the sender unblocks and terminates the ASR session
+        *self.pipe_tx.lock().unwrap() = None;
+
+        // Signal the capture thread to drop the stream
+        if let Some(tx) = self.capture_shutdown.lock().unwrap().take() {
+            let _ = tx.send(());
+        }
+
+        self.playback.resume(app);
+        emit_service_status(app, ServiceStatus::Offline, "语音服务已停止");
+        set_current_turn_status(app, ConversationStatus::Idle);
+    }
+
+    /// Open the mic: start a new utterance. If TTS audio is playing, pause
+    /// it and keep the remaining audio cached (barge-in).
+    pub fn open_mic(&self, app: &tauri::AppHandle) -> Result<(), String> {
+        if !self.is_active() {
+            return Err("Voice service is not running".to_string());
+        }
+        if self.auto_vad.load(Ordering::SeqCst) {
+            return Err("Auto VAD mode is active; the mic is hands-free".to_string());
+        }
+        if self.mic_open.swap(true, Ordering::SeqCst) {
+            return Ok(()); // already open
+        }
+
+        // Barge-in: pause TTS playback, cache what's left
+        interruption::user_started_speaking(app);
+
+        self.send(CaptureMsg::BeginUtterance);
+        emit_speech_start(app);
+
+        Ok(())
+    }
+
+    /// Close the mic: this is the explicit "sentence finished" signal. ASR
+    /// gets end_utterance and will reply with the authoritative final text;
+    /// paused TTS playback resumes from its cache unless the interruption
+    /// policy holds it for the final.
+    pub fn close_mic(&self, app: &tauri::AppHandle) {
+        if !self.mic_open.swap(false, Ordering::SeqCst) {
+            return; // already closed
+        }
+
+        self.send(CaptureMsg::EndUtterance);
+        emit_speech_end(app);
+
+        // Resume any paused TTS playback from the cache
+        interruption::user_stopped_speaking(app);
+    }
+
+    /// Abort the current utterance: nothing is sent to the LLM, ASR drops
+    /// its buffer, and paused TTS playback resumes. With no utterance in
+    /// flight the session ignores it.
+    pub fn cancel_utterance(&self) -> Result<(), String> {
+        if !self.is_active() {
+            return Err("Voice service is not running".to_string());
+        }
+        self.mic_open.store(false, Ordering::SeqCst);
+        self.send(CaptureMsg::CancelUtterance);
+        Ok(())
+    }
+
+    /// Choose who detects utterance boundaries: the user (manual), the local
+    /// VAD (client) or the ASR server (server).
+    pub fn set_endpointing_mode(&self, app: &tauri::AppHandle, mode: EndpointingMode) {
+        if self.endpointing_mode() == mode {
+            return;
+        }
+        let auto = mode != EndpointingMode::Manual;
+        self.auto_vad.store(auto, Ordering::SeqCst);
+        self.server_endpointing
+            .store(mode == EndpointingMode::Server, Ordering::SeqCst);
+
+        // Entering auto mode with a manual utterance in flight: close it first
+        if auto && self.mic_open.swap(false, Ordering::SeqCst) {
+            self.send(CaptureMsg::EndUtterance);
+            emit_speech_end(app);
+            interruption::user_stopped_speaking(app);
+        }
+
+        // Tell the session (FIFO, after any queued frames) so it can close
+        // an auto-detected utterance that is still in progress when
+        // switching.
+        self.send(CaptureMsg::SetEndpointing(mode));
+
+        self.emit_vad_mode(app);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpointing_mode_follows_the_flags() {
+        let service = VoiceService::new(OutputDevicePreference::load(None));
+        assert_eq!(service.endpointing_mode(), EndpointingMode::Manual);
+
+        service.auto_vad.store(true, Ordering::SeqCst);
+        assert_eq!(service.endpointing_mode(), EndpointingMode::Client);
+
+        service.server_endpointing.store(true, Ordering::SeqCst);
+        assert_eq!(service.endpointing_mode(), EndpointingMode::Server);
+
+        // Server endpointing only counts in hands-free mode
+        service.auto_vad.store(false, Ordering::SeqCst);
+        assert_eq!(service.endpointing_mode(), EndpointingMode::Manual);
+    }
+
+    #[test]
+    fn instances_do_not_share_state() {
+        let first = VoiceService::new(OutputDevicePreference::load(None));
+        let second = VoiceService::new(OutputDevicePreference::load(None));
+        first.mic_open.store(true, Ordering::SeqCst);
+        assert!(first.is_recording());
+        assert!(!second.is_recording());
+    }
+
+    #[test]
+    fn stopped_service_refuses_to_cancel_and_drops_messages() {
+        let service = VoiceService::new(OutputDevicePreference::load(None));
+        assert!(service.cancel_utterance().is_err());
+        // No session: nothing to deliver to, and nothing blocks
+        service.send(CaptureMsg::EndUtterance);
+        assert!(!service.playback.has_pending_audio());
+    }
+
+    #[test]
+    fn messages_reach_the_running_session_in_order() {
+        let service = VoiceService::new(OutputDevicePreference::load(None));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        *service.pipe_tx.lock().unwrap() = Some(tx);
+
+        service.send(CaptureMsg::BeginUtterance);
+        service.send(CaptureMsg::Frame(vec![1, 2]));
+        service.send(CaptureMsg::EndUtterance);
+
+        assert!(matches!(rx.try_recv(), Ok(CaptureMsg::BeginUtterance)));
+        assert!(matches!(rx.try_recv(), Ok(CaptureMsg::Frame(frame)) if frame == [1, 2]));
+        assert!(matches!(rx.try_recv(), Ok(CaptureMsg::EndUtterance)));
+    }
+}
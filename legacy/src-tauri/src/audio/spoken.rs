@@ -0,0 +1,242 @@
+//! What of a spoken reply the user actually heard.
+//!
+//! The TTS server announces each text chunk (`audio_start` with its
+//! `text_id`) before sending that chunk's frames, so every queued frame is
+//! tagged with the text it speaks. The output callback counts the bytes it
+//! plays; when the reply is cut short, `heard_text` turns those counts
+//! back into text.
+//!
+//! Chunks are clauses rather than sentences; `sentence_of` groups them
+//! into sentences for skipping and rewinding.
+
+use std::ops::Range;
+
+#[derive(Default)]
+pub struct SpokenReply {
+    /// Indexed by `text_id`.
+    chunks: Vec<SpokenChunk>,
+    /// Frames from a server that doesn't announce chunks.
+    untagged_queued: usize,
+    untagged_played: usize,
+    /// TTS was unavailable: the reply was only shown, and all of it seen.
+    text_only: bool,
+    /// Where the reply was recorded in the conversation history.
+    pub message_index: Option<usize>,
+}
+
+#[derive(Default)]
+struct SpokenChunk {
+    text: String,
+    /// Audio bytes the server announced for this chunk.
+    expected: Option<usize>,
+    queued: usize,
+    played: usize,
+}
+
+impl SpokenChunk {
+    fn total(&self) -> usize {
+        self.expected.unwrap_or(0).max(self.queued)
+    }
+}
+
+impl SpokenReply {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn chunk_mut(&mut self, text_id: usize) -> &mut SpokenChunk {
+        if self.chunks.len() <= text_id {
+            self.chunks.resize_with(text_id + 1, SpokenChunk::default);
+        }
+        &mut self.chunks[text_id]
+    }
+
+    pub fn add_text(&mut self, text_id: usize, text: &str) {
+        self.chunk_mut(text_id).text.push_str(text);
+    }
+
+    pub fn expect_audio(&mut self, text_id: usize, bytes: usize) {
+        self.chunk_mut(text_id).expected = Some(bytes);
+    }
+
+    pub fn add_queued(&mut self, text_id: Option<usize>, bytes: usize) {
+        match text_id {
+            Some(id) => self.chunk_mut(id).queued += bytes,
+            None => self.untagged_queued += bytes,
+        }
+    }
+
+    pub fn add_played(&mut self, text_id: Option<usize>, bytes: usize) {
+        match text_id {
+            Some(id) => self.chunk_mut(id).played += bytes,
+            None => self.untagged_played += bytes,
+        }
+    }
+
+    pub fn set_text_only(&mut self) {
+        self.text_only = true;
+    }
+
+    /// Share of the reply's audio that has been played so far.
+    pub fn played_fraction(&self) -> f32 {
+        if self.text_only {
+            return 1.0;
+        }
+        let total: usize =
+            self.untagged_queued + self.chunks.iter().map(SpokenChunk::total).sum::<usize>();
+        let played: usize =
+            self.untagged_played + self.chunks.iter().map(|c| c.played).sum::<usize>();
+        if total == 0 {
+            0.0
+        } else {
+            (played as f32 / total as f32).min(1.0)
+        }
+    }
+
+    /// The reply text up to where playback got: whole chunks that were
+    /// played, then the played share of the chunk that was cut off.
+    pub fn heard_text(&self) -> String {
+        let full: String = self.chunks.iter().map(|c| c.text.as_str()).collect();
+        if self.text_only {
+            return full;
+        }
+        if self.chunks.iter().all(|c| c.total() == 0) {
+            return text_prefix(&full, self.untagged_played, self.untagged_queued);
+        }
+
+        let mut heard = String::new();
+        for chunk in &self.chunks {
+            // Announced without audio (punctuation only): nothing to wait for
+            if chunk.expected == Some(0) && chunk.queued == 0 {
+                heard.push_str(&chunk.text);
+                continue;
+            }
+            let total = chunk.total();
+            if total == 0 || chunk.played < total {
+                heard.push_str(&text_prefix(&chunk.text, chunk.played, total));
+                break;
+            }
+            heard.push_str(&chunk.text);
+        }
+        heard
+    }
+
+    /// The chunks of the sentence chunk `text_id` is part of: a sentence
+    /// ends with a chunk whose text ends in terminal punctuation. The last
+    /// sentence may still be growing.
+    pub fn sentence_of(&self, text_id: usize) -> Range<usize> {
+        let ends = |id: usize| self.chunks.get(id).is_some_and(|c| ends_sentence(&c.text));
+        let start = (0..text_id)
+            .rev()
+            .find(|&id| ends(id))
+            .map_or(0, |id| id + 1);
+        let end = (text_id..self.chunks.len())
+            .find(|&id| ends(id))
+            .map_or(self.chunks.len().max(text_id + 1), |id| id + 1);
+        start..end
+    }
+}
+
+fn ends_sentence(text: &str) -> bool {
+    text.trim_end()
+        .ends_with(['.', '!', '?', '。', '！', '？', '…'])
+}
+
+/// The first `played / total` of `text`, by characters.
+fn text_prefix(text: &str, played: usize, total: usize) -> String {
+    if total == 0 {
+        return String::new();
+    }
+    let chars = text.chars().count();
+    let keep = chars * played.min(total) / total;
+    text.chars().take(keep).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(chunks: &[&str]) -> SpokenReply {
+        let mut reply = SpokenReply::new();
+        for (id, text) in chunks.iter().enumerate() {
+            reply.add_text(id, text);
+            reply.add_queued(Some(id), 1000);
+        }
+        reply
+    }
+
+    #[test]
+    fn fully_played_reply_is_heard_in_full() {
+        let mut reply = reply(&["你好，", "今天天气不错。"]);
+        reply.add_played(Some(0), 1000);
+        reply.add_played(Some(1), 1000);
+        assert_eq!(reply.heard_text(), "你好，今天天气不错。");
+        assert_eq!(reply.played_fraction(), 1.0);
+    }
+
+    #[test]
+    fn cut_off_chunk_keeps_its_played_share() {
+        let mut reply = reply(&["第一句。", "二二二二", "第三句。"]);
+        reply.add_played(Some(0), 1000);
+        reply.add_played(Some(1), 500);
+        assert_eq!(reply.heard_text(), "第一句。二二");
+        assert_eq!(reply.played_fraction(), 0.5);
+    }
+
+    #[test]
+    fn announced_audio_counts_before_it_is_queued() {
+        let mut reply = SpokenReply::new();
+        reply.add_text(0, "abcd");
+        reply.expect_audio(0, 4000);
+        reply.add_queued(Some(0), 1000);
+        reply.add_played(Some(0), 1000);
+        assert_eq!(reply.heard_text(), "a");
+    }
+
+    #[test]
+    fn silent_chunks_do_not_stop_the_count() {
+        let mut reply = reply(&["好。"]);
+        reply.add_text(1, "……");
+        reply.expect_audio(1, 0);
+        reply.add_text(2, "再见");
+        reply.add_queued(Some(2), 1000);
+        reply.add_played(Some(0), 1000);
+        reply.add_played(Some(2), 1000);
+        assert_eq!(reply.heard_text(), "好。……再见");
+    }
+
+    #[test]
+    fn chunks_group_into_sentences() {
+        let reply = reply(&["你好，", "今天天气不错。", "要出门吗？", "带把伞，", "可能"]);
+        assert_eq!(reply.sentence_of(0), 0..2);
+        assert_eq!(reply.sentence_of(1), 0..2);
+        assert_eq!(reply.sentence_of(2), 2..3);
+        assert_eq!(reply.sentence_of(3), 3..5, "still growing");
+        assert_eq!(reply.sentence_of(7), 3..8, "not announced yet");
+    }
+
+    #[test]
+    fn nothing_played_means_nothing_heard() {
+        let reply = reply(&["还没播放"]);
+        assert_eq!(reply.heard_text(), "");
+        assert_eq!(SpokenReply::new().played_fraction(), 0.0);
+    }
+
+    #[test]
+    fn untagged_audio_maps_onto_the_whole_text() {
+        let mut reply = SpokenReply::new();
+        reply.add_text(0, "abcd");
+        reply.add_text(1, "efgh");
+        reply.add_queued(None, 800);
+        reply.add_played(None, 400);
+        assert_eq!(reply.heard_text(), "abcd");
+    }
+
+    #[test]
+    fn text_only_reply_was_seen_in_full() {
+        let mut reply = SpokenReply::new();
+        reply.add_text(0, "只有文字");
+        reply.set_text_only();
+        assert_eq!(reply.heard_text(), "只有文字");
+    }
+}
@@ -1,116 +1,120 @@
-//! Shared state and events for the voice service session.
-//!
-//! SERVICE_ACTIVE covers the whole voice service (capture stream + ASR link),
-//! MIC_OPEN only gates whether captured frames are forwarded to ASR, and
-//! AUTO_VAD switches from manual mic toggling to hands-free voice detection.
+//! Shared types and events for the voice service session. The session
+//! state itself lives on the `VoiceService` (see `audio::service`).
 
-use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, Mutex, OnceLock};
-use tauri::Emitter;
-use tokio::sync::mpsc;
+use ts_rs::TS;
+
+use crate::audio::asr_protocol::TranscriptDetails;
+use crate::audio::earcon::Earcon;
+use crate::audio::service::voice_service;
+use crate::conversation::{set_current_turn_status, ConversationStatus};
+use crate::events::{
+    self, ErrorEvent, ServiceStatus, ServiceStatusEvent, Severity, VadEvent, VadStatus,
+};
 
 pub const TARGET_SAMPLE_RATE: u32 = 16000;
 pub const ASR_HOST: &str = "ws://127.0.0.1:8765";
 pub const TTS_HOST: &str = "ws://127.0.0.1:8766";
 pub const AUDIO_FRAME_SIZE: usize = 640; // 20ms at 16kHz mono = 320 samples * 2 bytes
 
-#[derive(Clone, serde::Serialize)]
-pub struct VadEvent {
-    pub status: String,
-}
-
-#[derive(Clone, serde::Serialize)]
-pub struct AudioLevel {
-    pub level: f32,
-}
-
-#[derive(Clone, serde::Serialize, serde::Deserialize, Debug)]
+#[derive(Clone, serde::Serialize, serde::Deserialize, Debug, TS)]
 pub struct AsrTranscript {
+    /// The utterance this text belongs to (see `audio::utterance`).
+    #[serde(default)]
+    pub utterance_id: Option<usize>,
     pub partial: String,
     #[serde(rename = "final")]
     pub final_text: Option<String>,
     pub confidence: f32,
+    /// Word timings, per-word confidence, n-best and detected language,
+    /// when the ASR server provides them.
+    #[serde(flatten)]
+    pub details: TranscriptDetails,
+}
+
+/// Who decides where an utterance starts and ends.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize, TS)]
+#[serde(rename_all = "lowercase")]
+pub enum EndpointingMode {
+    /// The user opens and closes the mic.
+    Manual,
+    /// Hands-free, with the local `AutoVad`.
+    Client,
+    /// Hands-free, audio streams continuously and the ASR server sends
+    /// `endpoint` messages.
+    Server,
 }
 
 /// Messages flowing from capture/mic-toggling to the persistent ASR session.
 /// Audio frames and utterance boundaries share ONE channel so their relative
 /// order is preserved (an EndUtterance must never overtake the tail frames).
+/// Session settings ride the same channel so they apply at a frame boundary.
 pub enum CaptureMsg {
     Frame(Vec<u8>),
     BeginUtterance,
     EndUtterance,
-    SetAutoVad(bool),
+    SetEndpointing(EndpointingMode),
+    SetVocabulary(Vec<String>),
+    /// Throw away the in-flight utterance on both sides; no final follows.
+    CancelUtterance,
 }
 
-pub static SERVICE_ACTIVE: AtomicBool = AtomicBool::new(false);
-pub static MIC_OPEN: AtomicBool = AtomicBool::new(false);
-pub static AUTO_VAD: AtomicBool = AtomicBool::new(false);
-// Component health, aggregated into ONE user-facing pipeline status.
-pub static ASR_OK: AtomicBool = AtomicBool::new(false);
-pub static TTS_OK: AtomicBool = AtomicBool::new(false);
-
-static PIPE_TX: OnceLock<Arc<Mutex<Option<mpsc::UnboundedSender<CaptureMsg>>>>> = OnceLock::new();
-static CAPTURE_SHUTDOWN: OnceLock<Arc<Mutex<Option<std::sync::mpsc::Sender<()>>>>> =
-    OnceLock::new();
-
-pub fn get_pipe_tx() -> &'static Arc<Mutex<Option<mpsc::UnboundedSender<CaptureMsg>>>> {
-    PIPE_TX.get_or_init(|| Arc::new(Mutex::new(None)))
+pub fn emit_service_status(app: &tauri::AppHandle, status: ServiceStatus, message: &str) {
+    events::emit(
+        app,
+        ServiceStatusEvent {
+            status,
+            message: message.to_string(),
+        },
+    );
 }
 
-pub fn get_capture_shutdown() -> &'static Arc<Mutex<Option<std::sync::mpsc::Sender<()>>>> {
-    CAPTURE_SHUTDOWN.get_or_init(|| Arc::new(Mutex::new(None)))
+pub fn emit_error(app: &tauri::AppHandle, code: &str, message: &str) {
+    emit_notice(app, code, message, Severity::Error);
+    // Without an output stream the cue can't be heard, and would only try
+    // to open one again
+    if code != "PLAYBACK_FAILED" {
+        voice_service(app).playback.play_earcon(app, Earcon::Error);
+    }
 }
 
-pub fn emit_service_status(app: &tauri::AppHandle, status: &str, message: &str) {
-    let _ = app.emit(
-        "voice_assistant:service_status",
-        serde_json::json!({ "status": status, "message": message }),
-    );
+pub fn emit_warning(app: &tauri::AppHandle, code: &str, message: &str) {
+    emit_notice(app, code, message, Severity::Warning);
 }
 
-/// Derive the ONE status the user sees from component health. The pipeline
-/// is usable as long as ASR works: without TTS we degrade to text-only
-/// replies instead of failing, and recover automatically when TTS returns.
-pub fn emit_pipeline_status(app: &tauri::AppHandle) {
-    use std::sync::atomic::Ordering;
-
-    if !SERVICE_ACTIVE.load(Ordering::SeqCst) || !ASR_OK.load(Ordering::SeqCst) {
-        return; // transitional states are emitted by the ASR session itself
-    }
-
-    if TTS_OK.load(Ordering::SeqCst) {
-        emit_service_status(app, "ready", "语音服务就绪");
-    } else {
-        emit_service_status(
-            app,
-            "degraded",
-            "语音合成未连接：回复将只显示文字，不播放语音。启动 TTS 服务（端口 8766）后自动恢复。",
-        );
-    }
+fn emit_notice(app: &tauri::AppHandle, code: &str, message: &str, severity: Severity) {
+    events::emit(
+        app,
+        ErrorEvent {
+            code: code.to_string(),
+            message: message.to_string(),
+            severity,
+        },
+    );
 }
 
+/// The cue plays into a live mic; its echo cannot start another utterance
+/// because the VAD raises its threshold while an earcon is audible.
 pub fn emit_speech_start(app: &tauri::AppHandle) {
-    let _ = app.emit(
-        "voice_assistant:vad_status",
+    voice_service(app)
+        .playback
+        .play_earcon(app, Earcon::SpeechStart);
+    events::emit(
+        app,
         VadEvent {
-            status: "speech_start".to_string(),
+            status: VadStatus::SpeechStart,
         },
     );
-    let _ = app.emit(
-        "voice_assistant:state_changed",
-        serde_json::json!({ "state": "Listening" }),
-    );
 }
 
 pub fn emit_speech_end(app: &tauri::AppHandle) {
-    let _ = app.emit(
-        "voice_assistant:vad_status",
+    set_current_turn_status(app, ConversationStatus::FinalizingASR);
+    voice_service(app)
+        .playback
+        .play_earcon(app, Earcon::SpeechEnd);
+    events::emit(
+        app,
         VadEvent {
-            status: "speech_end".to_string(),
+            status: VadStatus::SpeechEnd,
         },
     );
-    let _ = app.emit(
-        "voice_assistant:state_changed",
-        serde_json::json!({ "state": "FinalizingASR" }),
-    );
 }
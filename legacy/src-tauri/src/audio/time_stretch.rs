@@ -0,0 +1,275 @@
+//! Playback speed that keeps the voice's pitch (WSOLA).
+//!
+//! Output is built from 20ms segments of the input, each cross-faded into
+//! the next over half its length. Segments are taken from the input `rate`
+//! times as far apart as they are laid down in the output, so speech plays
+//! faster or slower at the same pitch. Each is moved by up to SEARCH_MS to
+//! where it best continues the segment before it, which keeps the
+//! cross-fades from cancelling out.
+//!
+//! At rate 1 audio passes through untouched. Going back to rate 1, or
+//! running out of input, plays what the last segment was fading into as is,
+//! so neither clicks.
+
+/// Half a segment: the cross-fade, and how far apart segments are laid down.
+const OVERLAP_MS: u32 = 10;
+/// How far a segment may move from where the rate puts it.
+const SEARCH_MS: u32 = 5;
+
+pub const MIN_RATE: f32 = 0.5;
+pub const MAX_RATE: f32 = 2.0;
+
+pub struct TimeStretcher {
+    rate: f32,
+    /// (sample rate, channels) of the input; a change starts over.
+    format: (u32, u16),
+    /// Interleaved input not yet played.
+    input: Vec<f32>,
+    /// The input mixed to mono, for the search.
+    mono: Vec<f32>,
+    /// The input frame that continues what has been output: the second half
+    /// of the last segment, which is fading out.
+    continuation: Option<usize>,
+    /// Where the next segment starts before the search, in input frames.
+    next: f64,
+}
+
+impl Default for TimeStretcher {
+    fn default() -> Self {
+        Self {
+            rate: 1.0,
+            format: (0, 0),
+            input: Vec::new(),
+            mono: Vec::new(),
+            continuation: None,
+            next: 0.0,
+        }
+    }
+}
+
+impl TimeStretcher {
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    /// In [MIN_RATE, MAX_RATE]; applies from the next input on.
+    pub fn set_rate(&mut self, rate: f32) {
+        self.rate = rate;
+    }
+
+    /// Drop the input held back, keeping the rate.
+    pub fn reset(&mut self) {
+        *self = Self {
+            rate: self.rate,
+            ..Self::default()
+        };
+    }
+
+    /// Stretch interleaved samples. Up to about a segment of input is held
+    /// back for the search.
+    pub fn process(&mut self, samples: &[f32], format: (u32, u16)) -> Vec<f32> {
+        if format != self.format {
+            self.reset();
+            self.format = format;
+        }
+        if self.rate == 1.0 {
+            let mut output = self.finish();
+            output.extend_from_slice(samples);
+            return output;
+        }
+
+        let channels = format.1 as usize;
+        self.input.extend_from_slice(samples);
+        self.mono.extend(
+            samples
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>()),
+        );
+        self.stretch()
+    }
+
+    /// The input held back, played as is.
+    pub fn finish(&mut self) -> Vec<f32> {
+        let channels = self.format.1 as usize;
+        let start = self.continuation.unwrap_or(0) * channels;
+        let output = self.input.get(start..).unwrap_or_default().to_vec();
+        self.input.clear();
+        self.mono.clear();
+        self.continuation = None;
+        self.next = 0.0;
+        output
+    }
+
+    fn stretch(&mut self) -> Vec<f32> {
+        let (sample_rate, channels) = self.format;
+        let channels = channels as usize;
+        let overlap = (sample_rate * OVERLAP_MS / 1000) as usize;
+        let search = (sample_rate * SEARCH_MS / 1000) as usize;
+        let mut output = Vec::new();
+        if overlap == 0 {
+            return output;
+        }
+
+        loop {
+            let frames = self.mono.len();
+            let Some(continuation) = self.continuation else {
+                // The first segment starts with the input: its first half
+                // plays as is
+                if frames < overlap {
+                    break;
+                }
+                output.extend_from_slice(&self.input[..overlap * channels]);
+                self.continuation = Some(overlap);
+                self.next = overlap as f64 * self.rate as f64;
+                continue;
+            };
+
+            let nominal = self.next.round() as usize;
+            let (lo, hi) = (nominal.saturating_sub(search), nominal + search);
+            if hi.max(continuation) + overlap > frames {
+                break;
+            }
+            let start = self.best_match(continuation, nominal, lo..=hi, overlap);
+
+            for i in 0..overlap {
+                let fade = (std::f32::consts::FRAC_PI_2 * (i as f32 + 0.5) / overlap as f32)
+                    .sin()
+                    .powi(2);
+                let (old, new) = ((continuation + i) * channels, (start + i) * channels);
+                for c in 0..channels {
+                    output.push(self.input[old + c] * (1.0 - fade) + self.input[new + c] * fade);
+                }
+            }
+            self.next += overlap as f64 * self.rate as f64;
+
+            // Drop the input no later segment can reach
+            let continuation = start + overlap;
+            let done = (self.next.round() as usize)
+                .saturating_sub(search)
+                .min(continuation);
+            self.input.drain(..done * channels);
+            self.mono.drain(..done);
+            self.continuation = Some(continuation - done);
+            self.next -= done as f64;
+        }
+        output
+    }
+
+    /// The segment start in `range` whose first `len` frames best match
+    /// those at `target`; `nominal` unless another matches better.
+    fn best_match(
+        &self,
+        target: usize,
+        nominal: usize,
+        range: std::ops::RangeInclusive<usize>,
+        len: usize,
+    ) -> usize {
+        let target = &self.mono[target..target + len];
+        let score = |start: usize| {
+            let candidate = &self.mono[start..start + len];
+            let (mut correlation, mut energy) = (0.0, 0.0);
+            for (a, b) in candidate.iter().zip(target) {
+                correlation += a * b;
+                energy += a * a;
+            }
+            if energy > 0.0 {
+                correlation / f32::sqrt(energy)
+            } else {
+                0.0
+            }
+        };
+
+        let mut best = (nominal, score(nominal));
+        for start in range {
+            let candidate = score(start);
+            if candidate > best.1 {
+                best = (start, candidate);
+            }
+        }
+        best.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16000;
+
+    /// `seconds` of a 440Hz sine at half scale, in 20ms frames.
+    fn sine(seconds: f32) -> Vec<Vec<f32>> {
+        let samples: Vec<f32> = (0..(seconds * RATE as f32) as usize)
+            .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / RATE as f32).sin())
+            .collect();
+        samples.chunks(320).map(<[f32]>::to_vec).collect()
+    }
+
+    fn stretch(stretcher: &mut TimeStretcher, frames: &[Vec<f32>]) -> Vec<f32> {
+        let mut output = Vec::new();
+        for frame in frames {
+            output.extend(stretcher.process(frame, (RATE, 1)));
+        }
+        output
+    }
+
+    /// Upward zero crossings per second.
+    fn frequency(samples: &[f32]) -> f32 {
+        let crossings = samples
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count();
+        crossings as f32 * RATE as f32 / samples.len() as f32
+    }
+
+    /// The largest step from one sample to the next.
+    fn largest_step(samples: &[f32]) -> f32 {
+        samples
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn rate_one_passes_audio_through() {
+        let mut stretcher = TimeStretcher::default();
+        let input = sine(0.1);
+        assert_eq!(stretch(&mut stretcher, &input), input.concat());
+    }
+
+    #[test]
+    fn length_follows_the_rate_and_pitch_does_not() {
+        for rate in [MIN_RATE, 0.8, 1.25, MAX_RATE] {
+            let mut stretcher = TimeStretcher::default();
+            stretcher.set_rate(rate);
+            let mut output = stretch(&mut stretcher, &sine(1.0));
+            output.extend(stretcher.finish());
+
+            let expected = RATE as f32 / rate;
+            let error = (output.len() as f32 - expected).abs() / expected;
+            assert!(error < 0.02, "{}x: {} samples", rate, output.len());
+            let frequency = frequency(&output);
+            assert!(
+                (frequency - 440.0).abs() < 10.0,
+                "{}x: {}Hz",
+                rate,
+                frequency
+            );
+        }
+    }
+
+    #[test]
+    fn changing_the_rate_does_not_click() {
+        // A 440Hz sine at half scale steps by at most 0.087 a sample
+        let input = sine(0.6);
+        let mut stretcher = TimeStretcher::default();
+        let mut output = stretch(&mut stretcher, &input[..10]);
+        stretcher.set_rate(1.5);
+        output.extend(stretch(&mut stretcher, &input[10..20]));
+        stretcher.set_rate(0.5);
+        output.extend(stretch(&mut stretcher, &input[20..25]));
+        stretcher.set_rate(1.0);
+        output.extend(stretch(&mut stretcher, &input[25..]));
+
+        assert!(largest_step(&output) < 0.1, "{}", largest_step(&output));
+    }
+}
@@ -0,0 +1,149 @@
+//! The audio the TTS server sends.
+//!
+//! Each `audio_start` announces the format of the frames that follow:
+//! `sample_rate`, `channels` and `encoding` (`pcm_s16le` or `pcm_f32le`).
+//! A field left out keeps the original 16kHz mono s16le, so older servers
+//! work unchanged.
+//!
+//! The encoding may also be a compressed file (`wav`, `mp3`, `ogg` with
+//! Vorbis or Opus, or `opus` in Ogg), whose frames are pieces of one stream
+//! decoded by `tts_decoder`; the file's own rate and channels then win over
+//! the announced ones.
+
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleEncoding {
+    PcmS16le,
+    PcmF32le,
+    Wav,
+    Mp3,
+    Ogg,
+    Opus,
+}
+
+impl SampleEncoding {
+    pub fn is_pcm(self) -> bool {
+        matches!(self, Self::PcmS16le | Self::PcmF32le)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub encoding: SampleEncoding,
+}
+
+impl Default for AudioFormat {
+    fn default() -> Self {
+        Self {
+            sample_rate: 16000,
+            channels: 1,
+            encoding: SampleEncoding::PcmS16le,
+        }
+    }
+}
+
+impl AudioFormat {
+    /// The format an `audio_start` message announces.
+    pub fn from_announcement(message: &serde_json::Value) -> Result<Self, String> {
+        let format = Self::deserialize(message)
+            .map_err(|e| format!("Unsupported TTS audio format: {}", e))?;
+        if format.sample_rate == 0 || format.channels == 0 {
+            return Err(format!(
+                "Unsupported TTS audio format: {}Hz, {} channels",
+                format.sample_rate, format.channels
+            ));
+        }
+        Ok(format)
+    }
+
+    /// Interleaved samples in [-1, 1]. Only raw PCM decodes here; compressed
+    /// audio gives nothing.
+    pub fn decode(&self, bytes: &[u8]) -> Vec<f32> {
+        match self.encoding {
+            SampleEncoding::PcmS16le => bytes
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+                .collect(),
+            SampleEncoding::PcmF32le => bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]).clamp(-1.0, 1.0))
+                .collect(),
+            SampleEncoding::Wav
+            | SampleEncoding::Mp3
+            | SampleEncoding::Ogg
+            | SampleEncoding::Opus => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn announcement_without_a_format_is_the_legacy_one() {
+        let format =
+            AudioFormat::from_announcement(&json!({ "type": "audio_start", "text_id": 0 }))
+                .unwrap();
+        assert_eq!(format, AudioFormat::default());
+    }
+
+    #[test]
+    fn announced_format_is_read() {
+        let format = AudioFormat::from_announcement(&json!({
+            "type": "audio_start",
+            "sample_rate": 24000,
+            "channels": 2,
+            "encoding": "pcm_f32le",
+        }))
+        .unwrap();
+        assert_eq!(
+            format,
+            AudioFormat {
+                sample_rate: 24000,
+                channels: 2,
+                encoding: SampleEncoding::PcmF32le,
+            }
+        );
+
+        let format = AudioFormat::from_announcement(&json!({ "encoding": "mp3" })).unwrap();
+        assert_eq!(format.encoding, SampleEncoding::Mp3);
+        assert!(!format.encoding.is_pcm());
+
+        let format = AudioFormat::from_announcement(&json!({ "encoding": "opus" })).unwrap();
+        assert_eq!(format.encoding, SampleEncoding::Opus);
+    }
+
+    #[test]
+    fn unknown_or_empty_formats_are_refused() {
+        assert!(AudioFormat::from_announcement(&json!({ "encoding": "aac" })).is_err());
+        assert!(AudioFormat::from_announcement(&json!({ "sample_rate": 0 })).is_err());
+        assert!(AudioFormat::from_announcement(&json!({ "channels": 0 })).is_err());
+    }
+
+    #[test]
+    fn decodes_both_encodings() {
+        let s16 = AudioFormat::default();
+        let bytes: Vec<u8> = [16384i16, -32768]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        assert_eq!(s16.decode(&bytes), vec![0.5, -1.0]);
+
+        let f32le = AudioFormat {
+            encoding: SampleEncoding::PcmF32le,
+            ..AudioFormat::default()
+        };
+        let bytes: Vec<u8> = [0.25f32, 2.0]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        assert_eq!(f32le.decode(&bytes), vec![0.25, 1.0]);
+    }
+}
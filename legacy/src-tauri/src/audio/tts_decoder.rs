@@ -0,0 +1,585 @@
+//! Compressed TTS audio (WAV, MP3, Ogg Vorbis, Ogg Opus), decoded in pure
+//! Rust. Symphonia reads the files and decodes all but Opus, which has its
+//! own decoder.
+//!
+//! A TTS connection sends each chunk's file in pieces, so a `StreamDecoder`
+//! decodes the chunks of one connection on its own thread, in order, as the
+//! pieces arrive. A complete file, such as one queued from the webview, is
+//! decoded at once with `decode`.
+//!
+//! Decoded audio carries the encoded bytes it came from (a packet's size;
+//! headers and undecodable packets are added after the last packet), so the
+//! reply's progress stays counted in the bytes that were received.
+
+use std::io::{self, Cursor, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+
+use futures::channel::oneshot;
+use opus_decoder::OpusDecoder;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_OPUS};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, Packet};
+use symphonia::core::io::{MediaSource, MediaSourceStream, ReadOnlySource};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::audio::tts_audio::SampleEncoding;
+
+/// Opus always decodes at 48kHz.
+const OPUS_RATE: u32 = 48000;
+/// Frames in the longest Opus packet (120ms).
+const OPUS_MAX_FRAMES: usize = 5760;
+
+pub struct DecodedAudio {
+    /// Interleaved, in [-1, 1].
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Encoded bytes this audio was decoded from.
+    pub bytes: usize,
+}
+
+/// Decode a complete file.
+pub fn decode(
+    data: Vec<u8>,
+    encoding: SampleEncoding,
+    mut on_audio: impl FnMut(DecodedAudio),
+) -> Result<(), String> {
+    let total = data.len();
+    let mut attributed = 0;
+    let result = decode_stream(Box::new(Cursor::new(data)), encoding, &mut |audio| {
+        attributed += audio.bytes;
+        on_audio(audio);
+    });
+    on_audio(remainder(total, attributed));
+    result
+}
+
+enum Input<T> {
+    Start(SampleEncoding, T),
+    Data(Vec<u8>),
+}
+
+/// Decodes the streams of one connection on its own thread. Each `start`
+/// ends the previous stream and begins the next one; its tag is handed back
+/// with the stream's audio.
+///
+/// Dropping the decoder cancels it: audio still being decoded is
+/// discarded. `finish` waits for it instead.
+pub struct StreamDecoder<T> {
+    tx: Option<mpsc::Sender<Input<T>>>,
+    cancelled: Arc<AtomicBool>,
+    /// Closed when the thread ends.
+    done: Option<oneshot::Receiver<()>>,
+}
+
+impl<T: Send + 'static> StreamDecoder<T> {
+    pub fn spawn(
+        mut on_audio: impl FnMut(&T, DecodedAudio) + Send + 'static,
+        mut on_error: impl FnMut(&T, String) + Send + 'static,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let feed = Arc::new(Mutex::new(Feed {
+            rx,
+            next: None,
+            open: false,
+            received: 0,
+        }));
+
+        let (done_tx, done) = oneshot::channel::<()>();
+        let thread_cancelled = cancelled.clone();
+        std::thread::spawn(move || {
+            let _done = done_tx;
+            loop {
+                let Some((encoding, tag)) = feed.lock().unwrap().next_stream() else {
+                    break;
+                };
+                let reader = FeedReader {
+                    feed: feed.clone(),
+                    buf: Vec::new(),
+                    pos: 0,
+                };
+                let mut attributed = 0;
+                let result = decode_stream(
+                    Box::new(ReadOnlySource::new(reader)),
+                    encoding,
+                    &mut |audio| {
+                        attributed += audio.bytes;
+                        if !thread_cancelled.load(Ordering::SeqCst) {
+                            on_audio(&tag, audio);
+                        }
+                    },
+                );
+                // Whatever the decoder left of the stream still counts
+                let received = feed.lock().unwrap().skip_stream();
+                if thread_cancelled.load(Ordering::SeqCst) {
+                    continue;
+                }
+                on_audio(&tag, remainder(received, attributed));
+                if let Err(e) = result {
+                    on_error(&tag, e);
+                }
+            }
+        });
+
+        Self {
+            tx: Some(tx),
+            cancelled,
+            done: Some(done),
+        }
+    }
+
+    /// Begin a stream; the pieces pushed next belong to it.
+    pub fn start(&self, encoding: SampleEncoding, tag: T) {
+        self.send(Input::Start(encoding, tag));
+    }
+
+    pub fn push(&self, data: Vec<u8>) {
+        self.send(Input::Data(data));
+    }
+
+    /// End the last stream and wait until all of it is decoded.
+    pub async fn finish(&mut self) {
+        self.tx = None;
+        if let Some(done) = self.done.take() {
+            let _ = done.await;
+        }
+    }
+
+    fn send(&self, input: Input<T>) {
+        if let Some(tx) = self.tx.as_ref() {
+            let _ = tx.send(input);
+        }
+    }
+}
+
+impl<T> Drop for StreamDecoder<T> {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+/// What the decoder thread has been sent.
+struct Feed<T> {
+    rx: mpsc::Receiver<Input<T>>,
+    /// The stream that ended the current one.
+    next: Option<(SampleEncoding, T)>,
+    /// A stream is being read.
+    open: bool,
+    /// Bytes of the current stream read so far.
+    received: usize,
+}
+
+impl<T> Feed<T> {
+    /// Wait for the next stream; None once the decoder is finished.
+    fn next_stream(&mut self) -> Option<(SampleEncoding, T)> {
+        self.skip_stream();
+        let next = match self.next.take() {
+            Some(next) => next,
+            // Pieces without a stream have nothing to decode them with
+            None => loop {
+                match self.rx.recv().ok()? {
+                    Input::Start(encoding, tag) => break (encoding, tag),
+                    Input::Data(_) => {}
+                }
+            },
+        };
+        self.open = true;
+        self.received = 0;
+        Some(next)
+    }
+
+    /// The next piece of the current stream; None at its end.
+    fn next_data(&mut self) -> Option<Vec<u8>> {
+        if !self.open {
+            return None;
+        }
+        match self.rx.recv() {
+            Ok(Input::Data(data)) => {
+                self.received += data.len();
+                Some(data)
+            }
+            Ok(Input::Start(encoding, tag)) => {
+                self.next = Some((encoding, tag));
+                self.open = false;
+                None
+            }
+            Err(_) => {
+                self.open = false;
+                None
+            }
+        }
+    }
+
+    /// Read past the rest of the current stream. Returns its size.
+    fn skip_stream(&mut self) -> usize {
+        while self.next_data().is_some() {}
+        self.received
+    }
+}
+
+/// The current stream of a feed, as a blocking reader.
+struct FeedReader<T> {
+    feed: Arc<Mutex<Feed<T>>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<T> Read for FeedReader<T> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            match self.feed.lock().unwrap().next_data() {
+                Some(data) => {
+                    self.buf = data;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn decode_stream(
+    source: Box<dyn MediaSource>,
+    encoding: SampleEncoding,
+    on_audio: &mut dyn FnMut(DecodedAudio),
+) -> Result<(), String> {
+    let extension = match encoding {
+        SampleEncoding::Wav => "wav",
+        SampleEncoding::Mp3 => "mp3",
+        SampleEncoding::Ogg => "ogg",
+        SampleEncoding::Opus => "opus",
+        SampleEncoding::PcmS16le | SampleEncoding::PcmF32le => {
+            return Err(format!("{:?} audio is not a compressed stream", encoding));
+        }
+    };
+
+    let mut hint = Hint::new();
+    hint.with_extension(extension);
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            MediaSourceStream::new(source, Default::default()),
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| format!("Unreadable {} TTS audio: {}", extension, e))?;
+    let mut format = probed.format;
+    let track = format
+        .default_track()
+        .ok_or_else(|| format!("No audio track in {} TTS audio", extension))?;
+    let track_id = track.id;
+    let mut decoder = if track.codec_params.codec == CODEC_TYPE_OPUS {
+        TrackDecoder::Opus(Box::new(OpusTrack::new(&track.codec_params)?))
+    } else {
+        TrackDecoder::Symphonia(
+            symphonia::default::get_codecs()
+                .make(&track.codec_params, &DecoderOptions::default())
+                .map_err(|e| format!("Unsupported {} TTS audio: {}", extension, e))?,
+        )
+    };
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Ok(());
+            }
+            Err(e) => return Err(format!("Failed to read {} TTS audio: {}", extension, e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        match decoder.decode(&packet) {
+            Ok(audio) => on_audio(audio),
+            Err(SymphoniaError::DecodeError(e)) => {
+                eprintln!("Skipping undecodable {} TTS packet: {}", extension, e);
+            }
+            Err(e) => return Err(format!("Failed to decode {} TTS audio: {}", extension, e)),
+        }
+    }
+}
+
+enum TrackDecoder {
+    Symphonia(Box<dyn Decoder>),
+    Opus(Box<OpusTrack>),
+}
+
+impl TrackDecoder {
+    fn decode(&mut self, packet: &Packet) -> Result<DecodedAudio, SymphoniaError> {
+        let bytes = packet.buf().len();
+        match self {
+            Self::Symphonia(decoder) => {
+                let decoded = decoder.decode(packet)?;
+                let spec = *decoded.spec();
+                let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                buffer.copy_interleaved_ref(decoded);
+                Ok(DecodedAudio {
+                    samples: buffer.samples().to_vec(),
+                    sample_rate: spec.rate,
+                    channels: spec.channels.count() as u16,
+                    bytes,
+                })
+            }
+            Self::Opus(track) => Ok(DecodedAudio {
+                samples: track.decode(packet.buf())?,
+                sample_rate: OPUS_RATE,
+                channels: track.channels,
+                bytes,
+            }),
+        }
+    }
+}
+
+/// An Opus track of an Ogg file, mono or stereo.
+struct OpusTrack {
+    decoder: OpusDecoder,
+    channels: u16,
+    /// Frames still to drop: the encoder's delay at the start.
+    skip: usize,
+    pcm: Vec<f32>,
+}
+
+impl OpusTrack {
+    fn new(params: &CodecParameters) -> Result<Self, String> {
+        let channels = params.channels.map_or(0, |c| c.count());
+        if !(1..=2).contains(&channels) {
+            return Err(format!("Unsupported opus TTS audio: {} channels", channels));
+        }
+        let decoder = OpusDecoder::new(OPUS_RATE, channels)
+            .map_err(|e| format!("Unsupported opus TTS audio: {}", e))?;
+        Ok(Self {
+            decoder,
+            channels: channels as u16,
+            skip: params.delay.unwrap_or(0) as usize,
+            pcm: vec![0.0; OPUS_MAX_FRAMES * channels],
+        })
+    }
+
+    fn decode(&mut self, packet: &[u8]) -> Result<Vec<f32>, SymphoniaError> {
+        let frames = self
+            .decoder
+            .decode_float(packet, &mut self.pcm, false)
+            .map_err(|_| SymphoniaError::DecodeError("invalid Opus packet"))?;
+        let skip = self.skip.min(frames);
+        self.skip -= skip;
+        let channels = self.channels as usize;
+        Ok(self.pcm[skip * channels..frames * channels].to_vec())
+    }
+}
+
+/// The bytes of a stream no decoded audio accounts for, as audio with no
+/// samples.
+fn remainder(received: usize, attributed: usize) -> DecodedAudio {
+    DecodedAudio {
+        samples: Vec::new(),
+        sample_rate: 16000,
+        channels: 1,
+        bytes: received.saturating_sub(attributed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 16-bit PCM WAV file.
+    fn wav(samples: &[i16], sample_rate: u32, channels: u16) -> Vec<u8> {
+        let data_len = (samples.len() * 2) as u32;
+        let mut file = Vec::new();
+        file.extend_from_slice(b"RIFF");
+        file.extend_from_slice(&(36 + data_len).to_le_bytes());
+        file.extend_from_slice(b"WAVEfmt ");
+        file.extend_from_slice(&16u32.to_le_bytes());
+        file.extend_from_slice(&1u16.to_le_bytes());
+        file.extend_from_slice(&channels.to_le_bytes());
+        file.extend_from_slice(&sample_rate.to_le_bytes());
+        file.extend_from_slice(&(sample_rate * channels as u32 * 2).to_le_bytes());
+        file.extend_from_slice(&(channels * 2).to_le_bytes());
+        file.extend_from_slice(&16u16.to_le_bytes());
+        file.extend_from_slice(b"data");
+        file.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            file.extend_from_slice(&sample.to_le_bytes());
+        }
+        file
+    }
+
+    /// An Ogg page holding whole packets.
+    fn ogg_page(packets: &[&[u8]], header_type: u8, granule: u64, sequence: u32) -> Vec<u8> {
+        let mut page = b"OggS\0".to_vec();
+        page.push(header_type);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&1u32.to_le_bytes());
+        page.extend_from_slice(&sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        let lacing: Vec<u8> = packets
+            .iter()
+            .flat_map(|p| {
+                let mut lacing = vec![255; p.len() / 255];
+                lacing.push((p.len() % 255) as u8);
+                lacing
+            })
+            .collect();
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(&lacing);
+        for packet in packets {
+            page.extend_from_slice(packet);
+        }
+        let crc = page.iter().fold(0u32, |crc, &byte| {
+            (0..8).fold(crc ^ (byte as u32) << 24, |crc, _| {
+                if crc & 0x8000_0000 != 0 {
+                    crc << 1 ^ 0x04c1_1db7
+                } else {
+                    crc << 1
+                }
+            })
+        });
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    /// An Ogg Opus file of `packets` mono 20ms packets, with `pre_skip`.
+    fn ogg_opus(packets: usize, pre_skip: u16) -> Vec<u8> {
+        let mut head = b"OpusHead".to_vec();
+        head.extend_from_slice(&[1, 1]);
+        head.extend_from_slice(&pre_skip.to_le_bytes());
+        head.extend_from_slice(&48000u32.to_le_bytes());
+        head.extend_from_slice(&[0, 0, 0]);
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&[0; 8]);
+
+        let mut file = ogg_page(&[&head], 0x02, 0, 0);
+        file.extend(ogg_page(&[&tags], 0, 0, 1));
+        // CELT fullband 20ms mono, code 0 with an empty frame: silence
+        let packet: &[u8] = &[0xf8];
+        let audio = vec![packet; packets];
+        let granule = (packets * 960) as u64;
+        file.extend(ogg_page(&audio, 0x04, granule, 2));
+        file
+    }
+
+    fn ramp(len: usize) -> Vec<i16> {
+        (0..len).map(|i| (i as i16) * 8).collect()
+    }
+
+    #[test]
+    fn decodes_a_wav_file() {
+        let samples = ramp(2400);
+        let file = wav(&samples, 24000, 2);
+        let mut decoded = Vec::new();
+        let mut bytes = 0;
+        decode(file.clone(), SampleEncoding::Wav, |audio| {
+            bytes += audio.bytes;
+            if !audio.samples.is_empty() {
+                assert_eq!((audio.sample_rate, audio.channels), (24000, 2));
+            }
+            decoded.extend(audio.samples);
+        })
+        .unwrap();
+
+        let expected: Vec<f32> = samples.iter().map(|&s| s as f32 / 32768.0).collect();
+        assert_eq!(decoded, expected);
+        assert_eq!(bytes, file.len());
+    }
+
+    #[test]
+    fn decodes_an_ogg_opus_file() {
+        let file = ogg_opus(5, 312);
+        for encoding in [SampleEncoding::Opus, SampleEncoding::Ogg] {
+            let mut frames = 0;
+            let mut bytes = 0;
+            decode(file.clone(), encoding, |audio| {
+                bytes += audio.bytes;
+                if !audio.samples.is_empty() {
+                    assert_eq!((audio.sample_rate, audio.channels), (48000, 1));
+                }
+                frames += audio.samples.len();
+            })
+            .unwrap();
+
+            // The encoder's delay is dropped
+            assert_eq!(frames, 5 * 960 - 312);
+            assert_eq!(bytes, file.len());
+        }
+    }
+
+    #[test]
+    fn garbage_is_an_error_but_still_counted() {
+        let mut bytes = 0;
+        let result = decode(vec![7; 500], SampleEncoding::Mp3, |audio| {
+            bytes += audio.bytes;
+        });
+        assert!(result.is_err());
+        assert_eq!(bytes, 500);
+    }
+
+    #[test]
+    fn streams_decode_in_order_from_pieces() {
+        let (tx, rx) = mpsc::channel();
+        let errors = tx.clone();
+        let mut decoder = StreamDecoder::spawn(
+            move |tag: &usize, audio| {
+                let _ = tx.send((*tag, audio.samples.len(), audio.bytes));
+            },
+            move |tag, _| {
+                let _ = errors.send((*tag, usize::MAX, 0));
+            },
+        );
+
+        let first = wav(&ramp(1600), 16000, 1);
+        let second = wav(&ramp(800), 16000, 1);
+        decoder.start(SampleEncoding::Wav, 0);
+        for piece in first.chunks(333) {
+            decoder.push(piece.to_vec());
+        }
+        decoder.start(SampleEncoding::Mp3, 1);
+        decoder.push(vec![7; 100]);
+        decoder.start(SampleEncoding::Wav, 2);
+        for piece in second.chunks(100) {
+            decoder.push(piece.to_vec());
+        }
+        futures::executor::block_on(decoder.finish());
+
+        let received: Vec<(usize, usize, usize)> = rx.iter().collect();
+        let total = |tag: usize| -> (usize, usize) {
+            received
+                .iter()
+                .filter(|(t, samples, _)| *t == tag && *samples != usize::MAX)
+                .fold((0, 0), |(s, b), (_, samples, bytes)| {
+                    (s + samples, b + bytes)
+                })
+        };
+        assert_eq!(total(0), (1600, first.len()));
+        assert_eq!(total(1), (0, 100));
+        assert_eq!(total(2), (800, second.len()));
+        assert!(received.contains(&(1, usize::MAX, 0)));
+        // In order: no audio of a stream after the next one began
+        let tags: Vec<usize> = received.iter().map(|(t, _, _)| *t).collect();
+        assert!(tags.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn dropping_the_decoder_discards_its_audio() {
+        let (tx, rx) = mpsc::channel();
+        let decoder = StreamDecoder::spawn(
+            move |_: &(), audio: DecodedAudio| {
+                let _ = tx.send(audio.bytes);
+            },
+            |_, _| {},
+        );
+        decoder.start(SampleEncoding::Wav, ());
+        decoder.push(vec![0; 10]);
+        drop(decoder);
+        // The thread ends once the channel closes, having sent nothing
+        assert!(rx.recv().is_err());
+    }
+}
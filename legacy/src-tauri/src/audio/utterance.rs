@@ -0,0 +1,385 @@
+//! Utterance identity for the ASR session.
+//!
+//! Every BeginUtterance allocates an ID that is sent to the server, carried
+//! on every `user_transcript`/`utterance_final` event and recorded on the
+//! conversation `Turn`. Servers echo the ID back; for servers that don't,
+//! finals are matched to ended utterances in FIFO order.
+//!
+//! Each ended utterance gets a finalization deadline. A final that misses it
+//! is given up on (the session falls back to the last partial) and is
+//! swallowed if it turns up later. Without its ID it can only be told apart
+//! until the next utterance ends; after that the server is assumed to have
+//! dropped it, so the next final still goes to the utterance it is for.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How many timed-out IDs to remember so their late finals are dropped.
+const TIMED_OUT_MEMORY: usize = 16;
+
+struct AwaitingFinal {
+    id: usize,
+    /// Cancelled after end_utterance went out: the server will still send
+    /// a final, which must be swallowed.
+    cancelled: bool,
+    deadline: Instant,
+    last_partial: String,
+}
+
+struct TimedOut {
+    id: usize,
+    /// No utterance has ended since: a final without an ID may be this one's.
+    unnumbered_late: bool,
+}
+
+/// An utterance whose final will never be delivered by the server.
+#[derive(Debug, PartialEq)]
+pub struct Unfinalized {
+    pub id: usize,
+    /// The latest partial text, the best we have in place of the final.
+    pub last_partial: String,
+}
+
+/// What to do with an `utterance_final` from the server.
+#[derive(Debug, PartialEq)]
+pub enum FinalMatch {
+    /// Deliver it, attributed to this utterance (None if unmatched).
+    Deliver(Option<usize>),
+    /// The user cancelled this utterance; drop it.
+    Discard(usize),
+}
+
+pub struct UtteranceTracker {
+    next_id: usize,
+    /// The utterance currently receiving audio.
+    open: Option<usize>,
+    open_partial: String,
+    /// Ended utterances still waiting for their final, oldest first.
+    awaiting_final: VecDeque<AwaitingFinal>,
+    /// Recently given up on; a late final for these is dropped.
+    timed_out: VecDeque<TimedOut>,
+    final_timeout: Duration,
+}
+
+impl UtteranceTracker {
+    pub fn new(final_timeout: Duration) -> Self {
+        Self {
+            next_id: 1,
+            open: None,
+            open_partial: String::new(),
+            awaiting_final: VecDeque::new(),
+            timed_out: VecDeque::new(),
+            final_timeout,
+        }
+    }
+
+    /// Allocate an ID for a new utterance. An utterance still open is
+    /// implicitly abandoned: its late partials will be dropped.
+    pub fn begin(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.open = Some(id);
+        self.open_partial.clear();
+        id
+    }
+
+    /// Close the open utterance; its final is now expected.
+    pub fn end(&mut self) -> Option<usize> {
+        let id = self.open.take()?;
+        for timed_out in &mut self.timed_out {
+            timed_out.unnumbered_late = false;
+        }
+        self.awaiting_final.push_back(AwaitingFinal {
+            id,
+            cancelled: false,
+            deadline: Instant::now() + self.final_timeout,
+            last_partial: std::mem::take(&mut self.open_partial),
+        });
+        Some(id)
+    }
+
+    /// Drop the open utterance before its end was sent; no final follows.
+    pub fn discard(&mut self) -> Option<usize> {
+        self.open.take()
+    }
+
+    /// Cancel the in-flight utterance: the open one if there is one,
+    /// otherwise the most recent one still being finalized.
+    pub fn cancel(&mut self) -> Option<usize> {
+        if let Some(id) = self.open.take() {
+            return Some(id);
+        }
+        let pending = self
+            .awaiting_final
+            .iter_mut()
+            .rev()
+            .find(|p| !p.cancelled)?;
+        pending.cancelled = true;
+        Some(pending.id)
+    }
+
+    /// Which utterance a partial belongs to, or None if it is stale (from
+    /// an utterance that is no longer open) and must be dropped. Accepted
+    /// text is kept as the fallback should the final never come.
+    pub fn accept_partial(&mut self, echoed_id: Option<usize>, text: &str) -> Option<usize> {
+        let id = match (echoed_id, self.open) {
+            (Some(id), Some(open)) if id == open => id,
+            (None, Some(open)) => open,
+            _ => return None,
+        };
+        self.open_partial = text.to_string();
+        Some(id)
+    }
+
+    /// Which utterance a final belongs to. Unknown or already-finalized IDs
+    /// are still delivered (the text was spoken), just not matched.
+    pub fn accept_final(&mut self, echoed_id: Option<usize>) -> FinalMatch {
+        // Without IDs finals come in order: those of timed-out utterances
+        // come first, unless a later utterance has ended since
+        let late = match echoed_id {
+            Some(id) => self.timed_out.iter().position(|t| t.id == id),
+            None => self.timed_out.iter().position(|t| t.unnumbered_late),
+        };
+        if let Some(pos) = late {
+            return FinalMatch::Discard(self.timed_out.remove(pos).unwrap().id);
+        }
+
+        let index = match echoed_id {
+            Some(id) => self.awaiting_final.iter().position(|p| p.id == id),
+            None if self.awaiting_final.is_empty() => None,
+            None => Some(0),
+        };
+
+        match index.and_then(|i| self.awaiting_final.remove(i)) {
+            Some(pending) if pending.cancelled => FinalMatch::Discard(pending.id),
+            Some(pending) => FinalMatch::Deliver(Some(pending.id)),
+            None => FinalMatch::Deliver(echoed_id),
+        }
+    }
+
+    /// The earliest finalization deadline still pending.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.awaiting_final
+            .iter()
+            .filter(|p| !p.cancelled)
+            .map(|p| p.deadline)
+            .min()
+    }
+
+    /// Give up on every utterance whose deadline has passed.
+    pub fn take_expired(&mut self, now: Instant) -> Vec<Unfinalized> {
+        let mut expired = Vec::new();
+        let mut i = 0;
+        while i < self.awaiting_final.len() {
+            if self.awaiting_final[i].deadline > now {
+                i += 1;
+                continue;
+            }
+            let pending = self.awaiting_final.remove(i).unwrap();
+            if self.timed_out.len() == TIMED_OUT_MEMORY {
+                self.timed_out.pop_front();
+            }
+            self.timed_out.push_back(TimedOut {
+                id: pending.id,
+                unnumbered_late: true,
+            });
+            if !pending.cancelled {
+                expired.push(Unfinalized {
+                    id: pending.id,
+                    last_partial: pending.last_partial,
+                });
+            }
+        }
+        expired
+    }
+
+    /// The server connection was lost: nothing in flight will ever finish.
+    /// Returns the ended utterances that were still owed a final.
+    pub fn reset_in_flight(&mut self) -> Vec<Unfinalized> {
+        self.open = None;
+        self.open_partial.clear();
+        // Their finals died with the connection
+        self.timed_out.clear();
+        self.awaiting_final
+            .drain(..)
+            .filter(|p| !p.cancelled)
+            .map(|p| Unfinalized {
+                id: p.id,
+                last_partial: p.last_partial,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn ids_are_monotonic_and_partials_follow_the_open_utterance() {
+        let mut tracker = UtteranceTracker::new(TIMEOUT);
+        let first = tracker.begin();
+        assert_eq!(tracker.accept_partial(Some(first), ""), Some(first));
+        tracker.end();
+
+        let second = tracker.begin();
+        assert!(second > first);
+        // A late partial from the first utterance is stale
+        assert_eq!(tracker.accept_partial(Some(first), ""), None);
+        assert_eq!(tracker.accept_partial(Some(second), ""), Some(second));
+        // Legacy servers don't echo: attribute to the open utterance
+        assert_eq!(tracker.accept_partial(None, ""), Some(second));
+    }
+
+    #[test]
+    fn partials_after_end_are_dropped() {
+        let mut tracker = UtteranceTracker::new(TIMEOUT);
+        let id = tracker.begin();
+        tracker.end();
+        assert_eq!(tracker.accept_partial(Some(id), ""), None);
+        assert_eq!(tracker.accept_partial(None, ""), None);
+    }
+
+    #[test]
+    fn finals_without_ids_match_in_fifo_order() {
+        let mut tracker = UtteranceTracker::new(TIMEOUT);
+        let a = tracker.begin();
+        tracker.end();
+        let b = tracker.begin();
+        tracker.end();
+        assert_eq!(tracker.accept_final(None), FinalMatch::Deliver(Some(a)));
+        assert_eq!(tracker.accept_final(None), FinalMatch::Deliver(Some(b)));
+        assert_eq!(tracker.accept_final(None), FinalMatch::Deliver(None));
+    }
+
+    #[test]
+    fn echoed_final_is_removed_from_the_queue() {
+        let mut tracker = UtteranceTracker::new(TIMEOUT);
+        let a = tracker.begin();
+        tracker.end();
+        let b = tracker.begin();
+        tracker.end();
+        assert_eq!(tracker.accept_final(Some(b)), FinalMatch::Deliver(Some(b)));
+        assert_eq!(tracker.accept_final(None), FinalMatch::Deliver(Some(a)));
+    }
+
+    #[test]
+    fn cancelling_the_open_utterance_expects_no_final() {
+        let mut tracker = UtteranceTracker::new(TIMEOUT);
+        let id = tracker.begin();
+        assert_eq!(tracker.cancel(), Some(id));
+        assert_eq!(tracker.accept_partial(Some(id), ""), None);
+        assert_eq!(tracker.end(), None, "nothing left to end");
+        assert_eq!(tracker.cancel(), None);
+    }
+
+    #[test]
+    fn cancelling_while_finalizing_swallows_the_final() {
+        let mut tracker = UtteranceTracker::new(TIMEOUT);
+        let a = tracker.begin();
+        tracker.end();
+        let b = tracker.begin();
+        tracker.end();
+        assert_eq!(tracker.cancel(), Some(b));
+        // FIFO matching still lines up for a server that doesn't echo IDs
+        assert_eq!(tracker.accept_final(None), FinalMatch::Deliver(Some(a)));
+        assert_eq!(tracker.accept_final(None), FinalMatch::Discard(b));
+    }
+
+    #[test]
+    fn cancelling_with_nothing_in_flight_is_a_no_op() {
+        let mut tracker = UtteranceTracker::new(TIMEOUT);
+        assert_eq!(tracker.cancel(), None);
+
+        let a = tracker.begin();
+        tracker.end();
+        assert_eq!(tracker.accept_final(None), FinalMatch::Deliver(Some(a)));
+        assert_eq!(tracker.cancel(), None, "already finalized");
+
+        let b = tracker.begin();
+        tracker.end();
+        assert_eq!(tracker.cancel(), Some(b));
+        assert_eq!(tracker.cancel(), None, "already cancelled");
+        assert_eq!(tracker.accept_final(None), FinalMatch::Discard(b));
+    }
+
+    #[test]
+    fn expired_utterance_falls_back_to_last_partial_and_drops_late_final() {
+        let mut tracker = UtteranceTracker::new(TIMEOUT);
+        let id = tracker.begin();
+        tracker.accept_partial(Some(id), "打开");
+        tracker.accept_partial(Some(id), "打开窗户");
+        tracker.end();
+        let deadline = tracker.next_deadline().unwrap();
+
+        assert!(tracker.take_expired(Instant::now()).is_empty());
+        let expired = tracker.take_expired(deadline);
+        assert_eq!(
+            expired,
+            vec![Unfinalized {
+                id,
+                last_partial: "打开窗户".to_string()
+            }]
+        );
+        assert_eq!(tracker.next_deadline(), None);
+        assert_eq!(tracker.accept_final(Some(id)), FinalMatch::Discard(id));
+    }
+
+    #[test]
+    fn late_final_without_id_is_dropped_not_given_to_the_next_utterance() {
+        let mut tracker = UtteranceTracker::new(TIMEOUT);
+        let a = tracker.begin();
+        tracker.end();
+        let deadline = tracker.next_deadline().unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+        let b = tracker.begin();
+        tracker.end();
+        assert_eq!(tracker.take_expired(deadline).len(), 1);
+
+        assert_eq!(tracker.accept_final(None), FinalMatch::Discard(a));
+        assert_eq!(tracker.accept_final(None), FinalMatch::Deliver(Some(b)));
+    }
+
+    #[test]
+    fn final_without_id_goes_to_the_next_utterance_if_the_late_one_never_comes() {
+        let mut tracker = UtteranceTracker::new(TIMEOUT);
+        let a = tracker.begin();
+        tracker.end();
+        let deadline = tracker.next_deadline().unwrap();
+        assert_eq!(tracker.take_expired(deadline).len(), 1);
+
+        // A's final was lost; B ended after A was given up on
+        let b = tracker.begin();
+        tracker.end();
+        assert_eq!(tracker.accept_final(None), FinalMatch::Deliver(Some(b)));
+        let c = tracker.begin();
+        tracker.end();
+        assert_eq!(tracker.accept_final(None), FinalMatch::Deliver(Some(c)));
+        // With its ID, A's final is still recognized
+        assert_eq!(tracker.accept_final(Some(a)), FinalMatch::Discard(a));
+    }
+
+    #[test]
+    fn cancelled_utterances_have_no_deadline() {
+        let mut tracker = UtteranceTracker::new(TIMEOUT);
+        tracker.begin();
+        tracker.end();
+        tracker.cancel();
+        assert_eq!(tracker.next_deadline(), None);
+        assert!(tracker.take_expired(Instant::now() + TIMEOUT).is_empty());
+    }
+
+    #[test]
+    fn reconnect_returns_utterances_still_owed_a_final() {
+        let mut tracker = UtteranceTracker::new(TIMEOUT);
+        let a = tracker.begin();
+        tracker.end();
+        tracker.begin();
+        let lost = tracker.reset_in_flight();
+        assert_eq!(lost.len(), 1, "the open utterance never ended");
+        assert_eq!(lost[0].id, a);
+        assert_eq!(tracker.accept_partial(None, "x"), None);
+    }
+}
@@ -0,0 +1,230 @@
+//! Drop utterances that aren't worth a conversation turn.
+//!
+//! Quick mic taps and tiny VAD triggers carry too little voiced audio, and
+//! Whisper-style models hallucinate stock phrases on near-silence. Audio is
+//! judged when the utterance ends (the server is then told to reset instead
+//! of finalizing, or, when it endpoints itself, its final is dropped); text
+//! is judged when the final arrives. Either way the drop is reported as
+//! `voice_assistant:utterance_discarded`.
+
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::audio::state::AUDIO_FRAME_SIZE;
+use crate::audio::vad::{frame_ms, frame_rms};
+
+/// Less voiced audio than this is a tap or a click, not speech.
+const FILTER_MIN_VOICED_MS: f32 = 200.0;
+/// A 20ms frame at or above this RMS counts as voiced.
+const FILTER_VOICED_RMS: f32 = 0.01;
+/// An utterance whose loudest frame stays below this is background noise.
+const FILTER_MIN_PEAK_RMS: f32 = 0.02;
+
+/// Phrases Whisper-style models emit on silence. Overridden by
+/// `ASR_HALLUCINATION_PHRASES` (`|`-separated; empty disables the check).
+pub const DEFAULT_HALLUCINATION_PHRASES: &[&str] = &[
+    "谢谢观看",
+    "谢谢大家观看",
+    "感谢观看",
+    "请不吝点赞订阅转发打赏支持明镜与点点栏目",
+    "字幕由Amara.org社区提供",
+    "Thank you for watching",
+    "Thanks for watching",
+    "Please subscribe",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, TS)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscardReason {
+    /// The final had no words in it.
+    Empty,
+    /// Not enough voiced audio.
+    TooShort,
+    /// Never loud enough to be speech.
+    TooQuiet,
+    /// The text is a known silence hallucination.
+    Hallucination,
+    /// An acknowledgement spoken over a reply, which carries on instead.
+    Backchannel,
+}
+
+/// Energy statistics of the audio sent for the open utterance.
+pub struct UtteranceAudio {
+    total_ms: f32,
+    voiced_ms: f32,
+    peak_rms: f32,
+    /// Sub-frame remainder carried to the next `feed`.
+    pending: Vec<u8>,
+}
+
+impl UtteranceAudio {
+    pub fn new() -> Self {
+        Self {
+            total_ms: 0.0,
+            voiced_ms: 0.0,
+            peak_rms: 0.0,
+            pending: Vec::with_capacity(AUDIO_FRAME_SIZE),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Account for 16-bit PCM sent to the server, in any chunk size.
+    pub fn feed(&mut self, pcm: &[u8]) {
+        self.pending.extend_from_slice(pcm);
+        let whole = self.pending.len() / AUDIO_FRAME_SIZE * AUDIO_FRAME_SIZE;
+        for frame in self.pending[..whole].chunks_exact(AUDIO_FRAME_SIZE) {
+            let rms = frame_rms(frame);
+            let ms = frame_ms(frame);
+            self.total_ms += ms;
+            self.peak_rms = self.peak_rms.max(rms);
+            if rms >= FILTER_VOICED_RMS {
+                self.voiced_ms += ms;
+            }
+        }
+        self.pending.drain(..whole);
+    }
+
+    /// Why this audio should not be transcribed, if it shouldn't.
+    pub fn verdict(&self) -> Option<DiscardReason> {
+        if self.total_ms < FILTER_MIN_VOICED_MS {
+            Some(DiscardReason::TooShort)
+        } else if self.peak_rms < FILTER_MIN_PEAK_RMS {
+            Some(DiscardReason::TooQuiet)
+        } else if self.voiced_ms < FILTER_MIN_VOICED_MS {
+            Some(DiscardReason::TooShort)
+        } else {
+            None
+        }
+    }
+}
+
+/// Text-side checks on an utterance final.
+pub struct UtteranceFilter {
+    /// Normalized (see `normalize`) hallucination phrases.
+    hallucinations: Vec<String>,
+}
+
+impl UtteranceFilter {
+    pub fn new<S: AsRef<str>>(phrases: &[S]) -> Self {
+        Self {
+            hallucinations: phrases
+                .iter()
+                .map(|p| normalize(p.as_ref()))
+                .filter(|p| !p.is_empty())
+                .collect(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        match std::env::var("ASR_HALLUCINATION_PHRASES") {
+            Ok(raw) => Self::new(&raw.split('|').collect::<Vec<_>>()),
+            Err(_) => Self::new(DEFAULT_HALLUCINATION_PHRASES),
+        }
+    }
+
+    /// Why this final should not become a turn, if it shouldn't. A phrase
+    /// repeated back to back ("谢谢观看谢谢观看") counts as the phrase.
+    pub fn check_text(&self, text: &str) -> Option<DiscardReason> {
+        let text = normalize(text);
+        if text.is_empty() {
+            return Some(DiscardReason::Empty);
+        }
+        let is_hallucination = self
+            .hallucinations
+            .iter()
+            .any(|phrase| text.replace(phrase.as_str(), "").is_empty());
+        is_hallucination.then_some(DiscardReason::Hallucination)
+    }
+}
+
+/// Lowercase, without whitespace or punctuation, so "Thank you for
+/// watching!" and "thank you for watching." compare equal.
+fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `n` 20ms frames with the given constant amplitude.
+    fn frames(amplitude: i16, n: usize) -> Vec<u8> {
+        (0..320 * n)
+            .flat_map(|i| {
+                let s = if i % 2 == 0 { amplitude } else { -amplitude };
+                s.to_le_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quick_tap_is_too_short() {
+        let mut audio = UtteranceAudio::new();
+        audio.feed(&frames(8000, 5)); // 100ms
+        assert_eq!(audio.verdict(), Some(DiscardReason::TooShort));
+        assert_eq!(
+            UtteranceAudio::new().verdict(),
+            Some(DiscardReason::TooShort)
+        );
+    }
+
+    #[test]
+    fn long_but_quiet_audio_is_too_quiet() {
+        let mut audio = UtteranceAudio::new();
+        audio.feed(&frames(200, 50)); // 1s at rms ≈ 0.006
+        assert_eq!(audio.verdict(), Some(DiscardReason::TooQuiet));
+    }
+
+    #[test]
+    fn a_click_in_silence_is_too_short() {
+        let mut audio = UtteranceAudio::new();
+        audio.feed(&frames(8000, 3));
+        audio.feed(&frames(60, 45)); // VAD end silence
+        assert_eq!(audio.verdict(), Some(DiscardReason::TooShort));
+    }
+
+    #[test]
+    fn speech_passes_regardless_of_chunking() {
+        let mut audio = UtteranceAudio::new();
+        let speech = frames(8000, 20); // 400ms
+        for chunk in speech.chunks(333) {
+            audio.feed(chunk);
+        }
+        assert_eq!(audio.verdict(), None);
+    }
+
+    #[test]
+    fn hallucinations_and_empty_text_are_caught() {
+        let filter = UtteranceFilter::new(DEFAULT_HALLUCINATION_PHRASES);
+        assert_eq!(filter.check_text(" 。，"), Some(DiscardReason::Empty));
+        assert_eq!(
+            filter.check_text("谢谢观看！"),
+            Some(DiscardReason::Hallucination)
+        );
+        assert_eq!(
+            filter.check_text("谢谢观看。谢谢观看。"),
+            Some(DiscardReason::Hallucination)
+        );
+        assert_eq!(
+            filter.check_text("Thank you for watching."),
+            Some(DiscardReason::Hallucination)
+        );
+        assert_eq!(filter.check_text("谢谢观看这个视频的人"), None);
+        assert_eq!(filter.check_text("Thank you, that helps"), None);
+        // A real one-word answer
+        assert_eq!(filter.check_text("You."), None);
+    }
+
+    #[test]
+    fn empty_phrase_list_disables_the_check() {
+        let filter = UtteranceFilter::new(&[""]);
+        assert_eq!(filter.check_text("谢谢观看"), None);
+        assert_eq!(filter.check_text(""), Some(DiscardReason::Empty));
+    }
+}
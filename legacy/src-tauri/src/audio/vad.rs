@@ -16,6 +16,22 @@ const VAD_MIN_END_RMS: f32 = 0.008;
 const VAD_NOISE_FLOOR_ALPHA: f32 = 0.05;
 const VAD_PLAYBACK_GUARD: f32 = 3.0;
 
+/// RMS of 16-bit little-endian mono PCM, normalized to 0..1.
+pub(crate) fn frame_rms(frame: &[u8]) -> f32 {
+    let samples: Vec<f32> = frame
+        .chunks_exact(2)
+        .map(|c| i16::from_le_bytes([c[0], c[1]]) as f32 / 32768.0)
+        .collect();
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|&x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+pub(crate) fn frame_ms(frame: &[u8]) -> f32 {
+    (frame.len() / 2) as f32 / (TARGET_SAMPLE_RATE as f32) * 1000.0
+}
+
 pub enum VadAction {
     None,
     StartUtterance,
@@ -51,27 +67,12 @@ impl AutoVad {
         self.prebuffer_ms = 0.0;
     }
 
-    fn frame_rms(frame: &[u8]) -> f32 {
-        let samples: Vec<f32> = frame
-            .chunks_exact(2)
-            .map(|c| i16::from_le_bytes([c[0], c[1]]) as f32 / 32768.0)
-            .collect();
-        if samples.is_empty() {
-            return 0.0;
-        }
-        (samples.iter().map(|&x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
-    }
-
-    fn frame_ms(frame: &[u8]) -> f32 {
-        (frame.len() / 2) as f32 / (TARGET_SAMPLE_RATE as f32) * 1000.0
-    }
-
     /// Feed one frame. Returns (send_this_frame, action). When idle, frames
     /// go into the pre-roll buffer; on StartUtterance the caller must flush
     /// `prebuffer` (it already contains this frame).
     pub fn feed(&mut self, frame: Vec<u8>, playback_active: bool) -> (Option<Vec<u8>>, VadAction) {
-        let rms = Self::frame_rms(&frame);
-        let ms = Self::frame_ms(&frame);
+        let rms = frame_rms(&frame);
+        let ms = frame_ms(&frame);
 
         if !self.in_speech {
             let start_threshold = {
@@ -93,7 +94,7 @@ impl AutoVad {
             self.prebuffer.push_back(frame);
             while self.prebuffer_ms > VAD_PREBUFFER_MS {
                 if let Some(front) = self.prebuffer.pop_front() {
-                    self.prebuffer_ms -= Self::frame_ms(&front);
+                    self.prebuffer_ms -= frame_ms(&front);
                 } else {
                     break;
                 }
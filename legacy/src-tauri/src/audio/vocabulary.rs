@@ -0,0 +1,559 @@
+//! Hot-word vocabulary: product and people names the ASR keeps getting
+//! wrong.
+//!
+//! The list is persisted as JSON in the app config dir, pushed to the ASR
+//! server at session start and on every change, and used for a client-side
+//! correction pass over `utterance_final` text: spans that *sound* like a
+//! term (fuzzy pinyin for Chinese, a consonant skeleton for Latin script,
+//! each script run in turn for terms that mix them) are replaced by the
+//! term's canonical spelling.
+
+use pinyin::ToPinyin;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::audio::service::VoiceService;
+use crate::audio::state::CaptureMsg;
+
+pub const VOCABULARY_FILE: &str = "vocabulary.json";
+const MAX_VOCABULARY_TERMS: usize = 500;
+const MAX_TERM_CHARS: usize = 64;
+/// Minimum skeleton similarity for a Latin-script span to match a term.
+const LATIN_MATCH_SIMILARITY: f32 = 0.8;
+/// Terms this short (in letters) only match their exact spelling; fuzzy
+/// matching them would rewrite ordinary words.
+const LATIN_EXACT_ONLY_LETTERS: usize = 4;
+/// Skeletons this short must match exactly rather than approximately.
+const LATIN_EXACT_SKELETON_LEN: usize = 3;
+
+#[derive(Default, Serialize, Deserialize)]
+struct VocabularyFile {
+    #[serde(default)]
+    terms: Vec<String>,
+}
+
+pub struct Vocabulary {
+    path: Option<PathBuf>,
+    terms: Vec<String>,
+}
+
+impl Vocabulary {
+    /// Load from `path`; a missing or unreadable file gives an empty list.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let terms = path
+            .as_ref()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|raw| serde_json::from_str::<VocabularyFile>(&raw).ok())
+            .map(|file| file.terms)
+            .unwrap_or_default();
+
+        Self { path, terms }
+    }
+
+    pub fn terms(&self) -> &[String] {
+        &self.terms
+    }
+
+    /// Add a term. Returns false if it was already present.
+    pub fn add(&mut self, term: &str) -> Result<bool, String> {
+        let term = term.trim();
+        if term.is_empty() {
+            return Err("Vocabulary term is empty".to_string());
+        }
+        if term.chars().count() > MAX_TERM_CHARS {
+            return Err(format!(
+                "Vocabulary term is longer than {} characters",
+                MAX_TERM_CHARS
+            ));
+        }
+        if self.position(term).is_some() {
+            return Ok(false);
+        }
+        if self.terms.len() >= MAX_VOCABULARY_TERMS {
+            return Err(format!(
+                "Vocabulary is full ({} terms)",
+                MAX_VOCABULARY_TERMS
+            ));
+        }
+
+        let mut terms = self.terms.clone();
+        terms.push(term.to_string());
+        self.save(terms)?;
+        Ok(true)
+    }
+
+    /// Remove a term (case-insensitive). Returns false if it wasn't there.
+    pub fn remove(&mut self, term: &str) -> Result<bool, String> {
+        let Some(index) = self.position(term.trim()) else {
+            return Ok(false);
+        };
+        let mut terms = self.terms.clone();
+        terms.remove(index);
+        self.save(terms)?;
+        Ok(true)
+    }
+
+    fn position(&self, term: &str) -> Option<usize> {
+        let wanted = term.to_lowercase();
+        self.terms.iter().position(|t| t.to_lowercase() == wanted)
+    }
+
+    /// Write `terms` out, and only then make them the list in use.
+    fn save(&mut self, terms: Vec<String>) -> Result<(), String> {
+        let Some(path) = self.path.as_ref() else {
+            self.terms = terms;
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create vocabulary dir: {}", e))?;
+        }
+        let file = VocabularyFile { terms };
+        let json = serde_json::to_string_pretty(&file)
+            .map_err(|e| format!("Failed to encode vocabulary: {}", e))?;
+        std::fs::write(path, json).map_err(|e| format!("Failed to save vocabulary: {}", e))?;
+        self.terms = file.terms;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TokenKind {
+    Cjk,
+    Word,
+    Other,
+}
+
+struct Token {
+    text: String,
+    kind: TokenKind,
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c, '\u{4e00}'..='\u{9fff}' | '\u{3400}'..='\u{4dbf}')
+}
+
+/// Split text into single CJK characters, Latin/digit words, and the
+/// separators between them, so the pieces concatenate back to the input.
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+    for c in text.chars() {
+        let kind = if is_cjk(c) {
+            TokenKind::Cjk
+        } else if c.is_alphanumeric() {
+            TokenKind::Word
+        } else {
+            TokenKind::Other
+        };
+
+        match tokens.last_mut() {
+            Some(last) if kind != TokenKind::Cjk && last.kind == kind => last.text.push(c),
+            _ => tokens.push(Token {
+                text: c.to_string(),
+                kind,
+            }),
+        }
+    }
+    tokens
+}
+
+/// Toneless pinyin with the usual ASR/accent confusions folded together:
+/// zh/z, ch/c, sh/s, n/l, and -ng/-n.
+fn fuzzy_pinyin(c: char) -> Option<String> {
+    let plain = c.to_pinyin()?.plain();
+    let mut syllable = plain
+        .replacen("zh", "z", 1)
+        .replacen("ch", "c", 1)
+        .replacen("sh", "s", 1);
+    if syllable.starts_with('n') {
+        syllable.replace_range(..1, "l");
+    }
+    if syllable.ends_with("ng") {
+        syllable.pop();
+    }
+    Some(syllable)
+}
+
+fn fuzzy_pinyin_key(chars: impl Iterator<Item = char>) -> Option<Vec<String>> {
+    chars.map(fuzzy_pinyin).collect()
+}
+
+/// Consonant skeleton of a Latin-script string: spelling variants that sound
+/// alike ("ph"/"f", "c"/"k", doubled letters, vowels) collapse together.
+fn latin_skeleton(text: &str) -> String {
+    let spelled = ascii_letters(text)
+        .replace("ph", "f")
+        .replace("ck", "k")
+        .replace("qu", "kw")
+        .replace("th", "t")
+        .replace(['c', 'q'], "k")
+        .replace('x', "ks")
+        .replace('z', "s")
+        .replace('y', "i");
+
+    let mut skeleton = String::new();
+    for (i, c) in spelled.chars().enumerate() {
+        if i > 0 && "aeiou".contains(c) {
+            continue;
+        }
+        if skeleton.ends_with(c) {
+            continue;
+        }
+        skeleton.push(c);
+    }
+    skeleton
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+fn ascii_letters(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .collect()
+}
+
+fn latin_matches(term: &str, term_skeleton: &str, candidate: &str) -> bool {
+    let term_letters = ascii_letters(term);
+    let candidate_letters = ascii_letters(candidate);
+    if term_letters.len() <= LATIN_EXACT_ONLY_LETTERS {
+        return candidate_letters == term_letters;
+    }
+    // Way shorter or longer spans can't plausibly be the same name
+    if candidate_letters.len() * 2 < term_letters.len()
+        || candidate_letters.len() > term_letters.len() * 2
+    {
+        return false;
+    }
+
+    let candidate = latin_skeleton(candidate);
+    if term_skeleton.len() <= LATIN_EXACT_SKELETON_LEN {
+        return candidate == term_skeleton;
+    }
+    let a: Vec<char> = term_skeleton.chars().collect();
+    let b: Vec<char> = candidate.chars().collect();
+    let similarity = 1.0 - levenshtein(&a, &b) as f32 / a.len().max(b.len()) as f32;
+    similarity >= LATIN_MATCH_SIMILARITY
+}
+
+/// Replace CJK runs whose fuzzy pinyin equals the term's.
+fn correct_cjk(tokens: &mut Vec<Token>, term: &str) {
+    let Some(key) = fuzzy_pinyin_key(term.chars()) else {
+        return;
+    };
+    let n = key.len();
+    if n < 2 {
+        return;
+    }
+
+    let mut i = 0;
+    while i + n <= tokens.len() {
+        let window = &tokens[i..i + n];
+        let is_match = window.iter().all(|t| t.kind == TokenKind::Cjk)
+            && fuzzy_pinyin_key(window.iter().filter_map(|t| t.text.chars().next()))
+                .is_some_and(|k| k == key);
+
+        if is_match {
+            tokens.splice(
+                i..i + n,
+                [Token {
+                    text: term.to_string(),
+                    kind: TokenKind::Other,
+                }],
+            );
+        }
+        i += 1;
+    }
+}
+
+/// Replace runs of up to one word more than the term has (ASR likes to
+/// split unknown names) whose skeleton is close to the term's.
+fn correct_latin(tokens: &mut Vec<Token>, term: &str) {
+    let skeleton = latin_skeleton(term);
+    if skeleton.is_empty() {
+        return;
+    }
+    let max_words = term.split_whitespace().count() + 1;
+
+    let mut i = 0;
+    while i < tokens.len() {
+        if tokens[i].kind != TokenKind::Word {
+            i += 1;
+            continue;
+        }
+
+        // Candidate end indices: word tokens joined only by whitespace
+        let mut ends = vec![i];
+        let mut j = i;
+        while ends.len() < max_words
+            && j + 2 < tokens.len()
+            && tokens[j + 1].kind == TokenKind::Other
+            && tokens[j + 1].text.trim().is_empty()
+            && tokens[j + 2].kind == TokenKind::Word
+        {
+            j += 2;
+            ends.push(j);
+        }
+
+        // Prefer the longest span that matches
+        let matched = ends.iter().rev().copied().find(|&end| {
+            let span: String = tokens[i..=end].iter().map(|t| t.text.as_str()).collect();
+            latin_matches(term, &skeleton, &span)
+        });
+
+        if let Some(end) = matched {
+            tokens.splice(
+                i..=end,
+                [Token {
+                    text: term.to_string(),
+                    kind: TokenKind::Other,
+                }],
+            );
+        }
+        i += 1;
+    }
+}
+
+/// A run of one script in a mixed term: "微信Pay" is 微信, then Pay.
+enum Segment {
+    /// Fuzzy pinyin of each character.
+    Cjk(Vec<String>),
+    Latin {
+        word: String,
+        skeleton: String,
+    },
+}
+
+fn segments(term: &str) -> Option<Vec<Segment>> {
+    let mut segments = Vec::new();
+    for token in tokenize(term) {
+        match token.kind {
+            TokenKind::Cjk => {
+                let syllable = fuzzy_pinyin(token.text.chars().next()?)?;
+                match segments.last_mut() {
+                    Some(Segment::Cjk(key)) => key.push(syllable),
+                    _ => segments.push(Segment::Cjk(vec![syllable])),
+                }
+            }
+            TokenKind::Word => segments.push(Segment::Latin {
+                skeleton: latin_skeleton(&token.text),
+                word: token.text,
+            }),
+            TokenKind::Other => {}
+        }
+    }
+    Some(segments)
+}
+
+/// Replace spans that match a mixed term's segments in order; the
+/// segments may be split by whitespace.
+fn correct_mixed(tokens: &mut Vec<Token>, term: &str) {
+    let Some(segments) = segments(term) else {
+        return;
+    };
+
+    let mut i = 0;
+    while i < tokens.len() {
+        if let Some(end) = match_segments(tokens, i, &segments) {
+            tokens.splice(
+                i..end,
+                [Token {
+                    text: term.to_string(),
+                    kind: TokenKind::Other,
+                }],
+            );
+        }
+        i += 1;
+    }
+}
+
+/// Where a match of `segments` starting at token `start` ends.
+fn match_segments(tokens: &[Token], start: usize, segments: &[Segment]) -> Option<usize> {
+    let mut i = start;
+    for (n, segment) in segments.iter().enumerate() {
+        if n > 0
+            && tokens
+                .get(i)
+                .is_some_and(|t| t.kind == TokenKind::Other && t.text.trim().is_empty())
+        {
+            i += 1;
+        }
+        match segment {
+            Segment::Cjk(key) => {
+                let window = tokens.get(i..i + key.len())?;
+                let matched = window.iter().all(|t| t.kind == TokenKind::Cjk)
+                    && fuzzy_pinyin_key(window.iter().filter_map(|t| t.text.chars().next()))
+                        .is_some_and(|k| k == *key);
+                if !matched {
+                    return None;
+                }
+                i += key.len();
+            }
+            Segment::Latin { word, skeleton } => {
+                let token = tokens.get(i).filter(|t| t.kind == TokenKind::Word)?;
+                if !latin_matches(word, skeleton, &token.text) {
+                    return None;
+                }
+                i += 1;
+            }
+        }
+    }
+    Some(i)
+}
+
+/// Rewrite spans of `text` that sound like a vocabulary term into the term.
+/// Longer terms go first so they win over terms they contain.
+pub fn correct_transcript(text: &str, terms: &[String]) -> String {
+    if terms.is_empty() || text.is_empty() {
+        return text.to_string();
+    }
+
+    let mut ordered: Vec<&String> = terms.iter().collect();
+    ordered.sort_by_key(|t| std::cmp::Reverse(t.chars().count()));
+
+    let mut tokens = tokenize(text);
+    for term in ordered {
+        if term.chars().all(is_cjk) {
+            correct_cjk(&mut tokens, term);
+        } else if term.chars().any(is_cjk) {
+            correct_mixed(&mut tokens, term);
+        } else {
+            correct_latin(&mut tokens, term);
+        }
+    }
+
+    tokens.into_iter().map(|t| t.text).collect()
+}
+
+/// Push the current list to the running ASR session (FIFO with audio).
+fn notify_session(service: &VoiceService, terms: &[String]) {
+    service.send(CaptureMsg::SetVocabulary(terms.to_vec()));
+}
+
+#[tauri::command]
+pub fn list_vocabulary(state: tauri::State<'_, Arc<Mutex<Vocabulary>>>) -> Vec<String> {
+    state.lock().unwrap().terms().to_vec()
+}
+
+#[tauri::command]
+pub fn add_vocabulary_term(
+    state: tauri::State<'_, Arc<Mutex<Vocabulary>>>,
+    service: tauri::State<'_, Arc<VoiceService>>,
+    term: String,
+) -> Result<Vec<String>, String> {
+    let mut vocabulary = state.lock().unwrap();
+    if vocabulary.add(&term)? {
+        notify_session(&service, vocabulary.terms());
+    }
+    Ok(vocabulary.terms().to_vec())
+}
+
+#[tauri::command]
+pub fn remove_vocabulary_term(
+    state: tauri::State<'_, Arc<Mutex<Vocabulary>>>,
+    service: tauri::State<'_, Arc<VoiceService>>,
+    term: String,
+) -> Result<Vec<String>, String> {
+    let mut vocabulary = state.lock().unwrap();
+    if vocabulary.remove(&term)? {
+        notify_session(&service, vocabulary.terms());
+    }
+    Ok(vocabulary.terms().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(list: &[&str]) -> Vec<String> {
+        list.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn chinese_name_corrected_by_fuzzy_pinyin() {
+        // 张伟 (zhang wei) misheard as 章维 / 赞为 (zan wei: zh→z, -ng→-n)
+        let vocab = terms(&["张伟"]);
+        assert_eq!(
+            correct_transcript("请把文件发给章维。", &vocab),
+            "请把文件发给张伟。"
+        );
+        assert_eq!(correct_transcript("赞为在吗", &vocab), "张伟在吗");
+        assert_eq!(correct_transcript("今天天气不错", &vocab), "今天天气不错");
+    }
+
+    #[test]
+    fn split_latin_name_is_rejoined() {
+        let vocab = terms(&["Sisyphus", "Kubernetes"]);
+        assert_eq!(
+            correct_transcript("open sissy fuss on cooper netties", &vocab),
+            "open Sisyphus on Kubernetes"
+        );
+    }
+
+    #[test]
+    fn short_terms_only_match_exactly() {
+        let vocab = terms(&["Zed"]);
+        assert_eq!(correct_transcript("I said zed", &vocab), "I said Zed");
+        assert_eq!(correct_transcript("I said that", &vocab), "I said that");
+    }
+
+    #[test]
+    fn mixed_script_term_matches_each_run() {
+        let vocab = terms(&["微信Pay"]);
+        assert_eq!(
+            correct_transcript("用维信 pay付款", &vocab),
+            "用微信Pay付款"
+        );
+        assert_eq!(correct_transcript("用微信PAY付款", &vocab), "用微信Pay付款");
+        assert_eq!(correct_transcript("用微信付款", &vocab), "用微信付款");
+    }
+
+    #[test]
+    fn vocabulary_persists_and_dedupes() {
+        let path = std::env::temp_dir().join(format!(
+            "sisyphus-vocabulary-test-{}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let mut vocabulary = Vocabulary::load(Some(path.clone()));
+        assert!(vocabulary.add("Sisyphus").unwrap());
+        assert!(!vocabulary.add("  sisyphus ").unwrap());
+        assert!(vocabulary.add("").is_err());
+
+        let reloaded = Vocabulary::load(Some(path.clone()));
+        assert_eq!(reloaded.terms(), ["Sisyphus".to_string()]);
+
+        let mut reloaded = reloaded;
+        assert!(reloaded.remove("SISYPHUS").unwrap());
+        assert!(Vocabulary::load(Some(path.clone())).terms().is_empty());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn failed_save_leaves_the_list_unchanged() {
+        // A file where the config dir should be makes every save fail
+        let blocker = std::env::temp_dir().join(format!(
+            "sisyphus-vocabulary-blocker-{}",
+            std::process::id()
+        ));
+        std::fs::write(&blocker, "").unwrap();
+
+        let mut vocabulary = Vocabulary::load(Some(blocker.join("vocabulary.json")));
+        assert!(vocabulary.add("Sisyphus").is_err());
+        assert!(vocabulary.terms().is_empty());
+        let _ = std::fs::remove_file(&blocker);
+    }
+}
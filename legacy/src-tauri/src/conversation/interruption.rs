@@ -0,0 +1,229 @@
+//! What happens to the assistant's reply when the user talks over it.
+//!
+//! Playback is always paused the moment speech starts (or ducked, in the
+//! playback's ducking mode). The policy decides what comes next:
+//! - `PauseResume`: resume the reply when the user stops speaking.
+//! - `Cancel`: drop the reply at once (LLM stream, TTS stream and queued
+//!   audio) and mark its turn interrupted.
+//! - `CancelIfSubstantive`: hold the reply until the final arrives. A real
+//!   request cancels it; a backchannel ("嗯", "ok") resumes it and is not
+//!   answered.
+
+use tauri::Manager;
+use ts_rs::TS;
+
+use crate::audio::service::voice_service;
+use crate::conversation::TurnOrchestrator;
+use crate::events::{self, InterruptionPolicyChanged};
+
+/// Finals with fewer words than this never cancel a reply.
+const SUBSTANTIVE_MIN_WORDS: usize = 3;
+
+/// Short acknowledgements that never cancel a reply, whatever their length.
+const BACKCHANNELS: &[&str] = &[
+    "嗯",
+    "嗯嗯",
+    "啊",
+    "哦",
+    "噢",
+    "对",
+    "对对",
+    "对对对",
+    "是",
+    "是的",
+    "好",
+    "好的",
+    "行",
+    "没错",
+    "明白",
+    "知道了",
+    "ok",
+    "okay",
+    "yeah",
+    "yes",
+    "yep",
+    "uh huh",
+    "mm hmm",
+    "right",
+    "sure",
+    "i see",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+pub enum InterruptionPolicy {
+    PauseResume,
+    Cancel,
+    CancelIfSubstantive,
+}
+
+impl InterruptionPolicy {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "pause_resume" => Some(Self::PauseResume),
+            "cancel" => Some(Self::Cancel),
+            "cancel_if_substantive" => Some(Self::CancelIfSubstantive),
+            _ => None,
+        }
+    }
+}
+
+/// `BARGE_IN_POLICY` (pause_resume | cancel | cancel_if_substantive), or
+/// `PauseResume`.
+pub fn policy_from_env() -> InterruptionPolicy {
+    let Ok(value) = std::env::var("BARGE_IN_POLICY") else {
+        return InterruptionPolicy::PauseResume;
+    };
+    InterruptionPolicy::parse(&value).unwrap_or_else(|| {
+        eprintln!(
+            "Warning: unknown BARGE_IN_POLICY '{}', using pause_resume",
+            value
+        );
+        InterruptionPolicy::PauseResume
+    })
+}
+
+/// The app's policy; `PauseResume` until the orchestrator is set up.
+fn policy(app: &tauri::AppHandle) -> InterruptionPolicy {
+    app.try_state::<TurnOrchestrator>()
+        .map_or(InterruptionPolicy::PauseResume, |orchestrator| {
+            orchestrator.interruption_policy()
+        })
+}
+
+/// Whether a final is a real request rather than a backchannel. CJK
+/// characters count as one word each.
+pub fn is_substantive(text: &str) -> bool {
+    let normalized: String = text
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() {
+                c.to_lowercase().next().unwrap_or(c)
+            } else {
+                ' '
+            }
+        })
+        .collect();
+    let normalized = normalized.split_whitespace().collect::<Vec<_>>().join(" ");
+    if BACKCHANNELS.contains(&normalized.as_str()) {
+        return false;
+    }
+
+    let words: usize = normalized
+        .split(' ')
+        .map(|word| {
+            let cjk = word.chars().filter(|c| is_cjk(*c)).count();
+            cjk + usize::from(cjk < word.chars().count())
+        })
+        .sum();
+    words >= SUBSTANTIVE_MIN_WORDS
+}
+
+pub(crate) fn is_cjk(c: char) -> bool {
+    matches!(
+        c,
+        '\u{3040}'..='\u{30ff}'
+            | '\u{3400}'..='\u{4dbf}'
+            | '\u{4e00}'..='\u{9fff}'
+            | '\u{ac00}'..='\u{d7af}'
+    )
+}
+
+/// Speech started: pause (or duck) the reply, or drop it under `Cancel`.
+pub fn user_started_speaking(app: &tauri::AppHandle) {
+    voice_service(app).playback.yield_to_speech(app);
+    if policy(app) == InterruptionPolicy::Cancel {
+        if let Some(orchestrator) = app.try_state::<TurnOrchestrator>() {
+            orchestrator.interrupt(app);
+        }
+    }
+}
+
+/// Speech ended: resume the reply, unless its fate waits on the final.
+pub fn user_stopped_speaking(app: &tauri::AppHandle) {
+    if policy(app) == InterruptionPolicy::CancelIfSubstantive && reply_in_flight(app) {
+        return;
+    }
+    voice_service(app).playback.resume(app);
+}
+
+/// The utterance was cancelled or discarded: the reply carries on.
+pub fn utterance_dropped(app: &tauri::AppHandle) {
+    voice_service(app).playback.resume(app);
+}
+
+/// A final arrived. Returns false when it only acknowledged the reply it
+/// interrupted and should not be answered.
+pub fn accept_final(app: &tauri::AppHandle, text: &str) -> bool {
+    if policy(app) != InterruptionPolicy::CancelIfSubstantive || !reply_in_flight(app) {
+        return true;
+    }
+    if is_substantive(text) {
+        if let Some(orchestrator) = app.try_state::<TurnOrchestrator>() {
+            orchestrator.interrupt(app);
+        }
+        true
+    } else {
+        voice_service(app).playback.resume(app);
+        false
+    }
+}
+
+fn reply_in_flight(app: &tauri::AppHandle) -> bool {
+    app.try_state::<TurnOrchestrator>()
+        .is_some_and(|orchestrator| orchestrator.reply_in_flight())
+}
+
+#[tauri::command]
+pub fn get_interruption_policy(
+    orchestrator: tauri::State<'_, TurnOrchestrator>,
+) -> InterruptionPolicy {
+    orchestrator.interruption_policy()
+}
+
+#[tauri::command]
+pub fn set_interruption_policy(
+    app: tauri::AppHandle,
+    orchestrator: tauri::State<'_, TurnOrchestrator>,
+    policy: InterruptionPolicy,
+) -> Result<(), String> {
+    orchestrator.set_interruption_policy(policy);
+    events::emit(&app, InterruptionPolicyChanged { policy });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backchannels_are_not_substantive() {
+        assert!(!is_substantive("嗯嗯。"));
+        assert!(!is_substantive("好的！"));
+        assert!(!is_substantive("Uh-huh"));
+        assert!(!is_substantive("OK."));
+    }
+
+    #[test]
+    fn short_fragments_are_not_substantive() {
+        assert!(!is_substantive("等等"));
+        assert!(!is_substantive("wait no"));
+        assert!(!is_substantive(""));
+    }
+
+    #[test]
+    fn requests_are_substantive() {
+        assert!(is_substantive("停一下，换个话题"));
+        assert!(is_substantive("what about tomorrow"));
+        assert!(is_substantive("明天 weather 呢"));
+    }
+
+    #[test]
+    fn parses_policy_names() {
+        assert_eq!(
+            InterruptionPolicy::parse(" Cancel_If_Substantive "),
+            Some(InterruptionPolicy::CancelIfSubstantive)
+        );
+        assert_eq!(InterruptionPolicy::parse("pause"), None);
+    }
+}
@@ -1,7 +1,11 @@
+pub mod interruption;
+pub mod orchestrator;
+pub mod scheduler;
 pub mod state;
 
+pub use orchestrator::TurnOrchestrator;
 pub use state::{
     add_assistant_message, add_user_message, get_conversation_history, get_conversation_status,
-    is_conversation_active, transition_conversation_status, ConversationState,
-    Message, Role,
+    get_current_turn, is_conversation_active, set_current_turn_status, set_turn_status,
+    transition_conversation_status, ConversationState, ConversationStatus, Message, Role,
 };
@@ -0,0 +1,292 @@
+//! Drives each turn from ASR final to spoken reply, so the pipeline runs
+//! without the webview.
+//!
+//! The ASR session submits every delivered final, and typed messages come
+//! in through `stream_llm_response`. Only one reply runs at a time; how
+//! finals are grouped into turns, and whether a new one waits or supersedes
+//! the running reply, is up to the `scheduler`. Progress is recorded on the
+//! conversation `Turn` and announced as `voice_assistant:turn_state`; the
+//! number of waiting finals goes out as `voice_assistant:turn_queue`.
+//!
+//! A barge-in (see `interruption`) can cut the running reply short with
+//! `interrupt`, which is reported as `voice_assistant:turn_interrupted`.
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tauri::Manager;
+use tokio::sync::{oneshot, watch, Notify};
+
+use crate::audio::earcon::Earcon;
+use crate::audio::playback::Playback;
+use crate::audio::service::voice_service;
+use crate::audio::spoken::SpokenReply;
+use crate::audio::state::emit_error;
+use crate::conversation::interruption::{self, InterruptionPolicy};
+use crate::conversation::scheduler::{self, MergedTurn, TurnPolicy, TurnQueue};
+use crate::conversation::{set_turn_status, ConversationState, ConversationStatus};
+use crate::events::{self, TurnInterrupted, TurnsWaiting};
+use crate::llm::{run_assistant_reply, ReplyHandle};
+
+/// Told the reply text once a submitted message has been answered.
+type ReplyWaiter = oneshot::Sender<Result<String, String>>;
+
+/// The latest reply, kept after it finished generating while its audio may
+/// still be playing.
+struct ActiveReply {
+    turn_id: Option<usize>,
+    /// Closed once the reply has finished generating.
+    cancel: watch::Sender<bool>,
+    spoken: Arc<Mutex<SpokenReply>>,
+}
+
+pub struct TurnOrchestrator {
+    queue: Arc<Mutex<TurnQueue<ReplyWaiter>>>,
+    arrived: Arc<Notify>,
+    active: Arc<Mutex<Option<ActiveReply>>>,
+    playback: Arc<Playback>,
+    /// What the user talking over a reply does to it.
+    interruption_policy: Mutex<InterruptionPolicy>,
+    /// What a final arriving during a reply does to it.
+    turn_policy: Mutex<TurnPolicy>,
+}
+
+impl TurnOrchestrator {
+    pub fn new(app: tauri::AppHandle) -> Self {
+        let playback = voice_service(&app).playback.clone();
+        let queue = Arc::new(Mutex::new(TurnQueue::new(
+            scheduler::merge_window_from_env(),
+        )));
+        let arrived = Arc::new(Notify::new());
+        let active = Arc::new(Mutex::new(None));
+
+        let queue_for_worker = queue.clone();
+        let arrived_for_worker = arrived.clone();
+        let active_for_worker = active.clone();
+        tauri::async_runtime::spawn(async move {
+            loop {
+                // Wait out the next turn's merge window; a final that joins it
+                // restarts it
+                let ready_at = queue_for_worker.lock().unwrap().ready_at();
+                match ready_at {
+                    None => {
+                        arrived_for_worker.notified().await;
+                        continue;
+                    }
+                    Some(at) if Instant::now() < at => {
+                        tokio::select! {
+                            _ = tokio::time::sleep_until(at.into()) => {}
+                            _ = arrived_for_worker.notified() => {}
+                        }
+                        continue;
+                    }
+                    Some(_) => {}
+                }
+                let (turn, waiting) = {
+                    let mut queue = queue_for_worker.lock().unwrap();
+                    (queue.take_merged(), queue.len())
+                };
+                let Some(mut turn) = turn else {
+                    continue;
+                };
+                emit_turn_queue(&app, waiting);
+                if turn.finals > 1 {
+                    println!("Merged {} finals into turn {:?}", turn.finals, turn.turn_id);
+                }
+                // Earlier finals are answered as part of the newest one's turn
+                for absorbed in &turn.absorbed {
+                    set_turn_status(&app, Some(*absorbed), ConversationStatus::Idle);
+                }
+                // A typed message has no utterance turn; it gets its own
+                if turn.turn_id.is_none() {
+                    turn.turn_id = app
+                        .try_state::<Arc<Mutex<ConversationState>>>()
+                        .map(|conv_state| conv_state.lock().unwrap().start_new_turn());
+                }
+
+                let (cancel, cancelled) = watch::channel(false);
+                let spoken = Arc::new(Mutex::new(SpokenReply::new()));
+                *active_for_worker.lock().unwrap() = Some(ActiveReply {
+                    turn_id: turn.turn_id,
+                    cancel,
+                    spoken: spoken.clone(),
+                });
+                let handle = ReplyHandle {
+                    cancel: cancelled,
+                    spoken,
+                };
+                run_turn(&app, turn, handle).await;
+            }
+        });
+
+        Self {
+            queue,
+            arrived,
+            active,
+            playback,
+            interruption_policy: Mutex::new(interruption::policy_from_env()),
+            turn_policy: Mutex::new(scheduler::policy_from_env()),
+        }
+    }
+
+    pub fn interruption_policy(&self) -> InterruptionPolicy {
+        *self.interruption_policy.lock().unwrap()
+    }
+
+    pub fn set_interruption_policy(&self, policy: InterruptionPolicy) {
+        *self.interruption_policy.lock().unwrap() = policy;
+    }
+
+    pub fn turn_policy(&self) -> TurnPolicy {
+        *self.turn_policy.lock().unwrap()
+    }
+
+    pub fn set_turn_policy(&self, policy: TurnPolicy) {
+        *self.turn_policy.lock().unwrap() = policy;
+    }
+
+    /// Queue a final transcript to be answered.
+    pub fn submit(&self, app: &tauri::AppHandle, utterance_id: Option<usize>, text: String) {
+        let conv_state = app.state::<Arc<Mutex<ConversationState>>>();
+        let turn_id = {
+            let conv = conv_state.lock().unwrap();
+            match utterance_id {
+                Some(id) => conv.turn_for_segment(id),
+                None => Some(conv.get_current_turn().id),
+            }
+        };
+        set_turn_status(app, turn_id, ConversationStatus::FinalizingASR);
+
+        self.enqueue(app, turn_id, text, None);
+    }
+
+    /// Queue a typed message; resolves with the reply text once answered.
+    pub fn submit_text(
+        &self,
+        app: &tauri::AppHandle,
+        text: String,
+    ) -> oneshot::Receiver<Result<String, String>> {
+        let (waiter, reply) = oneshot::channel();
+        self.enqueue(app, None, text, Some(waiter));
+        reply
+    }
+
+    fn enqueue(
+        &self,
+        app: &tauri::AppHandle,
+        turn_id: Option<usize>,
+        text: String,
+        waiter: Option<ReplyWaiter>,
+    ) {
+        if self.turn_policy() == TurnPolicy::Supersede {
+            self.interrupt(app);
+        }
+        let waiting = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.push(turn_id, text, waiter, Instant::now());
+            queue.len()
+        };
+        emit_turn_queue(app, waiting);
+        self.arrived.notify_one();
+    }
+
+    /// A reply is still generating, or its audio is still queued or playing.
+    pub fn reply_in_flight(&self) -> bool {
+        self.active
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|reply| !reply.cancel.is_closed() || self.playback.has_pending_audio())
+    }
+
+    /// Cut the current reply short: stop the LLM and TTS streams, drop the
+    /// queued audio and mark its turn interrupted. A reply already in the
+    /// history is cut down to what was played. Returns false when there was
+    /// nothing left to interrupt.
+    pub fn interrupt(&self, app: &tauri::AppHandle) -> bool {
+        if !self.reply_in_flight() {
+            return false;
+        }
+        let Some(reply) = self.active.lock().unwrap().take() else {
+            return false;
+        };
+
+        let _ = reply.cancel.send(true);
+        self.playback.flush(app);
+
+        let (heard, played_fraction) = {
+            let conv_state = app.state::<Arc<Mutex<ConversationState>>>();
+            let mut conv = conv_state.lock().unwrap();
+            if let Some(id) = reply.turn_id {
+                conv.mark_interrupted(id);
+            }
+            let spoken = reply.spoken.lock().unwrap();
+            let heard = spoken.heard_text();
+            if let Some(index) = spoken.message_index {
+                conv.truncate_interrupted_reply(index, heard.clone());
+            }
+            (heard, spoken.played_fraction())
+        };
+        println!(
+            "Interrupted reply of turn {:?} after {:.0}% of its audio",
+            reply.turn_id,
+            played_fraction * 100.0
+        );
+        set_turn_status(app, reply.turn_id, ConversationStatus::Idle);
+        events::emit(
+            app,
+            TurnInterrupted {
+                turn_id: reply.turn_id,
+                spoken_text: heard,
+                played_fraction,
+            },
+        );
+        true
+    }
+
+    /// Playback ended: every turn still Speaking is over, except one whose
+    /// reply is still being generated. Only a reply that `drained` (was heard
+    /// in full, not stopped) gets the turn-complete cue.
+    pub fn playback_finished(&self, app: &tauri::AppHandle, drained: bool) {
+        let generating = self
+            .active
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|reply| !reply.cancel.is_closed())
+            .and_then(|reply| reply.turn_id);
+        let speaking = app
+            .try_state::<Arc<Mutex<ConversationState>>>()
+            .map(|conv_state| conv_state.lock().unwrap().speaking_turns())
+            .unwrap_or_default();
+        let mut completed = false;
+        for turn_id in speaking {
+            if Some(turn_id) != generating {
+                set_turn_status(app, Some(turn_id), ConversationStatus::Idle);
+                completed = true;
+            }
+        }
+        if completed && drained {
+            self.playback.play_earcon(app, Earcon::TurnComplete);
+        }
+    }
+}
+
+fn emit_turn_queue(app: &tauri::AppHandle, waiting: usize) {
+    events::emit(app, TurnsWaiting { waiting });
+}
+
+async fn run_turn(app: &tauri::AppHandle, turn: MergedTurn<ReplyWaiter>, handle: ReplyHandle) {
+    let result = run_assistant_reply(app, turn.text, turn.turn_id, Some(handle)).await;
+    if let Err(e) = &result {
+        eprintln!("Turn {:?} failed: {}", turn.turn_id, e);
+        emit_error(app, "LLM_REQUEST_FAILED", e);
+    }
+
+    // While the reply is still audible, playback completion ends the turn
+    if result.is_err() || !voice_service(app).playback.is_playing() {
+        set_turn_status(app, turn.turn_id, ConversationStatus::Idle);
+    }
+    for waiter in turn.waiters {
+        let _ = waiter.send(result.clone());
+    }
+}
@@ -0,0 +1,269 @@
+//! Which finals make up the next turn, and what a final does to a reply
+//! that is already under way.
+//!
+//! A final waits the merge window before the LLM is asked; finals that
+//! follow within it join the same user message, the way the Python
+//! `SemanticBufferProcessor` stitches fragmented utterances back together.
+//! A final that came a whole window after the one before it starts a turn
+//! of its own, even when both waited out a running reply.
+//! A final that arrives while a reply is running is queued for the next
+//! turn, or supersedes the running reply, according to `TurnPolicy`.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+use crate::conversation::interruption::is_cjk;
+use crate::conversation::TurnOrchestrator;
+use crate::events::{self, TurnPolicyChanged};
+
+/// How long a final waits for a follow-up before its turn starts.
+pub const DEFAULT_TURN_MERGE_WINDOW_MS: u64 = 600;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+pub enum TurnPolicy {
+    /// Answer it once the running reply has been generated.
+    Queue,
+    /// Cut the running reply short and answer the new final instead.
+    Supersede,
+}
+
+impl TurnPolicy {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "queue" => Some(Self::Queue),
+            "supersede" => Some(Self::Supersede),
+            _ => None,
+        }
+    }
+}
+
+/// `TURN_OVERLAP_POLICY` (queue | supersede), or `Queue`.
+pub fn policy_from_env() -> TurnPolicy {
+    let Ok(value) = std::env::var("TURN_OVERLAP_POLICY") else {
+        return TurnPolicy::Queue;
+    };
+    TurnPolicy::parse(&value).unwrap_or_else(|| {
+        eprintln!(
+            "Warning: unknown TURN_OVERLAP_POLICY '{}', using queue",
+            value
+        );
+        TurnPolicy::Queue
+    })
+}
+
+/// `TURN_MERGE_WINDOW_MS`, or the default.
+pub fn merge_window_from_env() -> Duration {
+    let ms = std::env::var("TURN_MERGE_WINDOW_MS")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_TURN_MERGE_WINDOW_MS);
+    Duration::from_millis(ms)
+}
+
+struct QueuedFinal<W> {
+    turn_id: Option<usize>,
+    text: String,
+    waiter: Option<W>,
+    arrived: Instant,
+}
+
+/// Queued finals merged into one user message.
+pub struct MergedTurn<W> {
+    /// The turn of the newest final.
+    pub turn_id: Option<usize>,
+    /// Turns of earlier finals, answered as part of `turn_id`.
+    pub absorbed: Vec<usize>,
+    pub text: String,
+    /// How many finals went into it.
+    pub finals: usize,
+    /// Whoever asked for a reply and waits for it.
+    pub waiters: Vec<W>,
+}
+
+/// Finals waiting for the LLM. `W` is whatever the caller needs to be told
+/// when its reply is done.
+pub struct TurnQueue<W> {
+    finals: VecDeque<QueuedFinal<W>>,
+    merge_window: Duration,
+}
+
+impl<W> TurnQueue<W> {
+    pub fn new(merge_window: Duration) -> Self {
+        Self {
+            finals: VecDeque::new(),
+            merge_window,
+        }
+    }
+
+    pub fn push(&mut self, turn_id: Option<usize>, text: String, waiter: Option<W>, now: Instant) {
+        self.finals.push_back(QueuedFinal {
+            turn_id,
+            text,
+            waiter,
+            arrived: now,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.finals.len()
+    }
+
+    /// How many finals at the front arrived within a merge window of the
+    /// one before.
+    fn leading_run(&self) -> usize {
+        let gaps = self.finals.iter().zip(self.finals.iter().skip(1));
+        1 + gaps
+            .take_while(|(prev, next)| next.arrived - prev.arrived <= self.merge_window)
+            .count()
+    }
+
+    /// When the next turn may be answered: one merge window after the last
+    /// final that joins it arrived.
+    pub fn ready_at(&self) -> Option<Instant> {
+        if self.finals.is_empty() {
+            return None;
+        }
+        let last = &self.finals[self.leading_run() - 1];
+        Some(last.arrived + self.merge_window)
+    }
+
+    /// The finals of the next turn, merged; later ones stay queued.
+    pub fn take_merged(&mut self) -> Option<MergedTurn<W>> {
+        if self.finals.is_empty() {
+            return None;
+        }
+        let run = self.leading_run();
+        let mut merged = MergedTurn {
+            turn_id: None,
+            absorbed: Vec::new(),
+            text: String::new(),
+            finals: 0,
+            waiters: Vec::new(),
+        };
+        for fin in self.finals.drain(..run) {
+            if let Some(turn_id) = fin.turn_id {
+                merged.absorbed.extend(merged.turn_id.replace(turn_id));
+            }
+            merged.text = join_utterances(&merged.text, &fin.text);
+            merged.finals += 1;
+            merged.waiters.extend(fin.waiter);
+        }
+        Some(merged)
+    }
+}
+
+/// Join two transcripts: a space between words, none between CJK text.
+pub fn join_utterances(first: &str, second: &str) -> String {
+    let (first, second) = (first.trim(), second.trim());
+    let cjk_boundary = first.chars().last().is_some_and(is_cjk_or_punct)
+        || second.chars().next().is_some_and(is_cjk_or_punct);
+    if first.is_empty() || second.is_empty() || cjk_boundary {
+        format!("{}{}", first, second)
+    } else {
+        format!("{} {}", first, second)
+    }
+}
+
+fn is_cjk_or_punct(c: char) -> bool {
+    is_cjk(c) || matches!(c, '\u{3000}'..='\u{303f}' | '\u{ff00}'..='\u{ffef}')
+}
+
+#[tauri::command]
+pub fn get_turn_policy(orchestrator: tauri::State<'_, TurnOrchestrator>) -> TurnPolicy {
+    orchestrator.turn_policy()
+}
+
+#[tauri::command]
+pub fn set_turn_policy(
+    app: tauri::AppHandle,
+    orchestrator: tauri::State<'_, TurnOrchestrator>,
+    policy: TurnPolicy,
+) -> Result<(), String> {
+    orchestrator.set_turn_policy(policy);
+    events::emit(&app, TurnPolicyChanged { policy });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_millis(600);
+
+    #[test]
+    fn finals_within_the_window_merge_into_one_message() {
+        let start = Instant::now();
+        let mut queue: TurnQueue<u32> = TurnQueue::new(WINDOW);
+        queue.push(Some(1), "我手里有你要的东".to_string(), None, start);
+        assert_eq!(queue.ready_at(), Some(start + WINDOW));
+
+        let later = start + Duration::from_millis(300);
+        queue.push(Some(2), "西。".to_string(), Some(7), later);
+        assert_eq!(queue.ready_at(), Some(later + WINDOW));
+
+        let merged = queue.take_merged().unwrap();
+        assert_eq!(merged.text, "我手里有你要的东西。");
+        assert_eq!(merged.turn_id, Some(2));
+        assert_eq!(merged.absorbed, vec![1]);
+        assert_eq!(merged.finals, 2);
+        assert_eq!(merged.waiters, vec![7]);
+        assert_eq!(queue.len(), 0);
+        assert!(queue.ready_at().is_none());
+        assert!(queue.take_merged().is_none());
+    }
+
+    #[test]
+    fn finals_a_window_apart_stay_separate_turns() {
+        let start = Instant::now();
+        let mut queue: TurnQueue<u32> = TurnQueue::new(WINDOW);
+        queue.push(Some(1), "a".to_string(), Some(1), start);
+        queue.push(Some(2), "b".to_string(), Some(2), start + WINDOW / 2);
+        let apart = start + WINDOW * 3;
+        queue.push(Some(3), "c".to_string(), Some(3), apart);
+        // The first two are ready once their own window is out
+        assert_eq!(queue.ready_at(), Some(start + WINDOW / 2 + WINDOW));
+
+        let first = queue.take_merged().unwrap();
+        assert_eq!(first.text, "a b");
+        assert_eq!(first.waiters, vec![1, 2]);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.ready_at(), Some(apart + WINDOW));
+
+        let second = queue.take_merged().unwrap();
+        assert_eq!(second.text, "c");
+        assert_eq!(second.turn_id, Some(3));
+        assert!(second.absorbed.is_empty());
+        assert!(queue.take_merged().is_none());
+    }
+
+    #[test]
+    fn merged_turn_keeps_the_newest_known_turn() {
+        let now = Instant::now();
+        let mut queue: TurnQueue<()> = TurnQueue::new(WINDOW);
+        queue.push(Some(3), "a".to_string(), None, now);
+        queue.push(None, "b".to_string(), None, now);
+        let merged = queue.take_merged().unwrap();
+        assert_eq!(merged.turn_id, Some(3));
+        assert!(merged.absorbed.is_empty());
+    }
+
+    #[test]
+    fn joins_words_with_spaces_and_cjk_without() {
+        assert_eq!(
+            join_utterances("turn on", "the lights"),
+            "turn on the lights"
+        );
+        assert_eq!(join_utterances("打开", "灯"), "打开灯");
+        assert_eq!(join_utterances("好的，", "then stop"), "好的，then stop");
+        assert_eq!(join_utterances("", " hi "), "hi");
+    }
+
+    #[test]
+    fn parses_policy_names() {
+        assert_eq!(TurnPolicy::parse(" Supersede"), Some(TurnPolicy::Supersede));
+        assert_eq!(TurnPolicy::parse("queue"), Some(TurnPolicy::Queue));
+        assert_eq!(TurnPolicy::parse("drop"), None);
+    }
+}
@@ -1,6 +1,14 @@
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::Manager;
+use ts_rs::TS;
+
+use crate::events::{self, TurnStateEvent};
+
+/// How many finished turns to keep for finals and replies that arrive late.
+const RECENT_TURN_MEMORY: usize = 16;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Role {
@@ -13,6 +21,10 @@ pub struct Message {
     pub role: Role,
     pub content: String,
     pub timestamp: u64,
+    /// An assistant reply the user talked over; `content` is only the part
+    /// that was played.
+    #[serde(default)]
+    pub interrupted: bool,
 }
 
 impl Message {
@@ -26,11 +38,12 @@ impl Message {
             role,
             content,
             timestamp,
+            interrupted: false,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
 pub enum ConversationStatus {
     Idle,
     Listening,
@@ -39,6 +52,63 @@ pub enum ConversationStatus {
     Speaking,
 }
 
+impl ConversationStatus {
+    /// A turn runs Listening → FinalizingASR → Thinking → Speaking (typed
+    /// messages start at Thinking) and may fall back to Idle from anywhere.
+    pub fn can_transition_to(self, next: ConversationStatus) -> bool {
+        use ConversationStatus::*;
+        matches!(
+            (self, next),
+            (_, Idle)
+                | (Idle, Listening)
+                | (Idle, Thinking)
+                | (Listening, FinalizingASR)
+                | (FinalizingASR, Thinking)
+                | (Thinking, Speaking)
+        )
+    }
+}
+
+/// When a turn entered a state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateEntry {
+    pub status: ConversationStatus,
+    /// Unix time in milliseconds.
+    pub at_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransitionError {
+    /// Too old to be remembered, or never existed.
+    UnknownTurn(usize),
+    /// The turn went back to Idle; a new turn must be started instead.
+    TurnFinished(usize),
+    Illegal {
+        turn_id: usize,
+        from: ConversationStatus,
+        to: ConversationStatus,
+    },
+}
+
+impl std::fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownTurn(id) => write!(f, "turn {} is unknown", id),
+            Self::TurnFinished(id) => write!(f, "turn {} has already finished", id),
+            Self::Illegal { turn_id, from, to } => {
+                write!(f, "turn {} cannot go from {:?} to {:?}", turn_id, from, to)
+            }
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Turn {
     pub id: usize,
@@ -46,6 +116,12 @@ pub struct Turn {
     pub asr_segment_id: Option<usize>,
     pub llm_request_id: Option<String>,
     pub tts_stream_id: Option<usize>,
+    /// The user talked over the reply and it was cut short.
+    #[serde(default)]
+    pub interrupted: bool,
+    /// Every state the turn entered, oldest first.
+    #[serde(default)]
+    pub states: Vec<StateEntry>,
 }
 
 impl Turn {
@@ -56,14 +132,27 @@ impl Turn {
             asr_segment_id: None,
             llm_request_id: None,
             tts_stream_id: None,
+            interrupted: false,
+            states: vec![StateEntry {
+                status: ConversationStatus::Idle,
+                at_ms: now_ms(),
+            }],
         }
     }
+
+    /// Back in Idle after having been active.
+    pub fn is_finished(&self) -> bool {
+        self.status == ConversationStatus::Idle && self.states.len() > 1
+    }
 }
 
 pub struct ConversationState {
     history: Vec<Message>,
     current_turn: Turn,
     next_turn_id: usize,
+    next_tts_stream_id: usize,
+    /// Turns overtaken by `current_turn`, newest last.
+    recent_turns: VecDeque<Turn>,
 }
 
 impl ConversationState {
@@ -72,69 +161,135 @@ impl ConversationState {
             history: Vec::new(),
             current_turn: Turn::new(0),
             next_turn_id: 1,
+            next_tts_stream_id: 1,
+            recent_turns: VecDeque::new(),
         }
     }
 
-    pub fn add_message(&mut self, message: Message) {
+    /// Append to the history and return the message's index.
+    pub fn add_message(&mut self, message: Message) -> usize {
         self.history.push(message);
+        self.history.len() - 1
+    }
+
+    /// Cut a recorded reply down to what the user heard before talking over it.
+    pub fn truncate_interrupted_reply(&mut self, index: usize, heard: String) {
+        if let Some(message) = self.history.get_mut(index) {
+            message.content = heard;
+            message.interrupted = true;
+        }
     }
 
     pub fn get_history(&self) -> &Vec<Message> {
         &self.history
     }
 
-    pub fn start_new_turn(&mut self) {
-        self.current_turn = Turn::new(self.next_turn_id);
+    /// Make a fresh Idle turn current and return its ID. The previous turn
+    /// carries on in the background until it finishes.
+    pub fn start_new_turn(&mut self) -> usize {
+        let finished = std::mem::replace(&mut self.current_turn, Turn::new(self.next_turn_id));
         self.next_turn_id += 1;
+        if self.recent_turns.len() == RECENT_TURN_MEMORY {
+            self.recent_turns.pop_front();
+        }
+        self.recent_turns.push_back(finished);
+        self.current_turn.id
+    }
+
+    fn turn_mut(&mut self, turn_id: usize) -> Option<&mut Turn> {
+        std::iter::once(&mut self.current_turn)
+            .chain(self.recent_turns.iter_mut())
+            .find(|turn| turn.id == turn_id)
+    }
+
+    /// Move a turn to `to` if the state machine allows it, recording when.
+    /// Staying in the same state is a no-op and yields no event.
+    pub fn transition(
+        &mut self,
+        turn_id: usize,
+        to: ConversationStatus,
+    ) -> Result<Option<TurnStateEvent>, TransitionError> {
+        let turn = self
+            .turn_mut(turn_id)
+            .ok_or(TransitionError::UnknownTurn(turn_id))?;
+        let from = turn.status;
+        if from == to {
+            return Ok(None);
+        }
+        if turn.is_finished() {
+            return Err(TransitionError::TurnFinished(turn_id));
+        }
+        if !from.can_transition_to(to) {
+            return Err(TransitionError::Illegal { turn_id, from, to });
+        }
+
+        let at_ms = now_ms();
+        turn.status = to;
+        turn.states.push(StateEntry { status: to, at_ms });
+        Ok(Some(TurnStateEvent {
+            turn_id,
+            previous: from,
+            state: to,
+            at_ms,
+        }))
+    }
+
+    /// Turns still speaking their reply, newest first.
+    pub fn speaking_turns(&self) -> Vec<usize> {
+        std::iter::once(&self.current_turn)
+            .chain(self.recent_turns.iter().rev())
+            .filter(|turn| turn.status == ConversationStatus::Speaking)
+            .map(|turn| turn.id)
+            .collect()
     }
 
-    #[allow(dead_code)]
     pub fn get_current_turn(&self) -> &Turn {
         &self.current_turn
     }
 
-    pub fn set_turn_status(&mut self, status: ConversationStatus) {
-        self.current_turn.status = status;
+    /// A turn still being answered may have been overtaken by a newer one;
+    /// only the current turn's fields may be updated.
+    pub fn is_current_turn(&self, turn_id: usize) -> bool {
+        self.current_turn.id == turn_id
     }
 
     pub fn get_turn_status(&self) -> ConversationStatus {
         self.current_turn.status
     }
 
-    #[allow(dead_code)]
+    /// The utterance (see `audio::utterance`) that opened this turn.
     pub fn set_asr_segment_id(&mut self, id: usize) {
         self.current_turn.asr_segment_id = Some(id);
     }
 
-    #[allow(dead_code)]
-    pub fn set_llm_request_id(&mut self, id: String) {
-        self.current_turn.llm_request_id = Some(id);
-    }
-
-    #[allow(dead_code)]
-    pub fn set_tts_stream_id(&mut self, id: usize) {
-        self.current_turn.tts_stream_id = Some(id);
-    }
-
-    pub fn transition_idle(&mut self) {
-        self.set_turn_status(ConversationStatus::Idle);
+    /// The turn an utterance opened, even if a newer turn has since started.
+    pub fn turn_for_segment(&self, segment_id: usize) -> Option<usize> {
+        std::iter::once(&self.current_turn)
+            .chain(self.recent_turns.iter().rev())
+            .find(|turn| turn.asr_segment_id == Some(segment_id))
+            .map(|turn| turn.id)
     }
 
-    pub fn transition_listening(&mut self) {
-        self.start_new_turn();
-        self.set_turn_status(ConversationStatus::Listening);
+    /// Flag a turn's reply as cut short, even if a newer turn has started.
+    pub fn mark_interrupted(&mut self, turn_id: usize) {
+        if let Some(turn) = self.turn_mut(turn_id) {
+            turn.interrupted = true;
+        }
     }
 
-    pub fn transition_finalizing_asr(&mut self) {
-        self.set_turn_status(ConversationStatus::FinalizingASR);
+    pub fn set_llm_request_id(&mut self, id: String) {
+        self.current_turn.llm_request_id = Some(id);
     }
 
-    pub fn transition_thinking(&mut self) {
-        self.set_turn_status(ConversationStatus::Thinking);
+    /// Each reply opens its own TTS stream; this allocates its ID.
+    pub fn next_tts_stream_id(&mut self) -> usize {
+        let id = self.next_tts_stream_id;
+        self.next_tts_stream_id += 1;
+        id
     }
 
-    pub fn transition_speaking(&mut self) {
-        self.set_turn_status(ConversationStatus::Speaking);
+    pub fn set_tts_stream_id(&mut self, id: usize) {
+        self.current_turn.tts_stream_id = Some(id);
     }
 
     #[allow(dead_code)]
@@ -150,6 +305,31 @@ impl ConversationState {
     }
 }
 
+/// Move a turn to `status` and announce it as `voice_assistant:turn_state`.
+/// A transition the state machine rejects is logged and changes nothing.
+pub fn set_turn_status(app: &tauri::AppHandle, turn_id: Option<usize>, status: ConversationStatus) {
+    let Some(turn_id) = turn_id else {
+        return;
+    };
+    let Some(conv_state) = app.try_state::<Arc<Mutex<ConversationState>>>() else {
+        return;
+    };
+    let result = conv_state.lock().unwrap().transition(turn_id, status);
+    match result {
+        Ok(Some(event)) => events::emit(app, event),
+        Ok(None) => {}
+        Err(e) => eprintln!("Rejected turn transition: {}", e),
+    }
+}
+
+/// `set_turn_status` for whichever turn is current.
+pub fn set_current_turn_status(app: &tauri::AppHandle, status: ConversationStatus) {
+    let turn_id = app
+        .try_state::<Arc<Mutex<ConversationState>>>()
+        .map(|conv_state| conv_state.lock().unwrap().get_current_turn().id);
+    set_turn_status(app, turn_id, status);
+}
+
 #[tauri::command]
 pub fn get_conversation_history(
     state: tauri::State<'_, Arc<Mutex<ConversationState>>>,
@@ -164,25 +344,40 @@ pub fn get_conversation_status(
     state.lock().unwrap().get_turn_status()
 }
 
+#[tauri::command]
+pub fn get_current_turn(state: tauri::State<'_, Arc<Mutex<ConversationState>>>) -> Turn {
+    state.lock().unwrap().get_current_turn().clone()
+}
+
 #[tauri::command]
 pub fn is_conversation_active(state: tauri::State<'_, Arc<Mutex<ConversationState>>>) -> bool {
     state.lock().unwrap().is_active()
 }
 
+/// Move the current turn to `new_status`. Listening after a finished turn
+/// starts a new one.
 #[tauri::command]
 pub fn transition_conversation_status(
+    app: tauri::AppHandle,
     state: tauri::State<'_, Arc<Mutex<ConversationState>>>,
     new_status: ConversationStatus,
-) {
-    let mut conv_state = state.lock().unwrap();
-
-    match new_status {
-        ConversationStatus::Idle => conv_state.transition_idle(),
-        ConversationStatus::Listening => conv_state.transition_listening(),
-        ConversationStatus::FinalizingASR => conv_state.transition_finalizing_asr(),
-        ConversationStatus::Thinking => conv_state.transition_thinking(),
-        ConversationStatus::Speaking => conv_state.transition_speaking(),
+) -> Result<(), String> {
+    let event = {
+        let mut conv_state = state.lock().unwrap();
+        if new_status == ConversationStatus::Listening
+            && conv_state.get_current_turn().is_finished()
+        {
+            conv_state.start_new_turn();
+        }
+        let turn_id = conv_state.get_current_turn().id;
+        conv_state
+            .transition(turn_id, new_status)
+            .map_err(|e| e.to_string())?
+    };
+    if let Some(event) = event {
+        events::emit(&app, event);
     }
+    Ok(())
 }
 
 #[tauri::command]
@@ -201,3 +396,85 @@ pub fn add_assistant_message(
     let message = Message::new(Role::Assistant, content);
     conv_state.add_message(message);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ConversationStatus::*;
+
+    #[test]
+    fn a_spoken_turn_runs_through_every_state() {
+        let mut conv = ConversationState::new();
+        let id = conv.start_new_turn();
+        for status in [Listening, FinalizingASR, Thinking, Speaking, Idle] {
+            let event = conv.transition(id, status).unwrap().unwrap();
+            assert_eq!(event.state, status);
+        }
+        let turn = conv.get_current_turn();
+        assert!(turn.is_finished());
+        let entered: Vec<_> = turn.states.iter().map(|entry| entry.status).collect();
+        assert_eq!(
+            entered,
+            vec![Idle, Listening, FinalizingASR, Thinking, Speaking, Idle]
+        );
+    }
+
+    #[test]
+    fn illegal_transitions_are_rejected_and_change_nothing() {
+        let mut conv = ConversationState::new();
+        let id = conv.start_new_turn();
+        conv.transition(id, Listening).unwrap();
+        conv.transition(id, FinalizingASR).unwrap();
+        conv.transition(id, Thinking).unwrap();
+        conv.transition(id, Speaking).unwrap();
+        assert_eq!(
+            conv.transition(id, FinalizingASR),
+            Err(TransitionError::Illegal {
+                turn_id: id,
+                from: Speaking,
+                to: FinalizingASR,
+            })
+        );
+        assert_eq!(conv.get_turn_status(), Speaking);
+    }
+
+    #[test]
+    fn same_state_is_a_no_op() {
+        let mut conv = ConversationState::new();
+        let id = conv.start_new_turn();
+        conv.transition(id, Listening).unwrap();
+        assert_eq!(conv.transition(id, Listening), Ok(None));
+        assert_eq!(conv.get_current_turn().states.len(), 2);
+    }
+
+    #[test]
+    fn finished_turns_stay_finished() {
+        let mut conv = ConversationState::new();
+        let id = conv.start_new_turn();
+        conv.transition(id, Thinking).unwrap();
+        conv.transition(id, Idle).unwrap();
+        assert_eq!(
+            conv.transition(id, Listening),
+            Err(TransitionError::TurnFinished(id))
+        );
+        assert_eq!(
+            conv.transition(99, Idle),
+            Err(TransitionError::UnknownTurn(99))
+        );
+    }
+
+    #[test]
+    fn overtaken_turns_keep_their_own_state() {
+        let mut conv = ConversationState::new();
+        let first = conv.start_new_turn();
+        conv.transition(first, Thinking).unwrap();
+        conv.transition(first, Speaking).unwrap();
+
+        let second = conv.start_new_turn();
+        conv.transition(second, Listening).unwrap();
+        assert_eq!(conv.speaking_turns(), vec![first]);
+        conv.transition(first, Idle).unwrap();
+        assert!(conv.speaking_turns().is_empty());
+        assert_eq!(conv.get_turn_status(), Listening);
+    }
+}
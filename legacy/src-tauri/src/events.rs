@@ -0,0 +1,380 @@
+//! Every event the core sends to the webview.
+//!
+//! Each payload is a type implementing `Event`, which ties it to its
+//! `voice_assistant:*` name, and goes out through `emit`, which stamps it
+//! with `schema_version`. The TypeScript side (`src/bindings/events.ts`) is
+//! generated from these types; the `typescript_bindings_are_current` test
+//! fails when it is stale. Regenerate it with
+//! `UPDATE_EVENT_BINDINGS=1 cargo test typescript_bindings`.
+
+use serde::Serialize;
+use tauri::Emitter;
+use ts_rs::TS;
+
+use crate::audio::asr_protocol::AsrCapabilities;
+use crate::audio::state::{AsrTranscript, EndpointingMode};
+use crate::audio::utterance_filter::DiscardReason;
+use crate::conversation::interruption::InterruptionPolicy;
+use crate::conversation::scheduler::TurnPolicy;
+use crate::conversation::ConversationStatus;
+use crate::llm::LlmChunk;
+
+/// Bumped whenever a payload changes incompatibly.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+pub trait Event: Serialize + Clone + TS + 'static {
+    const NAME: &'static str;
+}
+
+#[derive(Clone, Serialize)]
+struct Envelope<'a, E> {
+    schema_version: u32,
+    #[serde(flatten)]
+    payload: &'a E,
+}
+
+/// Send an event to the webview.
+pub fn emit<E: Event>(app: &tauri::AppHandle, event: E) {
+    let envelope = Envelope {
+        schema_version: EVENT_SCHEMA_VERSION,
+        payload: &event,
+    };
+    if let Err(e) = app.emit(E::NAME, envelope) {
+        eprintln!("Failed to emit {}: {}", E::NAME, e);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, TS)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceStatus {
+    Offline,
+    Starting,
+    Ready,
+    /// Usable without TTS: replies are only shown.
+    Degraded,
+    Reconnecting,
+    Error,
+}
+
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct ServiceStatusEvent {
+    pub status: ServiceStatus,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, TS)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    /// Informational; never stops the pipeline.
+    Warning,
+}
+
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct ErrorEvent {
+    pub code: String,
+    pub message: String,
+    pub severity: Severity,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, TS)]
+#[serde(rename_all = "snake_case")]
+pub enum VadStatus {
+    SpeechStart,
+    SpeechEnd,
+}
+
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct VadEvent {
+    pub status: VadStatus,
+}
+
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct VadModeEvent {
+    /// Hands-free, whichever side endpoints.
+    pub auto: bool,
+    pub mode: EndpointingMode,
+}
+
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct AudioLevel {
+    pub level: f32,
+}
+
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct UtteranceCancelled {
+    pub utterance_id: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct UtteranceDiscarded {
+    pub utterance_id: Option<usize>,
+    pub reason: DiscardReason,
+    pub text: Option<String>,
+}
+
+/// The text that is answered, after vocabulary correction.
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct UtteranceFinal {
+    pub utterance_id: Option<usize>,
+    pub text: String,
+    /// As the ASR server sent it.
+    pub raw_text: String,
+    pub confidence: f32,
+    pub language: Option<String>,
+    /// The server never finalized; this is its last partial.
+    pub from_partial: bool,
+}
+
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct PlaybackStarted {}
+
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct PlaybackEnded {}
+
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct PlaybackPaused {}
+
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct PlaybackResumed {}
+
+/// Queued audio was dropped by a barge-in.
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct PlaybackFlushed {}
+
+/// Sent when playback moves on to the next chunk of the reply, and every
+/// quarter second while it plays.
+#[derive(Clone, Debug, PartialEq, Serialize, TS)]
+pub struct PlaybackProgress {
+    /// The `LlmChunk` being spoken, when the TTS server said.
+    pub text_id: Option<usize>,
+    /// Audio played since playback started.
+    pub elapsed_ms: u32,
+    /// Audio queued and not yet played.
+    pub buffered_ms: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, TS)]
+pub struct TurnStateEvent {
+    pub turn_id: usize,
+    pub previous: ConversationStatus,
+    pub state: ConversationStatus,
+    /// Unix time in milliseconds.
+    #[ts(type = "number")]
+    pub at_ms: u64,
+}
+
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct TurnInterrupted {
+    pub turn_id: Option<usize>,
+    /// The part of the reply that was played.
+    pub spoken_text: String,
+    pub played_fraction: f32,
+}
+
+/// Finals waiting for the next turn.
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct TurnsWaiting {
+    pub waiting: usize,
+}
+
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct InterruptionPolicyChanged {
+    pub policy: InterruptionPolicy,
+}
+
+#[derive(Clone, Debug, Serialize, TS)]
+pub struct TurnPolicyChanged {
+    pub policy: TurnPolicy,
+}
+
+macro_rules! events {
+    ($($event:ty => $name:literal,)*) => {
+        $(
+            impl Event for $event {
+                const NAME: &'static str = $name;
+            }
+        )*
+
+        /// `src/bindings/events.ts`: every payload and the types it uses,
+        /// and the map from event name to payload.
+        #[cfg(test)]
+        fn typescript_bindings() -> String {
+            let mut declarations = bindings::Declarations::default();
+            $(declarations.add::<$event>();)*
+            let mut map = String::new();
+            $(
+                map.push_str(&format!(
+                    "  \"{}\": {};\n",
+                    $name,
+                    bindings::payload::<$event>()
+                ));
+            )*
+            bindings::render(declarations, &map)
+        }
+    };
+}
+
+events! {
+    ServiceStatusEvent => "voice_assistant:service_status",
+    ErrorEvent => "voice_assistant:error",
+    VadEvent => "voice_assistant:vad_status",
+    VadModeEvent => "voice_assistant:vad_mode",
+    AudioLevel => "voice_assistant:audio_level",
+    AsrCapabilities => "voice_assistant:asr_capabilities",
+    AsrTranscript => "voice_assistant:user_transcript",
+    UtteranceCancelled => "voice_assistant:utterance_cancelled",
+    UtteranceDiscarded => "voice_assistant:utterance_discarded",
+    UtteranceFinal => "voice_assistant:utterance_final",
+    LlmChunk => "voice_assistant:assistant_response",
+    PlaybackStarted => "voice_assistant:playback_started",
+    PlaybackEnded => "voice_assistant:playback_ended",
+    PlaybackPaused => "voice_assistant:playback_paused",
+    PlaybackResumed => "voice_assistant:playback_resumed",
+    PlaybackFlushed => "voice_assistant:playback_flushed",
+    PlaybackProgress => "voice_assistant:playback_progress",
+    TurnStateEvent => "voice_assistant:turn_state",
+    TurnInterrupted => "voice_assistant:turn_interrupted",
+    TurnsWaiting => "voice_assistant:turn_queue",
+    InterruptionPolicyChanged => "voice_assistant:interruption_policy",
+    TurnPolicyChanged => "voice_assistant:turn_policy",
+}
+
+#[cfg(test)]
+mod bindings {
+    use ts_rs::{TypeVisitor, TS};
+
+    use super::EVENT_SCHEMA_VERSION;
+
+    /// Declarations of every named type reachable from the events, each
+    /// after the types it uses.
+    #[derive(Default)]
+    pub struct Declarations {
+        seen: Vec<String>,
+        declarations: Vec<String>,
+    }
+
+    /// A type that gets its own declaration.
+    struct Named {
+        ident: String,
+        decl: fn() -> String,
+        dependencies: fn() -> Vec<Named>,
+        empty: bool,
+    }
+
+    fn named<T: TS + 'static + ?Sized>() -> Named {
+        Named {
+            ident: T::ident(),
+            decl: T::decl,
+            dependencies: dependencies::<T>,
+            empty: is_empty::<T>(),
+        }
+    }
+
+    /// Sorted, since ts-rs visits them in no fixed order.
+    fn dependencies<T: TS + 'static + ?Sized>() -> Vec<Named> {
+        struct Collect(Vec<Named>);
+        impl TypeVisitor for Collect {
+            fn visit<U: TS + 'static + ?Sized>(&mut self) {
+                // Primitives and std containers have nothing to declare
+                if U::output_path().is_some() {
+                    self.0.push(named::<U>());
+                }
+            }
+        }
+        let mut collect = Collect(Vec::new());
+        T::visit_dependencies(&mut collect);
+        collect.0.sort_by(|a, b| a.ident.cmp(&b.ident));
+        collect.0
+    }
+
+    impl Declarations {
+        pub fn add<T: TS + 'static>(&mut self) {
+            self.declare(named::<T>());
+        }
+
+        fn declare(&mut self, ty: Named) {
+            if ty.empty || self.seen.contains(&ty.ident) {
+                return;
+            }
+            self.seen.push(ty.ident);
+            for dependency in (ty.dependencies)() {
+                self.declare(dependency);
+            }
+            self.declarations.push(format!("export {}", (ty.decl)()));
+        }
+    }
+
+    /// A payload as the webview receives it. Events without fields carry
+    /// only the version.
+    pub fn payload<T: TS>() -> String {
+        if is_empty::<T>() {
+            VERSION_FIELD.to_string()
+        } else {
+            format!("{} & {}", T::ident(), VERSION_FIELD)
+        }
+    }
+
+    const VERSION_FIELD: &str = "{ schema_version: number }";
+
+    fn is_empty<T: TS + ?Sized>() -> bool {
+        T::inline() == "Record<string, never>"
+    }
+
+    pub fn render(declarations: Declarations, map: &str) -> String {
+        format!(
+            "// Generated from src-tauri/src/events.rs by \
+             `UPDATE_EVENT_BINDINGS=1 cargo test typescript_bindings`. Do not edit.\n\n\
+             export const EVENT_SCHEMA_VERSION = {};\n\n\
+             {}\n\n\
+             /** Event name to payload, as received by `listen`. */\n\
+             export type VoiceAssistantEvents = {{\n{}}};\n",
+            EVENT_SCHEMA_VERSION,
+            declarations.declarations.join("\n\n"),
+            map
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINDINGS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../src/bindings/events.ts");
+
+    #[test]
+    fn payloads_carry_the_schema_version() {
+        let envelope = Envelope {
+            schema_version: EVENT_SCHEMA_VERSION,
+            payload: &TurnsWaiting { waiting: 2 },
+        };
+        assert_eq!(
+            serde_json::to_value(envelope).unwrap(),
+            serde_json::json!({ "schema_version": EVENT_SCHEMA_VERSION, "waiting": 2 })
+        );
+
+        let envelope = Envelope {
+            schema_version: EVENT_SCHEMA_VERSION,
+            payload: &PlaybackEnded {},
+        };
+        assert_eq!(
+            serde_json::to_value(envelope).unwrap(),
+            serde_json::json!({ "schema_version": EVENT_SCHEMA_VERSION })
+        );
+    }
+
+    #[test]
+    fn typescript_bindings_are_current() {
+        let generated = typescript_bindings();
+        if std::env::var_os("UPDATE_EVENT_BINDINGS").is_some() {
+            std::fs::write(BINDINGS, &generated).unwrap();
+            return;
+        }
+        let current = std::fs::read_to_string(BINDINGS).unwrap_or_default();
+        assert!(
+            current == generated,
+            "{} is stale; run `UPDATE_EVENT_BINDINGS=1 cargo test typescript_bindings`",
+            BINDINGS
+        );
+    }
+}
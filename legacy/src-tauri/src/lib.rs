@@ -1,23 +1,36 @@
 mod audio;
 mod conversation;
+mod events;
 mod inference;
 mod llm;
 
 use audio::capture::{
-    close_mic, is_recording, is_service_active, open_mic, set_vad_mode, start_voice_service,
-    stop_voice_service,
+    cancel_utterance, close_mic, is_recording, is_service_active, open_mic, set_endpointing_mode,
+    set_vad_mode, start_voice_service, stop_voice_service,
 };
+use audio::output_device::{OutputDevicePreference, OUTPUT_DEVICE_FILE};
 use audio::playback::{
-    init_playback, is_playback_active, pause_playback, queue_playback_audio, resume_playback,
+    get_earcon_settings, get_playback_settings, get_playback_stats, is_playback_active,
+    list_output_devices, pause_playback, queue_playback_audio, replay_last_response,
+    resume_playback, rewind_sentence, set_earcon_enabled, set_earcon_volume, set_output_device,
+    set_playback_ducking, set_playback_rate, set_playback_volume, skip_to_next_sentence,
     start_playback, stop_playback,
 };
+use audio::service::{voice_service, VoiceService};
+use audio::vocabulary::{
+    add_vocabulary_term, list_vocabulary, remove_vocabulary_term, Vocabulary, VOCABULARY_FILE,
+};
+use conversation::interruption::{get_interruption_policy, set_interruption_policy};
+use conversation::scheduler::{get_turn_policy, set_turn_policy};
 use conversation::{
     add_assistant_message, add_user_message, get_conversation_history, get_conversation_status,
-    is_conversation_active, transition_conversation_status, ConversationState,
+    get_current_turn, is_conversation_active, transition_conversation_status, ConversationState,
+    TurnOrchestrator,
 };
 use inference::client::{test_asr_connection, test_tts_connection};
 use llm::{send_llm_request, stream_llm_response, LlmClient};
 use std::sync::{Arc, Mutex};
+use tauri::Manager;
 
 #[tauri::command]
 fn greet(name: &str) -> String {
@@ -44,8 +57,17 @@ pub fn run() {
         .manage(conversation_state)
         .manage(llm_client)
         .setup(|app| {
-            // Initialize playback with app handle for event emission
-            init_playback(app.handle().clone());
+            // Hot-word vocabulary and the output device live in the app config dir
+            let config_dir = app.path().app_config_dir().ok();
+            let vocabulary_path = config_dir.as_ref().map(|dir| dir.join(VOCABULARY_FILE));
+            app.manage(Arc::new(Mutex::new(Vocabulary::load(vocabulary_path))));
+            let output_device_path = config_dir.map(|dir| dir.join(OUTPUT_DEVICE_FILE));
+            app.manage(Arc::new(VoiceService::new(OutputDevicePreference::load(
+                output_device_path,
+            ))));
+
+            // ASR finals are answered in the backend; the UI only observes
+            app.manage(TurnOrchestrator::new(app.handle().clone()));
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -54,7 +76,13 @@ pub fn run() {
             stop_voice_service,
             open_mic,
             close_mic,
+            cancel_utterance,
             set_vad_mode,
+            set_endpointing_mode,
+            get_interruption_policy,
+            set_interruption_policy,
+            get_turn_policy,
+            set_turn_policy,
             is_recording,
             is_service_active,
             queue_playback_audio,
@@ -63,8 +91,22 @@ pub fn run() {
             pause_playback,
             resume_playback,
             is_playback_active,
+            get_playback_stats,
+            get_playback_settings,
+            set_playback_volume,
+            set_playback_rate,
+            replay_last_response,
+            skip_to_next_sentence,
+            rewind_sentence,
+            get_earcon_settings,
+            set_earcon_enabled,
+            set_earcon_volume,
+            set_playback_ducking,
+            list_output_devices,
+            set_output_device,
             get_conversation_history,
             get_conversation_status,
+            get_current_turn,
             is_conversation_active,
             transition_conversation_status,
             add_user_message,
@@ -72,8 +114,17 @@ pub fn run() {
             send_llm_request,
             stream_llm_response,
             test_asr_connection,
-            test_tts_connection
+            test_tts_connection,
+            list_vocabulary,
+            add_vocabulary_term,
+            remove_vocabulary_term
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // Release the output device before the process goes
+            if let tauri::RunEvent::Exit = event {
+                voice_service(app).playback.shutdown();
+            }
+        });
 }
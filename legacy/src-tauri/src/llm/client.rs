@@ -8,20 +8,39 @@ use futures::StreamExt;
 use futures_util::SinkExt;
 use serde::Serialize;
 use std::env;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
-use tauri::Emitter;
+use tauri::Manager;
+use tokio::sync::watch;
 use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
-
-use crate::audio::playback::queue_playback_audio;
-use crate::audio::state::{emit_pipeline_status, TTS_HOST, TTS_OK};
-use crate::conversation::{ConversationState, Message, Role};
+use ts_rs::TS;
+
+use crate::audio::playback::ReplyDecoder;
+use crate::audio::service::voice_service;
+use crate::audio::spoken::SpokenReply;
+use crate::audio::state::{emit_warning, TTS_HOST};
+use crate::audio::tts_audio::AudioFormat;
+use crate::conversation::{
+    set_turn_status, ConversationState, ConversationStatus, Message, Role, TurnOrchestrator,
+};
+use crate::events;
 
 const MIN_CHUNK_TOKENS: usize = 20;
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, TS)]
 pub struct LlmChunk {
     pub content: String,
     pub is_complete: bool,
+    /// Matches `PlaybackProgress::text_id` while this chunk is spoken.
+    pub text_id: Option<usize>,
+}
+
+/// What the orchestrator holds on to while a reply runs.
+pub struct ReplyHandle {
+    /// Turns true when the user barges in.
+    pub cancel: watch::Receiver<bool>,
+    /// How much of the reply has been played.
+    pub spoken: Arc<Mutex<SpokenReply>>,
 }
 
 #[derive(Debug, Serialize)]
@@ -110,6 +129,7 @@ impl LlmClient {
                                 chunks.push(LlmChunk {
                                     content: current_chunk.clone(),
                                     is_complete: false,
+                                    text_id: Some(chunks.len()),
                                 });
 
                                 current_chunk.clear();
@@ -127,6 +147,7 @@ impl LlmClient {
             chunks.push(LlmChunk {
                 content: current_chunk,
                 is_complete: true,
+                text_id: Some(chunks.len()),
             });
         } else if let Some(last) = chunks.last_mut() {
             last.is_complete = true;
@@ -159,6 +180,10 @@ fn convert_message_to_openai(
 ) -> Result<async_openai::types::ChatCompletionRequestMessage> {
     match msg.role {
         Role::User => create_user_message(msg.content.clone()),
+        // Tell the model where the user stopped listening
+        Role::Assistant if msg.interrupted => {
+            create_assistant_message(format!("{} [interrupted by the user]", msg.content))
+        }
         Role::Assistant => create_assistant_message(msg.content.clone()),
     }
 }
@@ -185,18 +210,48 @@ pub async fn send_llm_request(
     }
 }
 
-/// Stream LLM response with TTS integration
-/// This function:
-/// 1. Streams LLM response chunks in real-time
-/// 2. Sends each chunk to TTS for synthesis immediately
-/// 3. Queues received audio for playback
+/// Stream LLM response with TTS integration. Typed messages share the turn
+/// queue with spoken ones, so two replies never play at once.
 #[tauri::command]
 pub async fn stream_llm_response(
     app: tauri::AppHandle,
-    llm_state: tauri::State<'_, Arc<Mutex<LlmClient>>>,
-    conv_state: tauri::State<'_, Arc<Mutex<ConversationState>>>,
     user_message: String,
 ) -> Result<(), String> {
+    let reply = app
+        .state::<TurnOrchestrator>()
+        .submit_text(&app, user_message);
+    reply
+        .await
+        .map_err(|_| "Reply was dropped".to_string())?
+        .map(|_| ())
+}
+
+/// Answer one user message and return the full reply text. This:
+/// 1. Streams LLM response chunks in real-time
+/// 2. Sends each chunk to TTS for synthesis immediately
+/// 3. Queues received audio for playback
+/// 4. Records both messages in the conversation history
+///
+/// With a `turn_id`, the turn's status, LLM request ID and TTS stream ID
+/// are kept up to date along the way. Once the handle's `cancel` turns true
+/// the LLM stream is dropped, TTS is told to cancel and the queued audio
+/// flushed; history then records only the part of the reply that was
+/// played, flagged `interrupted`. The generated text is still returned.
+pub async fn run_assistant_reply(
+    app: &tauri::AppHandle,
+    user_message: String,
+    turn_id: Option<usize>,
+    handle: Option<ReplyHandle>,
+) -> Result<String, String> {
+    let (mut cancel, spoken) = match handle {
+        Some(handle) => (Some(handle.cancel), handle.spoken),
+        None => (None, Arc::new(Mutex::new(SpokenReply::new()))),
+    };
+
+    let llm_state = app.state::<Arc<Mutex<LlmClient>>>();
+    let conv_state = app.state::<Arc<Mutex<ConversationState>>>();
+    let service = voice_service(app);
+
     // Clone the client, model and history to release locks before await
     let (client, model, history) = {
         let llm_guard = llm_state.lock().unwrap();
@@ -230,25 +285,85 @@ pub async fn stream_llm_response(
     // text (graceful degradation) — never fail the whole turn on TTS.
     let (mut ws_writer, tts_receiver) = match connect_async(TTS_HOST).await {
         Ok((ws_stream, _)) => {
-            if !TTS_OK.swap(true, std::sync::atomic::Ordering::SeqCst) {
-                emit_pipeline_status(&app);
+            if !service.tts_ok.swap(true, Ordering::SeqCst) {
+                service.emit_pipeline_status(app);
+            }
+            if let Some(id) = turn_id {
+                let mut conv = conv_state.lock().unwrap();
+                let tts_stream_id = conv.next_tts_stream_id();
+                if conv.is_current_turn(id) {
+                    conv.set_tts_stream_id(tts_stream_id);
+                }
             }
             let (writer, mut ws_reader) = ws_stream.split();
+            let spoken = spoken.clone();
+            let playback = service.playback.clone();
+            let app_for_audio = app.clone();
 
             // Receive TTS audio continuously while LLM stream is still generating text.
             // This prevents the server-side send buffer from stalling and triggering ping timeouts.
             let receiver = tauri::async_runtime::spawn(async move {
+                // The chunk being spoken and its audio format, as announced
+                // by `audio_start`; frames in a format we can't play are dropped
+                let mut text_id: Option<usize> = None;
+                let mut format = Some(AudioFormat::default());
+                // Compressed chunks are decoded off this task
+                let mut decoder: Option<ReplyDecoder> = None;
                 while let Some(msg_result) = ws_reader.next().await {
                     match msg_result {
-                        Ok(WsMessage::Binary(audio_data)) => {
-                            if let Err(e) = queue_playback_audio(audio_data.to_vec()) {
-                                eprintln!("Failed to queue audio: {}", e);
+                        Ok(WsMessage::Binary(audio_data)) => match format {
+                            Some(format) if format.encoding.is_pcm() => {
+                                playback.queue_reply_audio(
+                                    &app_for_audio,
+                                    audio_data.to_vec(),
+                                    format,
+                                    &spoken,
+                                    text_id,
+                                );
                             }
-                        }
+                            Some(_) => {
+                                if let Some(decoder) = decoder.as_ref() {
+                                    decoder.push(audio_data.to_vec());
+                                }
+                            }
+                            None => {}
+                        },
                         Ok(WsMessage::Text(text)) => {
                             if let Ok(status) = serde_json::from_str::<serde_json::Value>(&text) {
-                                if status.get("type").and_then(|v| v.as_str()) == Some("complete") {
-                                    break;
+                                match status.get("type").and_then(|v| v.as_str()) {
+                                    Some("complete") => break,
+                                    Some("audio_start") => {
+                                        text_id = status
+                                            .get("text_id")
+                                            .and_then(|v| v.as_u64())
+                                            .map(|id| id as usize);
+                                        let bytes = status.get("bytes").and_then(|v| v.as_u64());
+                                        if let (Some(id), Some(bytes)) = (text_id, bytes) {
+                                            spoken.lock().unwrap().expect_audio(id, bytes as usize);
+                                        }
+                                        format = match AudioFormat::from_announcement(&status) {
+                                            Ok(format) => Some(format),
+                                            Err(e) => {
+                                                eprintln!("{}", e);
+                                                emit_warning(
+                                                    &app_for_audio,
+                                                    "TTS_FORMAT_UNSUPPORTED",
+                                                    &e,
+                                                );
+                                                None
+                                            }
+                                        };
+                                        if let Some(format) =
+                                            format.filter(|f| !f.encoding.is_pcm())
+                                        {
+                                            decoder
+                                                .get_or_insert_with(|| {
+                                                    playback.reply_decoder(&app_for_audio, &spoken)
+                                                })
+                                                .start(format.encoding, text_id);
+                                        }
+                                    }
+                                    _ => {}
                                 }
                             }
                         }
@@ -260,24 +375,25 @@ pub async fn stream_llm_response(
                         _ => {}
                     }
                 }
+                if let Some(decoder) = decoder.as_mut() {
+                    decoder.finish().await;
+                }
+                playback.play_buffered(&app_for_audio);
             });
 
             (Some(writer), Some(receiver))
         }
         Err(e) => {
             eprintln!("TTS unavailable, continuing text-only: {}", e);
-            if TTS_OK.swap(false, std::sync::atomic::Ordering::SeqCst) {
-                emit_pipeline_status(&app);
+            spoken.lock().unwrap().set_text_only();
+            if service.tts_ok.swap(false, Ordering::SeqCst) {
+                service.emit_pipeline_status(app);
             }
             (None, None)
         }
     };
 
-    // Emit state change to Thinking
-    let _ = app.emit(
-        "voice_assistant:state_changed",
-        serde_json::json!({ "state": "Thinking" }),
-    );
+    set_turn_status(app, turn_id, ConversationStatus::Thinking);
 
     // Build LLM request
     let request = CreateChatCompletionRequestArgs::default()
@@ -300,9 +416,20 @@ pub async fn stream_llm_response(
     let mut text_id = 0;
     let mut last_emit_time = std::time::Instant::now();
     let mut first_chunk = true;
+    let mut interrupted = false;
 
     // Process LLM stream in real-time
-    while let Some(result) = llm_stream.next().await {
+    loop {
+        let result = tokio::select! {
+            next = llm_stream.next() => match next {
+                Some(result) => result,
+                None => break,
+            },
+            _ = cancelled(&mut cancel) => {
+                interrupted = true;
+                break;
+            }
+        };
         match result {
             Ok(response) => {
                 if let Some(choice) = response.choices.first() {
@@ -310,10 +437,13 @@ pub async fn stream_llm_response(
                         // On first content, switch to Speaking state
                         if first_chunk {
                             first_chunk = false;
-                            let _ = app.emit(
-                                "voice_assistant:state_changed",
-                                serde_json::json!({ "state": "Speaking" }),
-                            );
+                            if let Some(id) = turn_id {
+                                let mut conv = conv_state.lock().unwrap();
+                                if conv.is_current_turn(id) {
+                                    conv.set_llm_request_id(response.id.clone());
+                                }
+                            }
+                            set_turn_status(app, turn_id, ConversationStatus::Speaking);
                         }
 
                         full_response.push_str(content);
@@ -340,12 +470,14 @@ pub async fn stream_llm_response(
                             let chunk = LlmChunk {
                                 content: current_chunk.clone(),
                                 is_complete: false,
+                                text_id: Some(text_id),
                             };
 
                             // Emit to frontend immediately
-                            let _ = app.emit("voice_assistant:assistant_response", &chunk);
+                            events::emit(app, chunk);
 
                             // Send to TTS
+                            spoken.lock().unwrap().add_text(text_id, &current_chunk);
                             let tts_request = TtsRequest {
                                 request_type: "text_chunk".to_string(),
                                 text: current_chunk.clone(),
@@ -374,13 +506,15 @@ pub async fn stream_llm_response(
     }
 
     // Send remaining chunk
-    if !current_chunk.is_empty() {
+    if !interrupted && !current_chunk.is_empty() {
         let chunk = LlmChunk {
             content: current_chunk.clone(),
             is_complete: false,
+            text_id: Some(text_id),
         };
-        let _ = app.emit("voice_assistant:assistant_response", &chunk);
+        events::emit(app, chunk);
 
+        spoken.lock().unwrap().add_text(text_id, &current_chunk);
         let tts_request = TtsRequest {
             request_type: "text_chunk".to_string(),
             text: current_chunk,
@@ -395,32 +529,73 @@ pub async fn stream_llm_response(
     }
 
     // Send end signal to TTS and wait for the remaining audio
+    if !interrupted {
+        if let Some(writer) = ws_writer.as_mut() {
+            let end_request = serde_json::json!({ "type": "end" });
+            let _ = writer.send(WsMessage::Text(end_request.to_string())).await;
+        }
+    }
+    if let Some(mut receiver) = tts_receiver {
+        if !interrupted {
+            tokio::select! {
+                _ = &mut receiver => {}
+                _ = cancelled(&mut cancel) => interrupted = true,
+            }
+        }
+        if interrupted {
+            receiver.abort();
+            let _ = receiver.await;
+            // Audio that slipped in before the abort
+            service.playback.flush(app);
+        }
+    }
     if let Some(mut writer) = ws_writer {
-        let end_request = serde_json::json!({ "type": "end" });
-        let _ = writer.send(WsMessage::Text(end_request.to_string())).await;
+        if interrupted {
+            let cancel_request = serde_json::json!({ "type": "cancel" });
+            let _ = writer
+                .send(WsMessage::Text(cancel_request.to_string()))
+                .await;
+        }
         let _ = writer.send(WsMessage::Close(None)).await;
     }
-    if let Some(receiver) = tts_receiver {
-        let _ = receiver.await;
-    }
 
-    // Persist the turn so later requests carry multi-turn context
+    // Persist the turn so later requests carry multi-turn context. Only
+    // what was played counts when the user cut the reply short; a barge-in
+    // after this point truncates the message through `message_index`.
     {
         let mut conv = conv_state.lock().unwrap();
         conv.add_message(Message::new(Role::User, user_message));
-        if !full_response.trim().is_empty() {
-            conv.add_message(Message::new(Role::Assistant, full_response.clone()));
+        let interrupted = interrupted || cancel.as_ref().is_some_and(|c| *c.borrow());
+        let mut spoken = spoken.lock().unwrap();
+        if interrupted {
+            let mut message = Message::new(Role::Assistant, spoken.heard_text());
+            message.interrupted = true;
+            spoken.message_index = Some(conv.add_message(message));
+        } else if !full_response.trim().is_empty() {
+            let message = Message::new(Role::Assistant, full_response.clone());
+            spoken.message_index = Some(conv.add_message(message));
         }
     }
 
     // Emit final complete event (empty content, just signal completion)
-    let _ = app.emit(
-        "voice_assistant:assistant_response",
+    events::emit(
+        app,
         LlmChunk {
             content: String::new(),
             is_complete: true,
+            text_id: None,
         },
     );
 
-    Ok(())
+    Ok(full_response)
+}
+
+/// Resolves once the reply is cancelled; never without a cancel handle.
+async fn cancelled(cancel: &mut Option<watch::Receiver<bool>>) {
+    if let Some(cancel) = cancel {
+        if cancel.wait_for(|cancelled| *cancelled).await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending::<()>().await
 }
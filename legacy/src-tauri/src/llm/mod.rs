@@ -1,3 +1,5 @@
 pub mod client;
 
-pub use client::{send_llm_request, stream_llm_response, LlmClient};
+pub use client::{
+    run_assistant_reply, send_llm_request, stream_llm_response, LlmChunk, LlmClient, ReplyHandle,
+};
@@ -0,0 +1,123 @@
+// Generated from src-tauri/src/events.rs by `UPDATE_EVENT_BINDINGS=1 cargo test typescript_bindings`. Do not edit.
+
+export const EVENT_SCHEMA_VERSION = 1;
+
+export type ServiceStatus = "offline" | "starting" | "ready" | "degraded" | "reconnecting" | "error";
+
+export type ServiceStatusEvent = { status: ServiceStatus, message: string, };
+
+export type Severity = "error" | "warning";
+
+export type ErrorEvent = { code: string, message: string, severity: Severity, };
+
+export type VadStatus = "speech_start" | "speech_end";
+
+export type VadEvent = { status: VadStatus, };
+
+export type EndpointingMode = "manual" | "client" | "server";
+
+export type VadModeEvent = { 
+/**
+ * Hands-free, whichever side endpoints.
+ */
+auto: boolean, mode: EndpointingMode, };
+
+export type AudioLevel = { level: number, };
+
+export type AsrCapabilities = { protocol_version: number, server: string | null, sample_rates: Array<number>, encodings: Array<string>, languages: Array<string>, features: Array<string>, };
+
+export type AsrAlternative = { text: string, confidence: number | null, };
+
+export type AsrWord = { word: string, start: number | null, end: number | null, confidence: number | null, };
+
+export type AsrTranscript = { 
+/**
+ * The utterance this text belongs to (see `audio::utterance`).
+ */
+utterance_id: number | null, partial: string, final: string | null, confidence: number, words?: Array<AsrWord>, alternatives?: Array<AsrAlternative>, language?: string, };
+
+export type UtteranceCancelled = { utterance_id: number | null, };
+
+export type DiscardReason = "empty" | "too_short" | "too_quiet" | "hallucination" | "backchannel";
+
+export type UtteranceDiscarded = { utterance_id: number | null, reason: DiscardReason, text: string | null, };
+
+export type UtteranceFinal = { utterance_id: number | null, text: string, 
+/**
+ * As the ASR server sent it.
+ */
+raw_text: string, confidence: number, language: string | null, 
+/**
+ * The server never finalized; this is its last partial.
+ */
+from_partial: boolean, };
+
+export type LlmChunk = { content: string, is_complete: boolean, 
+/**
+ * Matches `PlaybackProgress::text_id` while this chunk is spoken.
+ */
+text_id: number | null, };
+
+export type PlaybackProgress = { 
+/**
+ * The `LlmChunk` being spoken, when the TTS server said.
+ */
+text_id: number | null, 
+/**
+ * Audio played since playback started.
+ */
+elapsed_ms: number, 
+/**
+ * Audio queued and not yet played.
+ */
+buffered_ms: number, };
+
+export type ConversationStatus = "Idle" | "Listening" | "FinalizingASR" | "Thinking" | "Speaking";
+
+export type TurnStateEvent = { turn_id: number, previous: ConversationStatus, state: ConversationStatus, 
+/**
+ * Unix time in milliseconds.
+ */
+at_ms: number, };
+
+export type TurnInterrupted = { turn_id: number | null, 
+/**
+ * The part of the reply that was played.
+ */
+spoken_text: string, played_fraction: number, };
+
+export type TurnsWaiting = { waiting: number, };
+
+export type InterruptionPolicy = "pause_resume" | "cancel" | "cancel_if_substantive";
+
+export type InterruptionPolicyChanged = { policy: InterruptionPolicy, };
+
+export type TurnPolicy = "queue" | "supersede";
+
+export type TurnPolicyChanged = { policy: TurnPolicy, };
+
+/** Event name to payload, as received by `listen`. */
+export type VoiceAssistantEvents = {
+  "voice_assistant:service_status": ServiceStatusEvent & { schema_version: number };
+  "voice_assistant:error": ErrorEvent & { schema_version: number };
+  "voice_assistant:vad_status": VadEvent & { schema_version: number };
+  "voice_assistant:vad_mode": VadModeEvent & { schema_version: number };
+  "voice_assistant:audio_level": AudioLevel & { schema_version: number };
+  "voice_assistant:asr_capabilities": AsrCapabilities & { schema_version: number };
+  "voice_assistant:user_transcript": AsrTranscript & { schema_version: number };
+  "voice_assistant:utterance_cancelled": UtteranceCancelled & { schema_version: number };
+  "voice_assistant:utterance_discarded": UtteranceDiscarded & { schema_version: number };
+  "voice_assistant:utterance_final": UtteranceFinal & { schema_version: number };
+  "voice_assistant:assistant_response": LlmChunk & { schema_version: number };
+  "voice_assistant:playback_started": { schema_version: number };
+  "voice_assistant:playback_ended": { schema_version: number };
+  "voice_assistant:playback_paused": { schema_version: number };
+  "voice_assistant:playback_resumed": { schema_version: number };
+  "voice_assistant:playback_flushed": { schema_version: number };
+  "voice_assistant:playback_progress": PlaybackProgress & { schema_version: number };
+  "voice_assistant:turn_state": TurnStateEvent & { schema_version: number };
+  "voice_assistant:turn_interrupted": TurnInterrupted & { schema_version: number };
+  "voice_assistant:turn_queue": TurnsWaiting & { schema_version: number };
+  "voice_assistant:interruption_policy": InterruptionPolicyChanged & { schema_version: number };
+  "voice_assistant:turn_policy": TurnPolicyChanged & { schema_version: number };
+};
@@ -1,13 +1,15 @@
 import { useEffect, useState, useCallback } from 'react';
-import { listen, UnlistenFn } from '@tauri-apps/api/event';
+import { UnlistenFn } from '@tauri-apps/api/event';
 import { invoke } from '@tauri-apps/api/core';
 
 import {
-  ConversationStatus,
+  EndpointingMode,
+  InterruptionPolicy,
   ServiceStatus,
-  pumpLlmQueue,
+  TurnPolicy,
   useConversation,
 } from './voiceStore';
+import { listenEvent } from './events';
 
 const SERVICE_LABELS: Record<ServiceStatus, string> = {
   offline: '服务未启动',
@@ -44,12 +46,15 @@ export function VoiceAssistant() {
     serviceStatus,
     serviceMessage,
     autoVad,
+    endpointing,
+    interruptionPolicy,
+    turnPolicy,
     micOpen,
     playbackPaused,
     transcript,
     audioLevel,
     pendingAssistantResponse,
-    utteranceQueue,
+    waitingTurns,
     errorBanner,
   } = useConversation();
 
@@ -62,89 +67,130 @@ export function VoiceAssistant() {
 
     const setupListeners = async () => {
       unlisteners.push(
-        await listen<{ status: ServiceStatus; message: string }>(
-          'voice_assistant:service_status',
-          (e) => {
-            store().setServiceStatus(e.payload.status, e.payload.message);
-            if (e.payload.status === 'offline' || e.payload.status === 'error') {
-              store().setMicOpen(false);
-            }
+        await listenEvent('voice_assistant:service_status', (e) => {
+          store().setServiceStatus(e.payload.status, e.payload.message);
+          if (e.payload.status === 'offline' || e.payload.status === 'error') {
+            store().setMicOpen(false);
           }
-        )
+        })
       );
 
+      // Show the newest turn; an older reply finishing must not override it
+      let latestTurn = -1;
       unlisteners.push(
-        await listen<{ state: ConversationStatus }>('voice_assistant:state_changed', (e) => {
-          // While the mic is open, ignore Idle from playback completion —
-          // pipeline states run in parallel with capture now.
-          if (e.payload.state === 'Idle' && store().micOpen) return;
+        await listenEvent('voice_assistant:turn_state', (e) => {
+          if (e.payload.turn_id < latestTurn) return;
+          latestTurn = e.payload.turn_id;
           store().setStatus(e.payload.state);
         })
       );
 
       // In auto-VAD mode the backend detects speech; mirror it in the UI
       unlisteners.push(
-        await listen<{ status: string }>('voice_assistant:vad_status', (e) => {
+        await listenEvent('voice_assistant:vad_status', (e) => {
           if (e.payload.status === 'speech_start') store().setMicOpen(true);
           if (e.payload.status === 'speech_end') store().setMicOpen(false);
         })
       );
 
       unlisteners.push(
-        await listen<{ auto: boolean }>('voice_assistant:vad_mode', (e) => {
-          store().setAutoVad(e.payload.auto);
+        await listenEvent('voice_assistant:vad_mode', (e) => {
+          store().setEndpointing(e.payload.mode);
         })
       );
 
       unlisteners.push(
-        await listen<{ partial: string; final: string | null }>(
-          'voice_assistant:user_transcript',
-          (e) => {
-            const { partial, final } = e.payload;
-            if (final) store().setTranscript(final);
-            else if (partial) store().setTranscript(partial);
-          }
+        await listenEvent('voice_assistant:interruption_policy', (e) =>
+          store().setInterruptionPolicy(e.payload.policy)
+        )
+      );
+      invoke<InterruptionPolicy>('get_interruption_policy')
+        .then((policy) => store().setInterruptionPolicy(policy))
+        .catch((error) => console.error('Failed to read interruption policy:', error));
+
+      unlisteners.push(
+        await listenEvent('voice_assistant:turn_policy', (e) =>
+          store().setTurnPolicy(e.payload.policy)
         )
       );
+      invoke<TurnPolicy>('get_turn_policy')
+        .then((policy) => store().setTurnPolicy(policy))
+        .catch((error) => console.error('Failed to read turn policy:', error));
 
-      // Authoritative end-of-utterance text (with punctuation) → LLM queue
       unlisteners.push(
-        await listen<{ text: string }>('voice_assistant:utterance_final', (e) => {
+        await listenEvent('voice_assistant:user_transcript', (e) => {
+          const { partial, final } = e.payload;
+          if (final) store().setTranscript(final);
+          else if (partial) store().setTranscript(partial);
+        })
+      );
+
+      // Authoritative end-of-utterance text (with punctuation). The backend
+      // orchestrator answers it; the UI only shows it.
+      unlisteners.push(
+        await listenEvent('voice_assistant:utterance_final', (e) => {
           const s = store();
           const text = (e.payload.text || s.transcript).trim();
           s.setTranscript('');
-          if (!text) {
-            if (!s.micOpen) s.setStatus('Idle');
-            return;
-          }
-          s.addMessage({ role: 'user', content: text });
-          s.enqueueUtterance(text);
-          void pumpLlmQueue();
+          if (text) s.addMessage({ role: 'user', content: text });
         })
       );
 
       unlisteners.push(
-        await listen<{ content: string; is_complete: boolean }>(
-          'voice_assistant:assistant_response',
-          (e) => store().appendAssistantResponse(e.payload.content, e.payload.is_complete)
+        await listenEvent('voice_assistant:turn_queue', (e) =>
+          store().setWaitingTurns(e.payload.waiting)
         )
       );
 
+      // Noise, a quick tap or an ASR hallucination: drop the partial text
       unlisteners.push(
-        await listen<{ level: number }>('voice_assistant:audio_level', (e) =>
+        await listenEvent('voice_assistant:utterance_discarded', (e) => {
+          console.info(`Utterance discarded (${e.payload.reason})`, e.payload.text ?? '');
+          store().setTranscript('');
+        })
+      );
+
+      unlisteners.push(
+        await listenEvent('voice_assistant:assistant_response', (e) =>
+          store().appendAssistantResponse(e.payload.content, e.payload.is_complete)
+        )
+      );
+
+      unlisteners.push(
+        await listenEvent('voice_assistant:audio_level', (e) =>
           store().setAudioLevel(e.payload.level)
         )
       );
 
       unlisteners.push(
-        await listen('voice_assistant:playback_paused', () => store().setPlaybackPaused(true))
+        await listenEvent('voice_assistant:playback_paused', () => store().setPlaybackPaused(true))
+      );
+      unlisteners.push(
+        await listenEvent('voice_assistant:playback_resumed', () =>
+          store().setPlaybackPaused(false)
+        )
+      );
+      // Barge-in cancelled the reply: its queued audio is gone
+      unlisteners.push(
+        await listenEvent('voice_assistant:playback_flushed', () =>
+          store().setPlaybackPaused(false)
+        )
       );
       unlisteners.push(
-        await listen('voice_assistant:playback_resumed', () => store().setPlaybackPaused(false))
+        await listenEvent('voice_assistant:turn_interrupted', (e) =>
+          console.info(
+            `Turn ${e.payload.turn_id} interrupted after ${Math.round(e.payload.played_fraction * 100)}%:`,
+            e.payload.spoken_text
+          )
+        )
       );
 
       unlisteners.push(
-        await listen<{ code: string; message: string }>('voice_assistant:error', (e) => {
+        await listenEvent('voice_assistant:error', (e) => {
+          if (e.payload.severity === 'warning') {
+            console.warn(`${e.payload.code}: ${e.payload.message}`);
+            return;
+          }
           store().setErrorBanner(`${e.payload.code}: ${e.payload.message}`);
         })
       );
@@ -189,15 +235,31 @@ export function VoiceAssistant() {
     }
   }, []);
 
-  const handleToggleAutoVad = useCallback(async () => {
+  const handleSetEndpointing = useCallback(async (mode: EndpointingMode) => {
     const s = useConversation.getState();
     try {
-      await invoke('set_vad_mode', { auto: !s.autoVad });
+      await invoke('set_endpointing_mode', { mode });
     } catch (error) {
       s.setErrorBanner(`切换模式失败: ${error}`);
     }
   }, []);
 
+  const handleSetInterruptionPolicy = useCallback(async (policy: InterruptionPolicy) => {
+    try {
+      await invoke('set_interruption_policy', { policy });
+    } catch (error) {
+      useConversation.getState().setErrorBanner(`切换打断策略失败: ${error}`);
+    }
+  }, []);
+
+  const handleSetTurnPolicy = useCallback(async (policy: TurnPolicy) => {
+    try {
+      await invoke('set_turn_policy', { policy });
+    } catch (error) {
+      useConversation.getState().setErrorBanner(`切换排队策略失败: ${error}`);
+    }
+  }, []);
+
   if (!mounted) return null;
 
   const serviceOn = serviceStatus !== 'offline' && serviceStatus !== 'error';
@@ -323,11 +385,55 @@ export function VoiceAssistant() {
             type="checkbox"
             checked={autoVad}
             disabled={!serviceOn}
-            onChange={handleToggleAutoVad}
+            onChange={() => handleSetEndpointing(autoVad ? 'manual' : 'client')}
           />
           自动检测（免按键）
         </label>
 
+        {autoVad && (
+          <label
+            style={{
+              display: 'flex',
+              alignItems: 'center',
+              gap: '6px',
+              fontSize: '13px',
+              cursor: serviceOn ? 'pointer' : 'not-allowed',
+              color: serviceOn ? '#333' : '#999',
+            }}
+          >
+            <input
+              type="checkbox"
+              checked={endpointing === 'server'}
+              disabled={!serviceOn}
+              onChange={() => handleSetEndpointing(endpointing === 'server' ? 'client' : 'server')}
+            />
+            由识别服务判断句尾
+          </label>
+        )}
+
+        <label style={{ display: 'flex', alignItems: 'center', gap: '6px', fontSize: '13px' }}>
+          打断时
+          <select
+            value={interruptionPolicy}
+            onChange={(e) => handleSetInterruptionPolicy(e.target.value as InterruptionPolicy)}
+          >
+            <option value="pause_resume">暂停，说完继续</option>
+            <option value="cancel">立即取消回复</option>
+            <option value="cancel_if_substantive">有实际内容才取消</option>
+          </select>
+        </label>
+
+        <label style={{ display: 'flex', alignItems: 'center', gap: '6px', fontSize: '13px' }}>
+          回复中再提问
+          <select
+            value={turnPolicy}
+            onChange={(e) => handleSetTurnPolicy(e.target.value as TurnPolicy)}
+          >
+            <option value="queue">排队回答</option>
+            <option value="supersede">只回答新问题</option>
+          </select>
+        </label>
+
         {(micOpen || autoVad) && (
           <div
             style={{
@@ -354,9 +460,9 @@ export function VoiceAssistant() {
             ⏸ 播放已暂停（缓存中）
           </span>
         )}
-        {utteranceQueue.length > 0 && (
+        {waitingTurns > 0 && (
           <span style={{ fontSize: '13px', color: '#666' }}>
-            队列 {utteranceQueue.length} 句待处理
+            队列 {waitingTurns} 句待处理
           </span>
         )}
       </div>
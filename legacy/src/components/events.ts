@@ -0,0 +1,18 @@
+import { listen, EventCallback, UnlistenFn } from '@tauri-apps/api/event';
+
+import { EVENT_SCHEMA_VERSION, VoiceAssistantEvents } from '../bindings/events';
+
+/** `listen` for a core event, with the payload type generated from Rust. */
+export function listenEvent<K extends keyof VoiceAssistantEvents>(
+  name: K,
+  handler: EventCallback<VoiceAssistantEvents[K]>
+): Promise<UnlistenFn> {
+  return listen<VoiceAssistantEvents[K]>(name, (e) => {
+    if (e.payload.schema_version !== EVENT_SCHEMA_VERSION) {
+      console.warn(
+        `${name}: schema version ${e.payload.schema_version}, expected ${EVENT_SCHEMA_VERSION}`
+      );
+    }
+    handler(e);
+  });
+}
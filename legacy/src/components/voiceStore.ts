@@ -1,19 +1,26 @@
-import { invoke } from '@tauri-apps/api/core';
 import { create } from 'zustand';
 
+import type {
+  ConversationStatus,
+  EndpointingMode,
+  InterruptionPolicy,
+  ServiceStatus,
+  TurnPolicy,
+} from '../bindings/events';
+
 export interface Message {
   role: 'user' | 'assistant';
   content: string;
 }
 
-export type ConversationStatus = 'Idle' | 'Listening' | 'FinalizingASR' | 'Thinking' | 'Speaking';
-export type ServiceStatus =
-  | 'offline'
-  | 'starting'
-  | 'ready'
-  | 'degraded'
-  | 'reconnecting'
-  | 'error';
+// Shared with the Rust core; see src-tauri/src/events.rs
+export type {
+  ConversationStatus,
+  EndpointingMode,
+  InterruptionPolicy,
+  ServiceStatus,
+  TurnPolicy,
+};
 
 interface ConversationState {
   conversation: Message[];
@@ -21,27 +28,30 @@ interface ConversationState {
   serviceStatus: ServiceStatus;
   serviceMessage: string;
   autoVad: boolean;
+  endpointing: EndpointingMode;
+  interruptionPolicy: InterruptionPolicy;
+  turnPolicy: TurnPolicy;
   micOpen: boolean;
   playbackPaused: boolean;
   transcript: string;
   audioLevel: number;
   pendingAssistantResponse: string;
-  llmBusy: boolean;
-  utteranceQueue: string[];
+  // Finals waiting for the backend turn orchestrator
+  waitingTurns: number;
   errorBanner: string;
   addMessage: (message: Message) => void;
   setStatus: (status: ConversationStatus) => void;
   setServiceStatus: (status: ServiceStatus, message: string) => void;
-  setAutoVad: (auto: boolean) => void;
+  setEndpointing: (mode: EndpointingMode) => void;
+  setInterruptionPolicy: (policy: InterruptionPolicy) => void;
+  setTurnPolicy: (policy: TurnPolicy) => void;
   setMicOpen: (open: boolean) => void;
   setPlaybackPaused: (paused: boolean) => void;
   setTranscript: (transcript: string) => void;
   setAudioLevel: (level: number) => void;
   setErrorBanner: (message: string) => void;
   appendAssistantResponse: (chunk: string, isComplete: boolean) => void;
-  enqueueUtterance: (text: string) => void;
-  dequeueUtterance: () => string | undefined;
-  setLlmBusy: (busy: boolean) => void;
+  setWaitingTurns: (waiting: number) => void;
 }
 
 export const useConversation = create<ConversationState>((set, get) => ({
@@ -50,18 +60,22 @@ export const useConversation = create<ConversationState>((set, get) => ({
   serviceStatus: 'offline',
   serviceMessage: '',
   autoVad: false,
+  endpointing: 'manual',
+  interruptionPolicy: 'pause_resume',
+  turnPolicy: 'queue',
   micOpen: false,
   playbackPaused: false,
   transcript: '',
   audioLevel: 0,
   pendingAssistantResponse: '',
-  llmBusy: false,
-  utteranceQueue: [],
+  waitingTurns: 0,
   errorBanner: '',
   addMessage: (message) => set((s) => ({ conversation: [...s.conversation, message] })),
   setStatus: (status) => set({ status }),
   setServiceStatus: (serviceStatus, serviceMessage) => set({ serviceStatus, serviceMessage }),
-  setAutoVad: (autoVad) => set({ autoVad }),
+  setEndpointing: (endpointing) => set({ endpointing, autoVad: endpointing !== 'manual' }),
+  setInterruptionPolicy: (interruptionPolicy) => set({ interruptionPolicy }),
+  setTurnPolicy: (turnPolicy) => set({ turnPolicy }),
   setMicOpen: (micOpen) => set({ micOpen }),
   setPlaybackPaused: (playbackPaused) => set({ playbackPaused }),
   setTranscript: (transcript) => set({ transcript }),
@@ -82,32 +96,5 @@ export const useConversation = create<ConversationState>((set, get) => ({
       set((s) => ({ pendingAssistantResponse: s.pendingAssistantResponse + chunk }));
     }
   },
-  enqueueUtterance: (text) => set((s) => ({ utteranceQueue: [...s.utteranceQueue, text] })),
-  dequeueUtterance: () => {
-    const [next, ...rest] = get().utteranceQueue;
-    set({ utteranceQueue: rest });
-    return next;
-  },
-  setLlmBusy: (llmBusy) => set({ llmBusy }),
+  setWaitingTurns: (waitingTurns) => set({ waitingTurns }),
 }));
-
-// Serialize LLM turns: mic toggling only produces utterances; each utterance
-// runs through LLM+TTS in order without blocking the mic.
-export async function pumpLlmQueue() {
-  const state = useConversation.getState();
-  if (state.llmBusy) return;
-
-  const next = state.dequeueUtterance();
-  if (next === undefined) return;
-
-  state.setLlmBusy(true);
-  try {
-    await invoke('stream_llm_response', { userMessage: next });
-  } catch (error) {
-    console.error('LLM error:', error);
-    useConversation.getState().setErrorBanner(`LLM 请求失败: ${error}`);
-  } finally {
-    useConversation.getState().setLlmBusy(false);
-    void pumpLlmQueue();
-  }
-}